@@ -1,20 +1,129 @@
-use clap::{Arg, App, ArgMatches};
-use clap::value_t;
+use clap::{Arg, App, AppSettings, ArgMatches, SubCommand};
+use clap::{value_t, values_t};
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::str::FromStr;
+use crate::game::Grid;
+use crate::game::convert::{ConvertOptions, Format};
+use crate::game::library;
+use crate::game::lilypond::LilyPondOptions;
+use crate::game::playlist::Repeat;
+use crate::game::scale::Scale;
+use crate::game::screen::KeyLabels;
+use crate::game::sheet::SheetOptions;
+
+pub enum Command {
+    Quantize {
+        input: PathBuf,
+        output: PathBuf,
+        grid: Grid,
+        bpm: f32,
+        strength: f32,
+    },
+    Trim {
+        input: PathBuf,
+        output: PathBuf,
+    },
+    Cut {
+        input: PathBuf,
+        output: PathBuf,
+        start: u64,
+        end: u64,
+        keep: bool,
+    },
+    Concat {
+        first: PathBuf,
+        second: PathBuf,
+        output: PathBuf,
+        gap: u64,
+    },
+    Overlay {
+        first: PathBuf,
+        second: PathBuf,
+        output: PathBuf,
+        offset: u64,
+    },
+    Transpose {
+        input: PathBuf,
+        output: PathBuf,
+        semitones: i16,
+    },
+    Scale {
+        input: PathBuf,
+        output: PathBuf,
+        factor: f32,
+    },
+    Retrograde {
+        input: PathBuf,
+        output: PathBuf,
+    },
+    Invert {
+        input: PathBuf,
+        output: PathBuf,
+        pivot: Option<String>,
+    },
+    Augment {
+        input: PathBuf,
+        output: PathBuf,
+        factor: f32,
+    },
+    MapScale {
+        input: PathBuf,
+        output: PathBuf,
+        from: Scale,
+        to: Scale,
+    },
+    Sheet {
+        input: PathBuf,
+        output: PathBuf,
+        options: SheetOptions,
+    },
+    CheatSheet {
+        input: PathBuf,
+        output: PathBuf,
+    },
+    LilyPond {
+        input: PathBuf,
+        output: PathBuf,
+        options: LilyPondOptions,
+    },
+    Convert {
+        input: PathBuf,
+        output: PathBuf,
+        from: Option<Format>,
+        to: Option<Format>,
+        options: ConvertOptions,
+    },
+}
 
 pub struct Options {
     pub assets: Option<PathBuf>,
     pub host_address: SocketAddr,
     pub volume: f32,
     pub record_file: Option<String>,
-    pub play_file: Option<String>,
+    pub record_quantize: Option<Grid>,
+    pub quantize_strength: f32,
+    pub play_files: Vec<PathBuf>,
+    pub playlist_gap: u64,
+    pub shuffle: bool,
+    pub repeat: Repeat,
+    pub watch: bool,
+    pub library: PathBuf,
+    pub key_labels: KeyLabels,
+    pub falling_notes: u16,
     pub play_file_tempo: f32,
+    pub mute_tracks: Vec<usize>,
+    pub solo_tracks: Vec<usize>,
+    pub seek_step: u64,
+    pub bpm: f32,
+    pub beats_per_bar: u32,
+    pub arpeggio: bool,
     pub sequence: i8,
     pub note_duration: u64,
     pub mark_duration: u64,
     pub receiver_address: SocketAddr,
     pub sender_address: SocketAddr,
+    pub command: Option<Command>,
 }
 
 impl Options {
@@ -32,10 +141,36 @@ impl Options {
                                 .unwrap_or(1.0),
             record_file      : value_t!(arguments.value_of("record_file"), String)
                                 .ok(),
-            play_file        : value_t!(arguments.value_of("play_file"), String)
-                                .ok(),
+            record_quantize  : Self::optional_value(&arguments, "record_quantize"),
+            quantize_strength: value_t!(arguments.value_of("quantize_strength"), f32)
+                                .unwrap_or(100.0),
+            play_files       : values_t!(arguments.values_of("play_file"), PathBuf)
+                                .unwrap_or_default(),
+            playlist_gap     : value_t!(arguments.value_of("playlist_gap"), u64)
+                                .unwrap_or(2000),
+            shuffle          : arguments.is_present("shuffle"),
+            repeat           : value_t!(arguments.value_of("repeat"), Repeat)
+                                .unwrap_or(Repeat::Off),
+            watch            : arguments.is_present("watch"),
+            library          : value_t!(arguments.value_of("library"), PathBuf)
+                                .unwrap_or_else(|_| library::default_directory()),
+            key_labels       : value_t!(arguments.value_of("key_labels"), KeyLabels)
+                                .unwrap_or(KeyLabels::None),
+            falling_notes    : value_t!(arguments.value_of("falling_notes"), u16)
+                                .unwrap_or(0),
             play_file_tempo  : value_t!(arguments.value_of("play_file_tempo"), f32)
                                 .unwrap_or(1.0),
+            mute_tracks      : values_t!(arguments.values_of("mute_track"), usize)
+                                .unwrap_or_default(),
+            solo_tracks      : values_t!(arguments.values_of("solo_track"), usize)
+                                .unwrap_or_default(),
+            seek_step        : value_t!(arguments.value_of("seek_step"), u64)
+                                .unwrap_or(5),
+            bpm              : value_t!(arguments.value_of("bpm"), f32)
+                                .unwrap_or(120.0),
+            beats_per_bar    : value_t!(arguments.value_of("beats_per_bar"), u32)
+                                .unwrap_or(4),
+            arpeggio         : arguments.is_present("arpeggio"),
             sequence         : value_t!(arguments.value_of("sequence"), i8)
                                 .unwrap_or(2),
             note_duration    : value_t!(arguments.value_of("note_duration"), u64)
@@ -45,11 +180,176 @@ impl Options {
             receiver_address,
             sender_address   : value_t!(arguments.value_of("sender_address"), SocketAddr)
                                 .unwrap_or_else(|_| "0.0.0.0:0".parse().unwrap()),
+            command          : Self::read_command(&arguments),
         };
 
         parsed_arguments
     }
 
+    fn read_command(arguments: &ArgMatches) -> Option<Command> {
+        match arguments.subcommand() {
+            ("quantize", Some(command)) => Some(Command::Quantize {
+                input            : value_t!(command.value_of("input"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                output           : value_t!(command.value_of("output"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                grid             : value_t!(command.value_of("grid"), Grid)
+                                    .unwrap_or_else(|e| e.exit()),
+                bpm              : value_t!(command.value_of("bpm"), f32)
+                                    .unwrap_or(120.0),
+                strength         : value_t!(command.value_of("strength"), f32)
+                                    .unwrap_or(100.0),
+            }),
+            ("trim", Some(command)) => Some(Command::Trim {
+                input            : value_t!(command.value_of("input"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                output           : value_t!(command.value_of("output"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+            }),
+            ("cut", Some(command)) => Some(Command::Cut {
+                input            : value_t!(command.value_of("input"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                output           : value_t!(command.value_of("output"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                start            : value_t!(command.value_of("start"), u64)
+                                    .unwrap_or_else(|e| e.exit()),
+                end              : value_t!(command.value_of("end"), u64)
+                                    .unwrap_or_else(|e| e.exit()),
+                keep             : command.is_present("keep"),
+            }),
+            ("concat", Some(command)) => Some(Command::Concat {
+                first            : value_t!(command.value_of("first"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                second           : value_t!(command.value_of("second"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                output           : value_t!(command.value_of("output"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                gap              : value_t!(command.value_of("gap"), u64)
+                                    .unwrap_or(0),
+            }),
+            ("overlay", Some(command)) => Some(Command::Overlay {
+                first            : value_t!(command.value_of("first"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                second           : value_t!(command.value_of("second"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                output           : value_t!(command.value_of("output"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                offset           : value_t!(command.value_of("offset"), u64)
+                                    .unwrap_or(0),
+            }),
+            ("transpose", Some(command)) => Some(Command::Transpose {
+                input            : value_t!(command.value_of("input"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                output           : value_t!(command.value_of("output"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                semitones        : value_t!(command.value_of("semitones"), i16)
+                                    .unwrap_or_else(|e| e.exit()),
+            }),
+            ("scale", Some(command)) => Some(Command::Scale {
+                input            : value_t!(command.value_of("input"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                output           : value_t!(command.value_of("output"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                factor           : value_t!(command.value_of("factor"), f32)
+                                    .unwrap_or_else(|e| e.exit()),
+            }),
+            ("retrograde", Some(command)) => Some(Command::Retrograde {
+                input            : value_t!(command.value_of("input"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                output           : value_t!(command.value_of("output"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+            }),
+            ("invert", Some(command)) => Some(Command::Invert {
+                input            : value_t!(command.value_of("input"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                output           : value_t!(command.value_of("output"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                pivot            : command.value_of("pivot").map(str::to_string),
+            }),
+            ("augment", Some(command)) => Some(Command::Augment {
+                input            : value_t!(command.value_of("input"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                output           : value_t!(command.value_of("output"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                factor           : value_t!(command.value_of("factor"), f32)
+                                    .unwrap_or_else(|e| e.exit()),
+            }),
+            // Diminishing by a factor is augmenting by its inverse, which the validator keeps finite
+            ("diminish", Some(command)) => Some(Command::Augment {
+                input            : value_t!(command.value_of("input"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                output           : value_t!(command.value_of("output"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                factor           : 1.0 / value_t!(command.value_of("factor"), f32)
+                                    .unwrap_or_else(|e| e.exit()),
+            }),
+            ("map-scale", Some(command)) => Some(Command::MapScale {
+                input            : value_t!(command.value_of("input"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                output           : value_t!(command.value_of("output"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                from             : value_t!(command.value_of("from"), Scale)
+                                    .unwrap_or_else(|e| e.exit()),
+                to               : value_t!(command.value_of("to"), Scale)
+                                    .unwrap_or_else(|e| e.exit()),
+            }),
+            ("sheet", Some(command)) => Some(Command::Sheet {
+                input            : value_t!(command.value_of("input"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                output           : value_t!(command.value_of("output"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                options          : SheetOptions {
+                    bpm          : value_t!(command.value_of("bpm"), f32)
+                                    .unwrap_or(120.0),
+                    sequence     : value_t!(command.value_of("sequence"), i8)
+                                    .unwrap_or(2),
+                    chords       : !command.is_present("arpeggio"),
+                },
+            }),
+            ("cheatsheet", Some(command)) => Some(Command::CheatSheet {
+                input            : value_t!(command.value_of("input"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                output           : value_t!(command.value_of("output"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+            }),
+            ("lilypond", Some(command)) => Some(Command::LilyPond {
+                input            : value_t!(command.value_of("input"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                output           : value_t!(command.value_of("output"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                options          : LilyPondOptions {
+                    bpm          : Self::optional_value(command, "bpm"),
+                    grid         : Self::optional_value(command, "grid")
+                                    .unwrap_or_else(|| LilyPondOptions::default().grid),
+                    time_signature: Self::optional_value(command, "time")
+                                    .unwrap_or_else(|| LilyPondOptions::default().time_signature),
+                },
+            }),
+            ("convert", Some(command)) => Some(Command::Convert {
+                input            : value_t!(command.value_of("input"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                output           : value_t!(command.value_of("output"), PathBuf)
+                                    .unwrap_or_else(|e| e.exit()),
+                from             : Self::optional_value(command, "from"),
+                to               : Self::optional_value(command, "to"),
+                options          : ConvertOptions {
+                    sheet        : SheetOptions {
+                        bpm      : value_t!(command.value_of("bpm"), f32)
+                                    .unwrap_or(120.0),
+                        sequence : value_t!(command.value_of("sequence"), i8)
+                                    .unwrap_or(2),
+                        chords   : !command.is_present("arpeggio"),
+                    },
+                    lilypond     : LilyPondOptions {
+                        bpm      : Self::optional_value(command, "bpm"),
+                        ..LilyPondOptions::default()
+                    },
+                },
+            }),
+            _ => None,
+        }
+    }
+
     fn get_arguments<'a>() -> ArgMatches<'a> {
         App::new("piano-rs")
             .version("0.2.0")
@@ -84,20 +384,114 @@ impl Options {
                 .takes_value(true)
                 .help("Record notes to .yml file (Default: None)"))
 
+            .arg(Arg::with_name("record_quantize")
+                .long("quantize")
+                .value_name("GRID")
+                .takes_value(true)
+                .help("Quantize recorded notes to a grid such as 1/8, 1/16 or 1/8t, using --bpm (Default: None)"))
+
+            .arg(Arg::with_name("quantize_strength")
+                .long("quantize-strength")
+                .value_name("PERCENT")
+                .takes_value(true)
+                .help("How far to move recorded notes towards the grid, in percent (Default: 100)"))
+
             .arg(Arg::with_name("play_file")
                 .short("p")
                 .long("play-file")
                 .value_name("FILEPATH")
                 .takes_value(true)
-                .help("Play notes from .yml file (Default: None)"))
+                .multiple(true)
+                .help("Play notes from .yml, .mid, .abc, .musicxml or .txt letter sheet files, directories of them or .m3u playlists (Default: None)"))
+
+            .arg(Arg::with_name("playlist_gap")
+                .long("playlist-gap")
+                .value_name("DURATION")
+                .takes_value(true)
+                .help("Silence between songs when playing several files, in ms (Default: 2000)"))
+
+            .arg(Arg::with_name("shuffle")
+                .long("shuffle")
+                .help("Play the files in a random order"))
+
+            .arg(Arg::with_name("repeat")
+                .long("repeat")
+                .value_name("MODE")
+                .takes_value(true)
+                .possible_values(&["off", "one", "all"])
+                .help("Repeat the current file or all of them (Default: off)"))
+
+            .arg(Arg::with_name("watch")
+                .short("w")
+                .long("watch")
+                .help("Reload the file being played whenever it changes"))
+
+            .arg(Arg::with_name("library")
+                .long("library")
+                .value_name("DIRECTORY")
+                .takes_value(true)
+                .help("Directory of songs to pick from with F4 (Default: ~/.local/share/piano-rs/songs/)"))
+
+            .arg(Arg::with_name("key_labels")
+                .long("key-labels")
+                .value_name("LABELS")
+                .takes_value(true)
+                .possible_values(&["none", "keys", "notes", "both"])
+                .help("Label the piano keys with the keys that play them, their notes or both (Default: none)"))
+
+            .arg(Arg::with_name("falling_notes")
+                .long("falling-notes")
+                .value_name("ROWS")
+                .takes_value(true)
+                .help("Rows above the keyboard to show notes falling onto their keys while playing from file (Default: 0)"))
 
             .arg(Arg::with_name("play_file_tempo")
                 .short("t")
                 .long("playback-tempo")
                 .value_name("AMOUNT")
                 .takes_value(true)
+                .validator(Self::positive_number)
                 .help("Set playback speed when playing from file (Default: 1.0)"))
 
+            .arg(Arg::with_name("mute_track")
+                .long("mute-track")
+                .value_name("NUMBER")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Don't play the notes from this track when playing from file (Default: None)"))
+
+            .arg(Arg::with_name("solo_track")
+                .long("solo-track")
+                .value_name("NUMBER")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Only play the notes from this track when playing from file (Default: None)"))
+
+            .arg(Arg::with_name("seek_step")
+                .long("seek-step")
+                .value_name("SECONDS")
+                .takes_value(true)
+                .help("Seconds to skip with PageUp and PageDown when playing from file (Default: 5)"))
+
+            .arg(Arg::with_name("bpm")
+                .long("bpm")
+                .value_name("AMOUNT")
+                .takes_value(true)
+                .validator(Self::positive_number)
+                .help("Beats per minute of the file being played or recorded, used to skip by bars and to quantize (Default: 120)"))
+
+            .arg(Arg::with_name("beats_per_bar")
+                .long("beats-per-bar")
+                .value_name("AMOUNT")
+                .takes_value(true)
+                .help("Beats in each bar of the file being played (Default: 4)"))
+
+            .arg(Arg::with_name("arpeggio")
+                .long("arpeggio")
+                .help("Play keys in brackets of a .txt letter sheet one after another instead of together"))
+
             .arg(Arg::with_name("sequence")
                 .short("s")
                 .long("sequence")
@@ -131,7 +525,279 @@ impl Options {
                 .takes_value(true)
                 .help("Set the IP Address and Port to which the sender socket will bind to. A port of 0 implies to bind on a random unused port (Default: 0.0.0.0:0)"))
 
+            .subcommand(SubCommand::with_name("quantize")
+                .about("Quantize the notes in a .yml file to a rhythmic grid")
+                .arg(Self::input_argument())
+                .arg(Self::output_argument())
+                .arg(Arg::with_name("grid")
+                    .short("g")
+                    .long("grid")
+                    .value_name("GRID")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Grid to snap notes to, such as 1/8, 1/16 or 1/8t for triplets"))
+                .arg(Arg::with_name("bpm")
+                    .long("bpm")
+                    .value_name("AMOUNT")
+                    .takes_value(true)
+                    .validator(Self::positive_number)
+                    .help("Beats per minute of the song (Default: 120)"))
+                .arg(Arg::with_name("strength")
+                    .long("strength")
+                    .value_name("PERCENT")
+                    .takes_value(true)
+                    .help("How far to move notes towards the grid, in percent (Default: 100)")))
+
+            .subcommand(SubCommand::with_name("trim")
+                .about("Remove the silence before the first note and after the last note of a .yml file")
+                .arg(Self::input_argument())
+                .arg(Self::output_argument()))
+
+            .subcommand(SubCommand::with_name("cut")
+                .about("Remove the notes within a time range of a .yml file")
+                .arg(Self::input_argument())
+                .arg(Self::output_argument())
+                .arg(Arg::with_name("start")
+                    .long("start")
+                    .value_name("TIME")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Start of the range, in ms"))
+                .arg(Arg::with_name("end")
+                    .long("end")
+                    .value_name("TIME")
+                    .takes_value(true)
+                    .required(true)
+                    .help("End of the range, in ms"))
+                .arg(Arg::with_name("keep")
+                    .long("keep")
+                    .help("Keep only the notes within the range instead of removing them")))
+
+            .subcommand(SubCommand::with_name("concat")
+                .about("Play one .yml file after another")
+                .arg(Self::first_argument())
+                .arg(Self::second_argument())
+                .arg(Self::output_argument().index(3))
+                .arg(Arg::with_name("gap")
+                    .long("gap")
+                    .value_name("DURATION")
+                    .takes_value(true)
+                    .help("Silence between the two files, in ms (Default: 0)")))
+
+            .subcommand(SubCommand::with_name("overlay")
+                .about("Play two .yml files on top of each other")
+                .arg(Self::first_argument())
+                .arg(Self::second_argument())
+                .arg(Self::output_argument().index(3))
+                .arg(Arg::with_name("offset")
+                    .long("offset")
+                    .value_name("DURATION")
+                    .takes_value(true)
+                    .help("Delay before the second file starts playing, in ms (Default: 0)")))
+
+            .subcommand(SubCommand::with_name("transpose")
+                .about("Shift the notes in a .yml file up or down")
+                .setting(AppSettings::AllowNegativeNumbers)
+                .arg(Self::input_argument())
+                .arg(Self::output_argument())
+                .arg(Arg::with_name("semitones")
+                    .value_name("SEMITONES")
+                    .required(true)
+                    .help("Semitones to shift the notes by, negative values shift them down")))
+
+            .subcommand(SubCommand::with_name("scale")
+                .about("Permanently change the tempo of a .yml file")
+                .arg(Self::input_argument())
+                .arg(Self::output_argument())
+                .arg(Arg::with_name("factor")
+                    .value_name("AMOUNT")
+                    .required(true)
+                    .validator(Self::positive_number)
+                    .help("Speed to scale the song by, where 2.0 plays twice as fast")))
+
+            .subcommand(SubCommand::with_name("retrograde")
+                .about("Reverse the order of the notes in a .yml file")
+                .arg(Self::input_argument())
+                .arg(Self::output_argument()))
+
+            .subcommand(SubCommand::with_name("invert")
+                .about("Turn the melody in a .yml file upside down around a pivot note")
+                .arg(Self::input_argument())
+                .arg(Self::output_argument())
+                .arg(Arg::with_name("pivot")
+                    .long("pivot")
+                    .value_name("NOTE")
+                    .takes_value(true)
+                    .help("Note to mirror the others around, such as e3 (Default: the first note)")))
+
+            .subcommand(SubCommand::with_name("augment")
+                .about("Lengthen the notes and gaps in a .yml file")
+                .arg(Self::input_argument())
+                .arg(Self::output_argument())
+                .arg(Arg::with_name("factor")
+                    .value_name("AMOUNT")
+                    .required(true)
+                    .validator(Self::positive_number)
+                    .help("Amount to lengthen the notes by, where 2.0 makes them twice as long")))
+
+            .subcommand(SubCommand::with_name("diminish")
+                .about("Shorten the notes and gaps in a .yml file")
+                .arg(Self::input_argument())
+                .arg(Self::output_argument())
+                .arg(Arg::with_name("factor")
+                    .value_name("AMOUNT")
+                    .required(true)
+                    .validator(Self::positive_number)
+                    .help("Amount to shorten the notes by, where 2.0 makes them half as long")))
+
+            .subcommand(SubCommand::with_name("map-scale")
+                .about("Move the notes in a .yml file to the same degrees of another scale or mode")
+                .arg(Self::input_argument())
+                .arg(Self::output_argument())
+                .arg(Arg::with_name("from")
+                    .long("from")
+                    .value_name("SCALE")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Scale the song is written in, such as \"c major\""))
+                .arg(Arg::with_name("to")
+                    .long("to")
+                    .value_name("SCALE")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Scale to move the notes to, such as \"a dorian\" (Modes: major, minor, harmonic_minor, dorian, phrygian, lydian, mixolydian, locrian)")))
+
+            .subcommand(SubCommand::with_name("sheet")
+                .about("Convert a virtual piano letter sheet such as \"[tu] y t | 8 9 0\" to a .yml file")
+                .arg(Self::input_argument()
+                    .help("The .txt letter sheet to read keys from"))
+                .arg(Self::output_argument())
+                .arg(Arg::with_name("bpm")
+                    .long("bpm")
+                    .value_name("AMOUNT")
+                    .takes_value(true)
+                    .validator(Self::positive_number)
+                    .help("Beats per minute to play the sheet at, with each spaced out key taking a beat (Default: 120)"))
+                .arg(Arg::with_name("sequence")
+                    .short("s")
+                    .long("sequence")
+                    .value_name("AMOUNT")
+                    .takes_value(true)
+                    .help("Frequency sequence the sheet is played at (Default: 2)"))
+                .arg(Arg::with_name("arpeggio")
+                    .long("arpeggio")
+                    .help("Play keys in brackets one after another instead of together")))
+
+            .subcommand(SubCommand::with_name("cheatsheet")
+                .about("Write the PC keyboard keys to play a song with, using as few sequence changes as possible")
+                .arg(Self::input_argument()
+                    .help("The .yml, .mid, .abc, .musicxml or .txt file to read notes from"))
+                .arg(Self::output_argument()
+                    .help("The file to write the cheat sheet to, as HTML if it ends with .html")))
+
+            .subcommand(SubCommand::with_name("lilypond")
+                .about("Write a song as LilyPond sheet music, with treble and bass staves")
+                .arg(Self::input_argument()
+                    .help("The .yml, .mid, .abc, .musicxml or .txt file to read notes from"))
+                .arg(Self::output_argument()
+                    .help("The .ly file to write the sheet music to"))
+                .arg(Arg::with_name("bpm")
+                    .long("bpm")
+                    .value_name("AMOUNT")
+                    .takes_value(true)
+                    .validator(Self::positive_number)
+                    .help("Beats per minute of the song (Default: the song's own tempo, or 120)"))
+                .arg(Arg::with_name("grid")
+                    .short("g")
+                    .long("grid")
+                    .value_name("GRID")
+                    .takes_value(true)
+                    .help("Shortest note to write, such as 1/8 or 1/16 (Default: 1/16)"))
+                .arg(Arg::with_name("time")
+                    .long("time")
+                    .value_name("SIGNATURE")
+                    .takes_value(true)
+                    .help("Time signature to split the song into bars with, such as 3/4 (Default: 4/4)")))
+
+            .subcommand(SubCommand::with_name("convert")
+                .about("Convert a song between the .yml, .mid, .abc, .musicxml, .txt letter sheet and .ly formats")
+                .arg(Self::input_argument()
+                    .help("The file to read notes from, in a format guessed from its extension or content"))
+                .arg(Self::output_argument()
+                    .help("The file to write notes to, in the format matching its extension"))
+                .arg(Arg::with_name("from")
+                    .long("from")
+                    .value_name("FORMAT")
+                    .takes_value(true)
+                    .help("Format of the input: yml, mid, abc, musicxml or txt (Default: guessed)"))
+                .arg(Arg::with_name("to")
+                    .long("to")
+                    .value_name("FORMAT")
+                    .takes_value(true)
+                    .help("Format of the output: yml, mid, txt or ly (Default: from the extension)"))
+                .arg(Arg::with_name("bpm")
+                    .long("bpm")
+                    .value_name("AMOUNT")
+                    .takes_value(true)
+                    .validator(Self::positive_number)
+                    .help("Beats per minute of letter sheets and sheet music (Default: 120, or the song's own tempo for sheet music)"))
+                .arg(Arg::with_name("sequence")
+                    .short("s")
+                    .long("sequence")
+                    .value_name("AMOUNT")
+                    .takes_value(true)
+                    .help("Frequency sequence letter sheets are played at (Default: 2)"))
+                .arg(Arg::with_name("arpeggio")
+                    .long("arpeggio")
+                    .help("Play keys in brackets of a letter sheet one after another instead of together")))
+
             .get_matches()
     }
 
+    // Values that can be left out, but still have to be valid when given
+    fn optional_value<T: FromStr>(arguments: &ArgMatches, name: &str) -> Option<T> {
+        arguments.value_of(name)
+            .map(|_| value_t!(arguments.value_of(name), T).unwrap_or_else(|e| e.exit()))
+    }
+
+    // Tempos and speeds of zero or less can't be played at
+    fn positive_number(value: String) -> Result<(), String> {
+        match value.parse::<f32>() {
+            Ok(number) if number > 0.0 && number.is_finite() => Ok(()),
+            _ => Err(format!("\"{}\" isn't a number greater than 0", value)),
+        }
+    }
+
+    fn input_argument<'a, 'b>() -> Arg<'a, 'b> {
+        Arg::with_name("input")
+            .value_name("INPUT")
+            .required(true)
+            .index(1)
+            .help("The .yml file to read notes from")
+    }
+
+    fn output_argument<'a, 'b>() -> Arg<'a, 'b> {
+        Arg::with_name("output")
+            .value_name("OUTPUT")
+            .required(true)
+            .index(2)
+            .help("The .yml file to write the notes to")
+    }
+
+    fn first_argument<'a, 'b>() -> Arg<'a, 'b> {
+        Arg::with_name("first")
+            .value_name("FIRST")
+            .required(true)
+            .index(1)
+            .help("The first .yml file to read notes from")
+    }
+
+    fn second_argument<'a, 'b>() -> Arg<'a, 'b> {
+        Arg::with_name("second")
+            .value_name("SECOND")
+            .required(true)
+            .index(2)
+            .help("The second .yml file to read notes from")
+    }
+
 }
@@ -1,23 +1,63 @@
 pub mod screen;
+pub mod abc;
+pub mod cheatsheet;
+pub mod convert;
+pub mod edit;
+pub mod library;
+pub mod lilypond;
+pub mod midi;
+pub mod musicxml;
 pub mod notes;
 pub mod notes_file;
+pub mod playback;
+pub mod playlist;
+pub mod quantize;
+pub mod render;
+pub mod scale;
+pub mod scheduler;
+pub mod sheet;
+pub mod tempo;
+pub mod watch;
 
 use std::time::Duration;
 use std::path::PathBuf;
 pub use notes::Note;
 pub use notes::Player;
-pub use notes_file::{NoteReader, FileNote, NoteRecorder};
-use screen::pianokeys;
+pub use notes_file::{NoteReader, Control, FileControl, FileNote, FileTrack, NoteRecorder, NoteSource, Song};
+pub use playback::{Playback, PlaybackCommand, PlaybackEnd};
+pub use quantize::{Grid, Quantizer};
+pub use scheduler::Scheduler;
+use render::{RenderEvent, RenderThread};
+use screen::{FallingNote, KeyboardLayout, KeyLabels, StatusBar};
 use serde_derive::{Serialize, Deserialize};
-use crossterm::{KeyEvent, Result};
+use crossterm::{terminal, KeyEvent, Result};
 use crossterm_style::Color;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum GameEvent {
     Note(Note),
+    Playback(PlaybackCommand),
+    OpenLibrary,
     Quit,
 }
 
+const PLAYER_COLORS: [Color; 6] = [
+    Color::Blue,
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Cyan,
+    Color::Magenta,
+];
+
+pub fn player_color(id: usize) -> Color {
+    PLAYER_COLORS.get(id).copied().unwrap_or(Color::Black)
+}
+
+pub fn player_id(color: Color) -> Option<usize> {
+    PLAYER_COLORS.iter().position(|&player_color| player_color == color)
+}
+
 pub struct PianoKeyboard {
     sequence: i8,
     volume: f32,
@@ -26,6 +66,15 @@ pub struct PianoKeyboard {
     pub color: Color,
     player: Player,
     recorder: NoteRecorder,
+    layout: KeyboardLayout,
+    key_labels: KeyLabels,
+    // Asked for on the command line, and given as many as fit once drawn
+    falling_rows: u16,
+    // Started once the keyboard is first drawn
+    renderer: Option<RenderThread>,
+    // Everyone playing along, ourselves included
+    players: usize,
+    shown_status_bar: Option<StatusBar>,
 }
 
 impl PianoKeyboard {
@@ -43,48 +92,191 @@ impl PianoKeyboard {
             color,
             player,
             recorder: NoteRecorder::new(),
+            // Fitted to the terminal once drawn
+            layout: KeyboardLayout::fit(u16::MAX, u16::MAX, sequence),
+            key_labels: KeyLabels::None,
+            falling_rows: 0,
+            renderer: None,
+            players: 1,
+            shown_status_bar: None,
         }
     }
 
     pub fn set_record_file(&mut self, record_file: PathBuf) {
         self.recorder.set_file(record_file);
+        // Start the recording off with the settings we're playing with
+        self.record_control(Control::Volume(self.volume));
+        self.record_control(Control::Sequence(self.sequence));
+        self.record_control(Control::SoundDuration(self.sound_duration));
+        self.update_status_bar();
+    }
+
+    pub fn set_record_quantizer(&mut self, quantizer: Quantizer) {
+        self.recorder.set_quantizer(quantizer);
+    }
+
+    pub fn set_key_labels(&mut self, key_labels: KeyLabels) {
+        self.key_labels = key_labels;
+    }
+
+    pub fn set_falling_rows(&mut self, falling_rows: u16) {
+        self.falling_rows = falling_rows;
+    }
+
+    // Rows of falling notes that fit into the terminal
+    pub fn falling_rows(&self) -> u16 {
+        self.layout.falling_rows()
     }
 
-    pub fn draw(&self) -> Result<()> {
-        pianokeys::draw()?;
+    fn fit_layout(&mut self) -> Result<()> {
+        let (width, height) = terminal().size()?;
+        self.layout = KeyboardLayout::fit(width, height, self.sequence)
+            .with_labels(self.key_labels)
+            .with_falling_rows(self.falling_rows);
         Ok(())
     }
 
-    pub fn play_note(&mut self, note: Note) {
+    pub fn draw(&mut self) -> Result<()> {
+        self.fit_layout()?;
+        let layout = self.layout;
+        self.renderer.get_or_insert_with(|| RenderThread::start(layout));
+        self.render(RenderEvent::Draw(self.layout));
+        self.update_status_bar();
+        Ok(())
+    }
+
+    // Fits the keyboard to the terminal again and draws everything from scratch
+    pub fn redraw(&mut self) -> Result<()> {
+        self.fit_layout()?;
+        self.render(RenderEvent::Redraw(self.layout));
+        Ok(())
+    }
+
+    // Keeps the keys played at the current sequence in view on narrow terminals
+    fn follow_sequence(&mut self) {
+        let layout = self.layout.refit(self.sequence);
+        if layout != self.layout {
+            self.layout = layout;
+            self.render(RenderEvent::Draw(self.layout));
+        }
+    }
+
+    fn render(&self, event: RenderEvent) {
+        if let Some(ref renderer) = self.renderer {
+            renderer.send(event);
+        }
+    }
+
+    pub fn status_bar(&self) -> StatusBar {
+        StatusBar {
+            volume: self.volume,
+            sequence: self.sequence,
+            sound_duration: self.sound_duration,
+            recording: self.recorder.record_file.is_some(),
+            players: self.players,
+            color: self.color,
+        }
+    }
+
+    // Shows the settings under the keyboard whenever they change
+    fn update_status_bar(&mut self) {
+        let status_bar = self.status_bar();
+        if self.renderer.is_some() && self.shown_status_bar.as_ref() != Some(&status_bar) {
+            self.render(RenderEvent::StatusBar(status_bar.clone()));
+            self.shown_status_bar = Some(status_bar);
+        }
+    }
+
+    pub fn set_players(&mut self, players: usize) {
+        self.players = players;
+        self.update_status_bar();
+    }
+
+    pub fn show_falling_notes(&self, falling_notes: Vec<FallingNote>) {
+        self.render(RenderEvent::FallingNotes(falling_notes));
+    }
+
+    pub fn show_playback_status(&self, status: String) {
+        self.render(RenderEvent::PlaybackStatus(status));
+    }
+
+    pub fn show_playlist_status(&self, status: String) {
+        self.render(RenderEvent::PlaylistStatus(status));
+    }
+
+    pub fn show_song_text(&self, heading: String, sung: String, upcoming: String) {
+        self.render(RenderEvent::SongText { heading, sung, upcoming });
+    }
+
+    pub fn show_library(&self, heading: String, lines: Vec<String>, selected: Option<usize>) {
+        self.render(RenderEvent::Library { heading, lines, selected });
+    }
+
+    pub fn close_library(&self) {
+        self.render(RenderEvent::CloseLibrary);
+    }
+
+    pub fn play_note(&mut self, note: Note, source: NoteSource) {
         note.play(&self.player, self.volume);
 
-        screen::mark_note(
-            note.position,
-            note.white,
-            note.color,
-            self.mark_duration,
-        );
+        self.render(RenderEvent::Mark {
+            position: note.position,
+            white: note.white,
+            color: note.color,
+            duration: self.mark_duration,
+        });
 
         if self.recorder.record_file.is_some(){
-            self.recorder.write_note(note);
+            self.recorder.write_note(note, source);
+        }
+    }
+
+    pub fn apply_control(&mut self, control: Control) {
+        match control {
+            Control::Volume(volume) => self.volume = volume,
+            Control::Sequence(sequence) => {
+                self.sequence = sequence;
+                self.follow_sequence();
+            }
+            Control::SoundDuration(sound_duration) => self.sound_duration = sound_duration,
+            // Followed by the playback instead
+            Control::Tempo { .. } | Control::TimeSignature(_) => {}
+        }
+        self.update_status_bar();
+    }
+
+    fn record_control(&mut self, control: Control) {
+        if self.recorder.record_file.is_some() {
+            self.recorder.write_control(control);
         }
     }
 
     pub fn set_note_color(&mut self, color: Color) {
         self.color = color;
+        self.update_status_bar();
     }
 
     pub fn process_key(&mut self, key: KeyEvent) -> Option<GameEvent> {
+        let event = self.handle_key(key);
+        self.update_status_bar();
+        event
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Option<GameEvent> {
         match key {
             KeyEvent::Right => {
                 if self.sequence < 6 {
                     self.sequence += 1;
+                    self.record_control(Control::Sequence(self.sequence));
+                    self.follow_sequence();
                 }
                 None
             }
             KeyEvent::Left => {
                 if self.sequence > 0 {
                     self.sequence -= 1;
+                    self.record_control(Control::Sequence(self.sequence));
+                    self.follow_sequence();
                 }
                 None
             }
@@ -92,23 +284,43 @@ impl PianoKeyboard {
                 // The note sound files are maximum 8s in length
                 if self.sound_duration < Duration::from_millis(8000) {
                     self.sound_duration += Duration::from_millis(50);
+                    self.record_control(Control::SoundDuration(self.sound_duration));
                 }
                 None
             }
             KeyEvent::Down => {
                 if self.sound_duration > Duration::new(0, 0) {
                     self.sound_duration -= Duration::from_millis(50);
+                    self.record_control(Control::SoundDuration(self.sound_duration));
                 }
                 None
             }
             KeyEvent::Char('+') => {
                 self.volume += 0.1;
+                self.record_control(Control::Volume(self.volume));
                 None
             }
             KeyEvent::Char('-') => {
                 self.volume -= 0.1;
+                self.record_control(Control::Volume(self.volume));
                 None
             }
+            KeyEvent::Char(' ') => Some(GameEvent::Playback(PlaybackCommand::TogglePause)),
+            KeyEvent::PageDown => Some(GameEvent::Playback(PlaybackCommand::SeekForward)),
+            KeyEvent::PageUp => Some(GameEvent::Playback(PlaybackCommand::SeekBackward)),
+            KeyEvent::End => Some(GameEvent::Playback(PlaybackCommand::NextBar)),
+            KeyEvent::Home => Some(GameEvent::Playback(PlaybackCommand::PreviousBar)),
+            KeyEvent::F(1) => Some(GameEvent::Playback(PlaybackCommand::SetLoopStart)),
+            KeyEvent::F(2) => Some(GameEvent::Playback(PlaybackCommand::SetLoopEnd)),
+            KeyEvent::F(3) => Some(GameEvent::Playback(PlaybackCommand::ClearLoop)),
+            KeyEvent::F(4) => Some(GameEvent::OpenLibrary),
+            KeyEvent::F(5) => Some(GameEvent::Playback(PlaybackCommand::DecreaseTempo)),
+            KeyEvent::F(6) => Some(GameEvent::Playback(PlaybackCommand::IncreaseTempo)),
+            KeyEvent::F(7) => Some(GameEvent::Playback(PlaybackCommand::ResetTempo)),
+            KeyEvent::F(8) => Some(GameEvent::Playback(PlaybackCommand::PreviousMarker)),
+            KeyEvent::F(9) => Some(GameEvent::Playback(PlaybackCommand::NextMarker)),
+            KeyEvent::F(11) => Some(GameEvent::Playback(PlaybackCommand::PreviousSong)),
+            KeyEvent::F(12) => Some(GameEvent::Playback(PlaybackCommand::NextSong)),
             KeyEvent::Esc => {
                 Some(GameEvent::Quit)
             }
@@ -124,12 +336,17 @@ mod test {
     use super::{
         PianoKeyboard,
         Color,
+        Control,
         KeyEvent,
         Player,
         Duration,
         GameEvent,
+        KeyboardLayout,
+        KeyLabels,
         Note,
         NoteRecorder,
+        PlaybackCommand,
+        StatusBar,
     };
 
     #[test]
@@ -151,6 +368,12 @@ mod test {
             color: Color::Blue,
             player: Player::new(),
             recorder: NoteRecorder::new(),
+            layout: KeyboardLayout::fit(u16::MAX, u16::MAX, 2),
+            key_labels: KeyLabels::None,
+            falling_rows: 0,
+            renderer: None,
+            players: 1,
+            shown_status_bar: None,
         };
 
         assert_eq!(actual_keyboard.sequence, expected_keyboard.sequence);
@@ -158,6 +381,8 @@ mod test {
         assert_eq!(actual_keyboard.sound_duration, expected_keyboard.sound_duration);
         assert_eq!(actual_keyboard.mark_duration, expected_keyboard.mark_duration);
         assert_eq!(actual_keyboard.color, expected_keyboard.color);
+        assert_eq!(actual_keyboard.layout, expected_keyboard.layout);
+        assert_eq!(actual_keyboard.players, expected_keyboard.players);
     }
 
     #[test]
@@ -174,6 +399,50 @@ mod test {
         assert_eq!(keyboard.color, Color::Red);
     }
 
+    #[test]
+    fn apply_control() {
+        let mut keyboard = PianoKeyboard::new(
+            2,
+            0.4,
+            None,
+            Duration::from_millis(7000),
+            Duration::from_millis(500),
+            Color::Blue,
+        );
+        keyboard.apply_control(Control::Volume(0.8));
+        keyboard.apply_control(Control::Sequence(5));
+        keyboard.apply_control(Control::SoundDuration(Duration::from_millis(300)));
+        assert_eq!(keyboard.volume, 0.8);
+        assert_eq!(keyboard.sequence, 5);
+        assert_eq!(keyboard.sound_duration, Duration::from_millis(300));
+    }
+
+    #[test]
+    fn status_bar() {
+        let mut keyboard = PianoKeyboard::new(
+            2,
+            0.4,
+            None,
+            Duration::from_millis(7000),
+            Duration::from_millis(500),
+            Color::Blue,
+        );
+        keyboard.process_key(KeyEvent::Char('+'));
+        keyboard.process_key(KeyEvent::Left);
+        keyboard.set_players(3);
+        keyboard.set_note_color(Color::Red);
+
+        let expected_status_bar = StatusBar {
+            volume: 0.5,
+            sequence: 1,
+            sound_duration: Duration::from_millis(7000),
+            recording: false,
+            players: 3,
+            color: Color::Red,
+        };
+        assert_eq!(keyboard.status_bar(), expected_status_bar);
+    }
+
     #[test]
     fn process_increase_volume_key() {
         let mut keyboard = PianoKeyboard::new(
@@ -288,6 +557,24 @@ mod test {
         }
     }
 
+    #[test]
+    fn process_playback_key() {
+        let mut keyboard = PianoKeyboard::new(
+            2,
+            0.4,
+            None,
+            Duration::from_millis(7000),
+            Duration::from_millis(500),
+            Color::Blue,
+        );
+
+        let event = keyboard.process_key(KeyEvent::Char(' '));
+        match event {
+            Some(GameEvent::Playback(v)) => assert_eq!(v, PlaybackCommand::TogglePause),
+            _ => panic!("This key should have returned a Playback event!"),
+        }
+    }
+
     #[test]
     fn process_note_key() {
         let mut keyboard = PianoKeyboard::new(
@@ -315,5 +602,11 @@ mod test {
             Some(GameEvent::Note(v)) => assert_eq!(v, expected_note),
             _ => panic!("This key should have returned a corresponding Note!"),
         }
+
+        // Ctrl+i reaches us as Tab
+        match keyboard.process_key(KeyEvent::Tab) {
+            Some(GameEvent::Note(_)) => { },
+            _ => panic!("Tab should have returned a Note!"),
+        }
     }
 }
@@ -0,0 +1,758 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use crate::game::notes;
+use crate::game::notes_file::{self, FileNote, Song};
+
+// Scale steps from C, the order key signatures are kept in
+const STEPS: [char; 7] = ['C', 'D', 'E', 'F', 'G', 'A', 'B'];
+// Position of each step on the circle of fifths, counting from C
+const STEP_FIFTHS: [i16; 7] = [0, 2, 4, -1, 1, 3, 5];
+const SHARPS_ORDER: [char; 7] = ['F', 'C', 'G', 'D', 'A', 'E', 'B'];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Item {
+    // Pitches are semitones together with whether they're tied to the next notes.
+    // Rests don't have any pitches.
+    Notes { pitches: Vec<(i16, bool)>, length: f64 },
+    RepeatStart,
+    RepeatEnd,
+    Ending(u32),
+}
+
+#[derive(Debug)]
+struct Parser {
+    line: usize,
+    unit_length: Option<f64>,
+    meter: Option<(u32, u32)>,
+    tempo: Option<(Option<f64>, f64)>,
+    key: [i16; 7],
+    bar_accidentals: HashMap<(usize, i16), i16>,
+    broken_rhythm: f64,
+    tuplet: Option<(f64, u32)>,
+    items: Vec<Item>,
+}
+
+// Reads the first tune in an ABC file. Chord symbols, decorations and grace notes
+// don't change which notes are played, so they're skipped over.
+pub fn parse_song(content: &str) -> Result<Song, String> {
+    let mut parser = Parser::new();
+    let mut in_body = false;
+
+    for (number, line) in content.lines().enumerate() {
+        parser.line = number + 1;
+        if line.starts_with('%') {
+            continue;
+        }
+        let line = line.split('%').next().unwrap_or_default();
+        if line.trim().is_empty() {
+            // A blank line ends the tune
+            if in_body {
+                break;
+            }
+            continue;
+        }
+
+        let mut chars = line.chars();
+        match (chars.next(), chars.next()) {
+            (Some(field), Some(':')) if field.is_ascii_alphabetic() => {
+                if field == 'X' && in_body {
+                    break;
+                }
+                parser.apply_field(field, &line[2..], in_body)?;
+                if field == 'K' {
+                    in_body = true;
+                }
+            }
+            _ if in_body => parser.parse_music(line)?,
+            _ => { },
+        }
+    }
+
+    if !in_body {
+        return Err(String::from("Missing the K: field that starts the tune"));
+    }
+    Ok(parser.into_song())
+}
+
+impl Parser {
+    fn new() -> Parser {
+        Parser {
+            line: 0,
+            unit_length: None,
+            meter: None,
+            tempo: None,
+            key: [0; 7],
+            bar_accidentals: HashMap::new(),
+            broken_rhythm: 1.0,
+            tuplet: None,
+            items: Vec::new(),
+        }
+    }
+
+    fn error(&self, message: String) -> String {
+        format!("line {}: {}", self.line, message)
+    }
+
+    fn error_at(&self, column: usize, message: String) -> String {
+        format!("line {}, column {}: {}", self.line, column + 1, message)
+    }
+
+    // In whole notes
+    fn unit_length(&self) -> f64 {
+        match (self.unit_length, self.meter) {
+            (Some(unit_length), _) => unit_length,
+            (None, Some((beats, beat_type))) if (beats as f64 / beat_type as f64) < 0.75 => 1.0 / 16.0,
+            (None, _) => 1.0 / 8.0,
+        }
+    }
+
+    // In seconds. Without a Q: field tunes are played at 120 quarter notes per minute.
+    fn whole_note(&self) -> f64 {
+        let (beat, bpm) = self.tempo.unwrap_or((Some(0.25), 120.0));
+        60.0 / (bpm * beat.unwrap_or_else(|| self.unit_length()))
+    }
+
+    fn apply_field(&mut self, field: char, value: &str, in_body: bool) -> Result<(), String> {
+        let value = value.trim();
+        match field {
+            'L' => {
+                let unit_length = parse_fraction(value)
+                    .filter(|&unit_length| unit_length > 0.0)
+                    .ok_or_else(|| self.error(format!("Invalid unit note length \"{}\"", value)))?;
+                self.unit_length = Some(unit_length);
+            }
+            'M' => {
+                self.meter = parse_meter(value)
+                    .ok_or_else(|| self.error(format!("Invalid meter \"{}\"", value)))?;
+            }
+            'Q' => {
+                self.tempo = Some(parse_tempo(value)
+                    .ok_or_else(|| self.error(format!("Invalid tempo \"{}\"", value)))?);
+            }
+            'K' => {
+                self.key = parse_key(value).map_err(|e| self.error(e))?;
+                self.bar_accidentals.clear();
+            }
+            'V' => return Err(self.error(String::from("Tunes with several voices (V:) are not supported"))),
+            'm' => return Err(self.error(String::from("Macros (m:) are not supported"))),
+            'P' if in_body => return Err(self.error(String::from("Parts (P:) are not supported"))),
+            // Titles, lyrics and other information fields don't affect playback
+            _ => { },
+        }
+        Ok(())
+    }
+
+    fn parse_music(&mut self, line: &str) -> Result<(), String> {
+        let chars: Vec<char> = line.chars().collect();
+        let mut i = 0;
+
+        while i < chars.len() {
+            match chars[i] {
+                ' ' | '\t' | '`' | '\\' | '$' | ')' | 'y' => i += 1,
+                '.' | '~' | 'H' | 'L' | 'M' | 'O' | 'P' | 'S' | 'T' | 'u' | 'v' => i += 1,
+                '"' => i = self.skip_past(&chars, i, '"')?,
+                '!' => i = self.skip_past(&chars, i, '!')?,
+                '+' => i = self.skip_past(&chars, i, '+')?,
+                '{' => i = self.skip_past(&chars, i, '}')?,
+                '(' => {
+                    if chars.get(i + 1).is_some_and(|c| c.is_ascii_digit()) {
+                        i = self.parse_tuplet(&chars, i)?;
+                    } else {
+                        // Slurs only affect phrasing
+                        i += 1;
+                    }
+                }
+                '|' | ':' => i = self.parse_bar_line(&chars, i)?,
+                '[' => match (chars.get(i + 1), chars.get(i + 2)) {
+                    (Some('|'), _) => i = self.parse_bar_line(&chars, i + 1)?,
+                    (Some(c), _) if c.is_ascii_digit() => i = self.parse_ending(&chars, i + 1)?,
+                    (Some(c), Some(':')) if c.is_ascii_alphabetic() => {
+                        let end = self.skip_past(&chars, i, ']')?;
+                        let value: String = chars[i + 3..end - 1].iter().collect();
+                        self.apply_field(*c, &value, true)?;
+                        i = end;
+                    }
+                    _ => i = self.parse_chord(&chars, i)?,
+                },
+                '>' | '<' => i = self.parse_broken_rhythm(&chars, i)?,
+                '-' => {
+                    match self.items.last_mut() {
+                        Some(Item::Notes { pitches, .. }) if !pitches.is_empty() => {
+                            for pitch in pitches.iter_mut() {
+                                pitch.1 = true;
+                            }
+                        }
+                        _ => return Err(self.error_at(i, String::from("A tie must follow a note"))),
+                    }
+                    i += 1;
+                }
+                '^' | '_' | '=' | 'A'..='G' | 'a'..='g' => {
+                    let (semitone, next) = self.parse_pitch(&chars, i)?;
+                    let (units, next) = parse_length(&chars, next);
+                    let tied = chars.get(next) == Some(&'-');
+                    self.push_notes(vec![(semitone, tied)], units);
+                    i = if tied { next + 1 } else { next };
+                }
+                'z' | 'x' => {
+                    let (units, next) = parse_length(&chars, i + 1);
+                    self.push_notes(Vec::new(), units);
+                    i = next;
+                }
+                'Z' | 'X' => {
+                    let (bars, next) = parse_number(&chars, i + 1);
+                    let (beats, beat_type) = self.meter
+                        .ok_or_else(|| self.error_at(i, String::from("Multi-bar rests need a meter (M:)")))?;
+                    let length = bars.unwrap_or(1) as f64 * beats as f64 / beat_type as f64 * self.whole_note();
+                    self.items.push(Item::Notes { pitches: Vec::new(), length });
+                    i = next;
+                }
+                '&' => return Err(self.error_at(i, String::from("Overlaid voices (&) are not supported"))),
+                c => return Err(self.error_at(i, format!("Unsupported symbol '{}'", c))),
+            }
+        }
+        Ok(())
+    }
+
+    fn skip_past(&self, chars: &[char], start: usize, end: char) -> Result<usize, String> {
+        chars[start + 1..]
+            .iter()
+            .position(|&c| c == end)
+            .map(|offset| start + offset + 2)
+            .ok_or_else(|| self.error_at(start, format!("Missing closing '{}'", end)))
+    }
+
+    fn parse_pitch(&mut self, chars: &[char], start: usize) -> Result<(i16, usize), String> {
+        let mut i = start;
+        let mut accidental = None;
+        while let Some(&c) = chars.get(i) {
+            let alter = match c {
+                '^' => 1,
+                '_' => -1,
+                '=' => 0,
+                _ => break,
+            };
+            accidental = Some(accidental.unwrap_or(0) + alter);
+            i += 1;
+        }
+        if chars.get(i) == Some(&'/') {
+            return Err(self.error_at(i, String::from("Microtonal accidentals are not supported")));
+        }
+
+        let letter = match chars.get(i) {
+            Some(&letter) if letter.is_ascii_alphabetic() => letter,
+            _ => return Err(self.error_at(i, String::from("Expected a note after the accidental"))),
+        };
+        let step = STEPS.iter()
+            .position(|&step| step == letter.to_ascii_uppercase())
+            .ok_or_else(|| self.error_at(i, format!("Unknown note '{}'", letter)))?;
+        // Upper case letters start from middle C
+        let mut octave = if letter.is_ascii_uppercase() { 4 } else { 5 };
+        i += 1;
+        while let Some(&c) = chars.get(i) {
+            match c {
+                '\'' => octave += 1,
+                ',' => octave -= 1,
+                _ => break,
+            }
+            i += 1;
+        }
+
+        // Accidentals last till the end of the bar
+        let alter = match accidental {
+            Some(alter) => {
+                self.bar_accidentals.insert((step, octave), alter);
+                alter
+            }
+            None => self.bar_accidentals
+                .get(&(step, octave))
+                .copied()
+                .unwrap_or(self.key[step]),
+        };
+
+        let written: String = chars[start..i].iter().collect();
+        notes::pitch_to_semitone(STEPS[step], octave)
+            .map(|semitone| semitone + alter)
+            .filter(|&semitone| notes::semitone_to_sound(semitone).is_some())
+            .map(|semitone| (semitone, i))
+            .ok_or_else(|| self.error_at(start, format!("Note {} is outside the range of the piano", written)))
+    }
+
+    fn parse_chord(&mut self, chars: &[char], start: usize) -> Result<usize, String> {
+        let mut i = start + 1;
+        let mut pitches = Vec::new();
+        let mut chord_units = None;
+
+        loop {
+            match chars.get(i) {
+                Some(']') => break,
+                Some(' ') => i += 1,
+                Some('^') | Some('_') | Some('=') | Some('A'..='G') | Some('a'..='g') => {
+                    let (semitone, next) = self.parse_pitch(chars, i)?;
+                    let (units, next) = parse_length(chars, next);
+                    // Chords last as long as their first note
+                    chord_units.get_or_insert(units);
+                    let tied = chars.get(next) == Some(&'-');
+                    pitches.push((semitone, tied));
+                    i = if tied { next + 1 } else { next };
+                }
+                Some(&c) => return Err(self.error_at(i, format!("Unsupported symbol '{}' in chord", c))),
+                None => return Err(self.error_at(start, String::from("Missing closing ']'"))),
+            }
+        }
+
+        let (units, mut next) = parse_length(chars, i + 1);
+        if chars.get(next) == Some(&'-') {
+            for pitch in pitches.iter_mut() {
+                pitch.1 = true;
+            }
+            next += 1;
+        }
+        self.push_notes(pitches, chord_units.unwrap_or(1.0) * units);
+        Ok(next)
+    }
+
+    fn parse_bar_line(&mut self, chars: &[char], start: usize) -> Result<usize, String> {
+        let mut i = start;
+        while let Some(&c) = chars.get(i) {
+            match c {
+                '|' | ':' => i += 1,
+                ']' if chars[i - 1] == '|' => i += 1,
+                _ => break,
+            }
+        }
+
+        if chars[start] == ':' {
+            self.items.push(Item::RepeatEnd);
+        }
+        if chars[i - 1] == ':' && i - start > 1 {
+            self.items.push(Item::RepeatStart);
+        }
+        self.bar_accidentals.clear();
+
+        match chars.get(i) {
+            Some(c) if c.is_ascii_digit() => self.parse_ending(chars, i),
+            _ => Ok(i),
+        }
+    }
+
+    fn parse_ending(&mut self, chars: &[char], start: usize) -> Result<usize, String> {
+        let (number, next) = parse_number(chars, start);
+        if let Some(',') | Some('-') = chars.get(next) {
+            return Err(self.error_at(start, String::from("Endings shared by several repeats are not supported")));
+        }
+        self.items.push(Item::Ending(number.unwrap_or(1)));
+        Ok(next)
+    }
+
+    // Tuplets are written as (p:q:r, meaning r notes are played as p notes in the time of q
+    fn parse_tuplet(&mut self, chars: &[char], start: usize) -> Result<usize, String> {
+        let (p, mut i) = parse_number(chars, start + 1);
+        let p = p.unwrap_or(0);
+        let mut q = None;
+        let mut r = None;
+        if chars.get(i) == Some(&':') {
+            let (number, next) = parse_number(chars, i + 1);
+            q = number;
+            i = next;
+            if chars.get(i) == Some(&':') {
+                let (number, next) = parse_number(chars, i + 1);
+                r = number;
+                i = next;
+            }
+        }
+
+        let compound = self.meter.is_some_and(|(beats, _)| beats % 3 == 0 && beats > 3);
+        let q = match (q, p) {
+            (Some(q), _) => q,
+            (None, 2) | (None, 4) | (None, 8) => 3,
+            (None, 3) | (None, 6) => 2,
+            (None, 5) | (None, 7) | (None, 9) => if compound { 3 } else { 2 },
+            _ => return Err(self.error_at(start, format!("Unsupported tuplet ({}", p))),
+        };
+        if p == 0 || q == 0 {
+            return Err(self.error_at(start, String::from("Invalid tuplet")));
+        }
+        self.tuplet = Some((q as f64 / p as f64, r.unwrap_or(p)));
+        Ok(i)
+    }
+
+    // A>B lengthens A by half and shortens B by as much, A<B does the opposite
+    fn parse_broken_rhythm(&mut self, chars: &[char], start: usize) -> Result<usize, String> {
+        let symbol = chars[start];
+        let mut i = start;
+        while chars.get(i) == Some(&symbol) {
+            i += 1;
+        }
+        let shortened = 0.5_f64.powi((i - start) as i32);
+        let (first, second) = if symbol == '>' {
+            (2.0 - shortened, shortened)
+        } else {
+            (shortened, 2.0 - shortened)
+        };
+
+        match self.items.last_mut() {
+            Some(Item::Notes { length, .. }) => *length *= first,
+            _ => return Err(self.error_at(start, format!("'{}' must follow a note", symbol))),
+        }
+        self.broken_rhythm = second;
+        Ok(i)
+    }
+
+    fn push_notes(&mut self, pitches: Vec<(i16, bool)>, units: f64) {
+        let mut length = units * self.unit_length() * self.whole_note() * self.broken_rhythm;
+        self.broken_rhythm = 1.0;
+        if let Some((factor, remaining)) = self.tuplet.take() {
+            length *= factor;
+            if remaining > 1 {
+                self.tuplet = Some((factor, remaining - 1));
+            }
+        }
+        self.items.push(Item::Notes { pitches, length });
+    }
+
+    fn into_song(self) -> Song {
+        let mut timed_notes: Vec<(Duration, FileNote)> = Vec::new();
+        // Notes tied from the previous item, by semitone
+        let mut open_ties: Vec<(i16, usize)> = Vec::new();
+        let mut time = 0.0;
+
+        for (pitches, length) in expand_repeats(self.items) {
+            let mut ties = Vec::new();
+            for (semitone, tied) in pitches {
+                let index = match open_ties.iter().find(|&&(open, _)| open == semitone) {
+                    Some(&(_, index)) => {
+                        let note = &mut timed_notes[index];
+                        note.1.duration = to_millis(time + length) - note.0;
+                        index
+                    }
+                    None => {
+                        timed_notes.push((to_millis(time), FileNote {
+                            delay: Duration::from_millis(0),
+                            base_note: notes::semitone_to_sound(semitone).unwrap(),
+                            duration: to_millis(time + length) - to_millis(time),
+                            track: None,
+                        }));
+                        timed_notes.len() - 1
+                    }
+                };
+                if tied {
+                    ties.push((semitone, index));
+                }
+            }
+            open_ties = ties;
+            time += length;
+        }
+
+        let (start_times, mut notes): (Vec<Duration>, Vec<FileNote>) = timed_notes.into_iter().unzip();
+        notes_file::set_start_times(&mut notes, &start_times);
+        Song {
+            notes,
+            ..Song::default()
+        }
+    }
+}
+
+// Plays repeated sections twice, or once for each of their endings, playing the ending
+// numbered after the pass through the section
+fn expand_repeats(items: Vec<Item>) -> Vec<(Vec<(i16, bool)>, f64)> {
+    let mut expanded = Vec::new();
+    let mut section_start = 0;
+    let mut pass = 1;
+    let mut ending: Option<u32> = None;
+    let mut skipping = false;
+    let mut i = 0;
+
+    while i < items.len() {
+        match &items[i] {
+            Item::Notes { pitches, length } => {
+                if !skipping {
+                    expanded.push((pitches.clone(), *length));
+                }
+            }
+            Item::RepeatStart => {
+                section_start = i + 1;
+                pass = 1;
+                ending = None;
+                skipping = false;
+            }
+            Item::RepeatEnd => {
+                // Go back for another pass if the section hasn't been repeated yet, or if
+                // there's an ending for the next pass
+                let next_ending = match items.get(i + 1) {
+                    Some(Item::Ending(number)) => Some(*number),
+                    _ => None,
+                };
+                if skipping {
+                    skipping = false;
+                } else if (ending.is_none() && pass == 1) || next_ending.is_some_and(|number| number > pass) {
+                    pass += 1;
+                    ending = None;
+                    i = section_start;
+                    continue;
+                } else {
+                    section_start = i + 1;
+                    pass = 1;
+                }
+                ending = None;
+            }
+            Item::Ending(number) => {
+                ending = Some(*number);
+                skipping = *number != pass;
+            }
+        }
+        i += 1;
+    }
+    expanded
+}
+
+fn parse_number(chars: &[char], start: usize) -> (Option<u32>, usize) {
+    let digits: String = chars[start..].iter().take_while(|c| c.is_ascii_digit()).collect();
+    (digits.parse().ok(), start + digits.len())
+}
+
+// Lengths are multiples of the unit note length, such as 2, 3/2, / or //
+fn parse_length(chars: &[char], start: usize) -> (f64, usize) {
+    let (numerator, mut i) = parse_number(chars, start);
+    let mut denominator = 1;
+    while chars.get(i) == Some(&'/') {
+        let (number, next) = parse_number(chars, i + 1);
+        denominator *= number.unwrap_or(2);
+        i = next;
+    }
+    (numerator.unwrap_or(1) as f64 / denominator.max(1) as f64, i)
+}
+
+fn parse_fraction(value: &str) -> Option<f64> {
+    match value.split_once('/') {
+        Some((numerator, denominator)) => {
+            let numerator: f64 = numerator.trim().parse().ok()?;
+            let denominator: f64 = denominator.trim().parse().ok()?;
+            Some(numerator / denominator).filter(|fraction| fraction.is_finite())
+        }
+        None => value.trim().parse().ok(),
+    }
+}
+
+fn parse_meter(value: &str) -> Option<Option<(u32, u32)>> {
+    match value {
+        "" | "none" => Some(None),
+        "C" => Some(Some((4, 4))),
+        "C|" => Some(Some((2, 2))),
+        _ => {
+            let (beats, beat_type) = value.split_once('/')?;
+            // Complex meters such as (2+3)/8 add up their beats
+            let beats = beats.trim_matches(|c| c == '(' || c == ')')
+                .split('+')
+                .map(|beats| beats.trim().parse::<u32>().ok())
+                .sum::<Option<u32>>()?;
+            let beat_type = beat_type.trim().parse().ok()?;
+            Some(Some((beats, beat_type))).filter(|_| beats > 0 && beat_type > 0)
+        }
+    }
+}
+
+// Tempos are written as 1/4=120, or as just 120 unit note lengths per minute
+fn parse_tempo(value: &str) -> Option<(Option<f64>, f64)> {
+    // Leave out text such as "Allegro"
+    let value: String = value.split('"').step_by(2).collect();
+    let (beat, bpm) = match value.split_once('=') {
+        Some((beats, bpm)) => {
+            let beat = beats.split_whitespace()
+                .map(parse_fraction)
+                .sum::<Option<f64>>()?;
+            (Some(beat), bpm)
+        }
+        None => (None, value.as_str()),
+    };
+    let bpm: f64 = bpm.trim().parse().ok()?;
+    if bpm <= 0.0 || beat.is_some_and(|beat| beat <= 0.0) {
+        return None;
+    }
+    Some((beat, bpm))
+}
+
+// Returns how many semitones each scale step from C is raised or lowered by
+fn parse_key(value: &str) -> Result<[i16; 7], String> {
+    let mut key = [0; 7];
+    let mut words = value.split_whitespace().filter(|word| !word.contains('='));
+    let tonic = match words.next() {
+        None | Some("none") => return Ok(key),
+        Some(tonic) => tonic,
+    };
+    if tonic.starts_with('H') {
+        return Err(String::from("Highland pipe keys (K:HP) are not supported"));
+    }
+
+    let mut tonic_chars = tonic.chars();
+    let step = tonic_chars.next()
+        .and_then(|letter| STEPS.iter().position(|&step| step == letter))
+        .ok_or_else(|| format!("Unknown key \"{}\"", value))?;
+    let mut fifths = STEP_FIFTHS[step];
+    let mut mode = tonic_chars.as_str();
+    if let Some(rest) = mode.strip_prefix('#') {
+        fifths += 7;
+        mode = rest;
+    } else if let Some(rest) = mode.strip_prefix('b') {
+        fifths -= 7;
+        mode = rest;
+    }
+
+    let mut words = words.peekable();
+    if mode.is_empty() {
+        if let Some(word) = words.peek() {
+            if mode_fifths(word).is_some() {
+                mode = words.next().unwrap_or_default();
+            }
+        }
+    }
+    fifths += mode_fifths(mode).ok_or_else(|| format!("Unknown mode \"{}\"", mode))?;
+
+    if fifths > 0 {
+        for &step in SHARPS_ORDER.iter().take(fifths as usize) {
+            key[STEPS.iter().position(|&s| s == step).unwrap()] = 1;
+        }
+    } else {
+        for &step in SHARPS_ORDER.iter().rev().take(-fifths as usize) {
+            key[STEPS.iter().position(|&s| s == step).unwrap()] = -1;
+        }
+    }
+
+    // Keys can list extra accidentals, such as K:D ^c or K:D exp _b _e
+    for word in words {
+        if word == "exp" {
+            key = [0; 7];
+            continue;
+        }
+        let alter: i16 = word.chars()
+            .take_while(|c| "^_=".contains(*c))
+            .map(|c| match c { '^' => 1, '_' => -1, _ => 0 })
+            .sum();
+        let letter = word.trim_start_matches(|c| "^_=".contains(c));
+        if letter.len() == word.len() {
+            // Clefs and other key options don't change the notes
+            continue;
+        }
+        let step = letter.chars()
+            .next()
+            .and_then(|letter| STEPS.iter().position(|&step| step == letter.to_ascii_uppercase()))
+            .ok_or_else(|| format!("Unknown accidental \"{}\" in key", word))?;
+        key[step] = alter;
+    }
+    Ok(key)
+}
+
+fn mode_fifths(mode: &str) -> Option<i16> {
+    let mode = mode.to_lowercase();
+    if mode == "m" {
+        return Some(-3);
+    }
+    match mode.get(..3).unwrap_or(&mode) {
+        "" | "maj" | "ion" => Some(0),
+        "mix" => Some(-1),
+        "dor" => Some(-2),
+        "min" | "aeo" => Some(-3),
+        "phr" => Some(-4),
+        "loc" => Some(-5),
+        "lyd" => Some(1),
+        _ => None,
+    }
+}
+
+fn to_millis(seconds: f64) -> Duration {
+    Duration::from_millis((seconds * 1000.0).round() as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_song;
+
+    // Start times, sounds and durations of the notes, all in milliseconds
+    fn timeline(content: &str) -> Vec<(u128, String, u128)> {
+        let song = parse_song(content).unwrap();
+        let mut time = 0;
+        song.notes
+            .iter()
+            .map(|note| {
+                time += note.delay.as_millis();
+                (time, note.base_note.clone(), note.duration.as_millis())
+            })
+            .collect()
+    }
+
+    fn sounds(content: &str) -> Vec<String> {
+        timeline(content).into_iter().map(|(_, sound, _)| sound).collect()
+    }
+
+    #[test]
+    fn parse_header_and_lengths() {
+        let tune = "X:1\nT:Scale\nM:4/4\nL:1/4\nQ:1/4=60\nK:C\nC D2 E/ F3/2 z G|\n";
+        assert_eq!(timeline(tune), vec![
+            (0, String::from("c3"), 1000),
+            (1000, String::from("d3"), 2000),
+            (3000, String::from("e3"), 500),
+            (3500, String::from("f3"), 1500),
+            (6000, String::from("g3"), 1000),
+        ]);
+    }
+
+    #[test]
+    fn default_unit_length_and_tempo() {
+        // Eighth notes at 120 quarter notes per minute
+        let tune = "X:1\nM:6/8\nK:C\nc2 C,\n";
+        assert_eq!(timeline(tune), vec![(0, String::from("c4"), 500), (500, String::from("c2"), 250)]);
+    }
+
+    #[test]
+    fn key_signatures_and_accidentals() {
+        assert_eq!(sounds("X:1\nK:D\nF C c\n"), ["fs3", "cs3", "cs4"]);
+        assert_eq!(sounds("X:1\nK:Bb\nB E\n"), ["as3", "ds3"]);
+        assert_eq!(sounds("X:1\nK:Ador\nF G\n"), ["fs3", "g3"]);
+        assert_eq!(sounds("X:1\nK:F#m\nG\n"), ["gs3"]);
+        // Accidentals carry on till the end of the bar
+        assert_eq!(sounds("X:1\nK:C\n^F F =F _B| F B\n"), ["fs3", "fs3", "f3", "as3", "f3", "b3"]);
+    }
+
+    #[test]
+    fn ties_chords_and_rests() {
+        let tune = "X:1\nL:1/4\nK:C\n[CEG]2 [C-E]C z [ce]/\n";
+        assert_eq!(timeline(tune), vec![
+            (0, String::from("c3"), 1000),
+            (0, String::from("e3"), 1000),
+            (0, String::from("g3"), 1000),
+            (1000, String::from("c3"), 1000),
+            (1000, String::from("e3"), 500),
+            (2500, String::from("c4"), 250),
+            (2500, String::from("e4"), 250),
+        ]);
+    }
+
+    #[test]
+    fn broken_rhythm_and_triplets() {
+        let tune = "X:1\nL:1/4\nK:C\nC>D (3EFG\n";
+        let durations: Vec<u128> = timeline(tune).into_iter().map(|(_, _, duration)| duration).collect();
+        // Notes are rounded to whole milliseconds without drifting away from the beat
+        assert_eq!(durations, vec![750, 250, 333, 334, 333]);
+    }
+
+    #[test]
+    fn repeats_and_endings() {
+        assert_eq!(sounds("X:1\nK:C\nC|:D:|E\n"), ["c3", "d3", "d3", "e3"]);
+        assert_eq!(sounds("X:1\nK:C\n|:C|1D:|2E|F|]\n"), ["c3", "d3", "c3", "e3", "f3"]);
+        assert_eq!(sounds("X:1\nK:C\n|:C|1D:|2E:|3F|]\n"), ["c3", "d3", "c3", "e3", "c3", "f3"]);
+        assert_eq!(sounds("X:1\nK:C\n|:C:|D:|E\n"), ["c3", "c3", "d3", "d3", "e3"]);
+    }
+
+    #[test]
+    fn skip_decorations() {
+        assert_eq!(sounds("X:1\nK:C\n\"Am\"!trill!~C {g}D .E\nw: some lyrics\n"), ["c3", "d3", "e3"]);
+    }
+
+    #[test]
+    fn unsupported_constructs() {
+        assert!(parse_song("X:1\nT:No key\nC D E\n").unwrap_err().contains("K:"));
+        assert!(parse_song("X:1\nK:C\nV:2\nC\n").unwrap_err().contains("voices"));
+        assert_eq!(parse_song("X:1\nK:C\nC & E\n").unwrap_err(), "line 3, column 3: Overlaid voices (&) are not supported");
+        assert!(parse_song("X:1\nK:C\nC,,,,,\n").unwrap_err().contains("outside the range"));
+        assert!(parse_song("X:1\nK:Cx\nC\n").unwrap_err().contains("Unknown mode"));
+    }
+}
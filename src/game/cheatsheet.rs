@@ -0,0 +1,273 @@
+use std::time::Duration;
+use crossterm::KeyEvent;
+use crate::game::notes;
+use crate::game::notes_file::{self, Song};
+
+// Sequences that can be reached with the arrow keys
+const SEQUENCES: std::ops::RangeInclusive<i8> = 0..=6;
+// Gaps at least this long between notes are shown as pauses
+const PAUSE: Duration = Duration::from_millis(1000);
+// The sequence piano-rs starts at without --sequence
+const DEFAULT_SEQUENCE: i8 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyGroup {
+    // Keys to press together
+    pub keys: Vec<KeyEvent>,
+    pub pause_before: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    pub sequence: i8,
+    pub groups: Vec<KeyGroup>,
+}
+
+// The keys to press for a song, split into sections played at the same sequence
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheatSheet {
+    pub sections: Vec<Section>,
+}
+
+// Total cost of playing up to some note, compared switches first, then arrow key presses,
+// modifier keys and lastly how far the song starts from the default sequence
+type Cost = (u32, u32, u32, u32);
+// Keys for a chord along with how many modifiers they need, if it can be played
+type Keys = Option<(Vec<KeyEvent>, u32)>;
+
+impl CheatSheet {
+    pub fn from_song(song: &Song) -> Result<CheatSheet, String> {
+        let mut groups: Vec<(Duration, Vec<&str>)> = Vec::new();
+        for (time, note) in notes_file::start_times(&song.notes).into_iter().zip(&song.notes) {
+            match groups.last_mut() {
+                Some((start, sounds)) if *start == time => sounds.push(&note.base_note),
+                _ => groups.push((time, vec![&note.base_note])),
+            }
+        }
+
+        // Keys for each group at every sequence, along with how many modifiers they need
+        let options: Vec<Vec<Keys>> = groups
+            .iter()
+            .map(|(_, sounds)| SEQUENCES.map(|sequence| keys_for(sounds, sequence)).collect())
+            .collect();
+        for ((_, sounds), group_options) in groups.iter().zip(&options) {
+            if group_options.iter().all(Option::is_none) {
+                return Err(format!("{} can't be played from the keyboard", sounds.join(" ")));
+            }
+        }
+
+        // Find the cheapest sequence for each group, remembering where we came from
+        let mut costs: Vec<Vec<Option<(Cost, usize)>>> = Vec::new();
+        for (index, group_options) in options.iter().enumerate() {
+            let row = group_options
+                .iter()
+                .enumerate()
+                .map(|(sequence, option)| {
+                    let (_, modifiers) = option.as_ref()?;
+                    if index == 0 {
+                        let distance = (sequence as i8 + SEQUENCES.start() - DEFAULT_SEQUENCE).unsigned_abs();
+                        return Some(((0, 0, *modifiers, distance as u32), sequence));
+                    }
+                    costs[index - 1]
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(previous, cost)| cost.map(|(cost, _)| (previous, cost)))
+                        .map(|(previous, (switches, presses, previous_modifiers, start))| {
+                            let distance = (sequence as i32 - previous as i32).unsigned_abs();
+                            let cost = (
+                                switches + (distance > 0) as u32,
+                                presses + distance,
+                                previous_modifiers + modifiers,
+                                start,
+                            );
+                            (cost, previous)
+                        })
+                        .min()
+                })
+                .collect();
+            costs.push(row);
+        }
+
+        let mut sequence = match costs.last() {
+            Some(row) => row.iter()
+                .enumerate()
+                .filter_map(|(sequence, cost)| cost.map(|(cost, _)| (cost, sequence)))
+                .min()
+                .map(|(_, sequence)| sequence)
+                .unwrap_or_default(),
+            None => return Ok(CheatSheet { sections: Vec::new() }),
+        };
+        let mut sequences = vec![0; groups.len()];
+        for index in (0..groups.len()).rev() {
+            sequences[index] = sequence;
+            sequence = costs[index][sequence].map_or(sequence, |(_, previous)| previous);
+        }
+
+        let mut sections: Vec<Section> = Vec::new();
+        let mut previous_time = None;
+        for (index, &sequence) in sequences.iter().enumerate() {
+            let (time, _) = groups[index];
+            let (keys, _) = options[index][sequence].clone().unwrap();
+            let group = KeyGroup {
+                keys,
+                pause_before: previous_time.is_some_and(|previous| time - previous >= PAUSE),
+            };
+            previous_time = Some(time);
+
+            let sequence = sequence as i8 + SEQUENCES.start();
+            match sections.last_mut() {
+                Some(section) if section.sequence == sequence => section.groups.push(group),
+                _ => sections.push(Section { sequence, groups: vec![group] }),
+            }
+        }
+        Ok(CheatSheet { sections })
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let mut previous_sequence = None;
+        for section in &self.sections {
+            text.push_str(&match previous_sequence {
+                None => format!("Start piano-rs with --sequence {}\n", section.sequence),
+                Some(previous) => format!("\nPress {} for sequence {}\n", arrow_presses(previous, section.sequence, "Right", "Left"), section.sequence),
+            });
+            previous_sequence = Some(section.sequence);
+
+            // Sixteen keys or chords to a line, however long the section stays on one sequence
+            for line in section.groups.chunks(16) {
+                let keys: Vec<String> = line.iter()
+                    .map(|group| {
+                        // Written like letter sheets, unless a chord needs Ctrl
+                        let separator = if group.keys.iter().all(|key| matches!(key, KeyEvent::Char(_))) { "" } else { " " };
+                        let keys = group.keys.iter().map(key_label).collect::<Vec<String>>().join(separator);
+                        let keys = if group.keys.len() > 1 { format!("[{}]", keys) } else { keys };
+                        if group.pause_before { format!("| {}", keys) } else { keys }
+                    })
+                    .collect();
+                text.push_str(&format!("  {}\n", keys.join(" ")));
+            }
+        }
+        text
+    }
+
+    pub fn to_html(&self, title: &str) -> String {
+        let mut body = String::new();
+        let mut previous_sequence = None;
+        for section in &self.sections {
+            body.push_str(&format!("<h2>Sequence {}</h2>\n", section.sequence));
+            body.push_str(&match previous_sequence {
+                None => format!("<p>Start piano-rs with <code>--sequence {}</code></p>\n", section.sequence),
+                Some(previous) => format!("<p>Press {}</p>\n", arrow_presses(previous, section.sequence, "<kbd>&rarr;</kbd>", "<kbd>&larr;</kbd>")),
+            });
+            previous_sequence = Some(section.sequence);
+
+            body.push_str("<p class=\"keys\">\n");
+            for group in &section.groups {
+                if group.pause_before {
+                    body.push_str("<span class=\"pause\">|</span>\n");
+                }
+                let keys: String = group.keys.iter()
+                    .map(|key| format!("<kbd>{}</kbd>", escape_html(&key_label(key))))
+                    .collect();
+                body.push_str(&format!("<span class=\"group\">{}</span>\n", keys));
+            }
+            body.push_str("</p>\n");
+        }
+
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>\n\
+             kbd {{ border: 1px solid #888; border-radius: 3px; padding: 0 4px; font-family: monospace; }}\n\
+             .group {{ display: inline-block; margin: 4px 8px 4px 0; }}\n\
+             .pause {{ color: #888; margin-right: 8px; }}\n\
+             </style>\n</head>\n<body>\n<h1>{title}</h1>\n{body}</body>\n</html>\n",
+            title = escape_html(title),
+            body = body,
+        )
+    }
+}
+
+// Picks the key with the fewest modifiers for each sound, preferring letters and digits
+// over the symbols that play the same note
+fn keys_for(sounds: &[&str], sequence: i8) -> Keys {
+    let mut keys = Vec::new();
+    let mut total_modifiers = 0;
+    for sound in sounds {
+        let key = notes::base_note_to_keys(sound, sequence)
+            .into_iter()
+            .min_by_key(|key| (modifiers(key), !is_alphanumeric(key)))?;
+        total_modifiers += modifiers(&key);
+        keys.push(key);
+    }
+    Some((keys, total_modifiers))
+}
+
+fn modifiers(key: &KeyEvent) -> u32 {
+    match key {
+        KeyEvent::Char(c) if c.is_ascii_uppercase() || "~!@#$%^&*()_+{}|:\"<>?".contains(*c) => 1,
+        KeyEvent::Ctrl(_) => 1,
+        _ => 0,
+    }
+}
+
+fn is_alphanumeric(key: &KeyEvent) -> bool {
+    match key {
+        KeyEvent::Char(c) | KeyEvent::Ctrl(c) => c.is_ascii_alphanumeric(),
+        _ => false,
+    }
+}
+
+pub fn key_label(key: &KeyEvent) -> String {
+    match key {
+        KeyEvent::Ctrl(c) => format!("Ctrl+{}", c),
+        KeyEvent::Char(c) => c.to_string(),
+        key => format!("{:?}", key),
+    }
+}
+
+fn arrow_presses(from: i8, to: i8, right: &str, left: &str) -> String {
+    let arrow = if to > from { right } else { left };
+    match (to - from).abs() {
+        1 => format!("{} once", arrow),
+        presses => format!("{} {} times", arrow, presses),
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CheatSheet, KeyEvent};
+    use crate::game::notes_file::test_song;
+
+    #[test]
+    fn stay_on_one_sequence() {
+        let cheat_sheet = CheatSheet::from_song(&test_song(&[(0, "c3", 0), (200, "e3", 0), (0, "g3", 0), (1500, "c4", 0)])).unwrap();
+        assert_eq!(cheat_sheet.sections.len(), 1);
+        assert_eq!(cheat_sheet.sections[0].sequence, 2);
+        assert_eq!(cheat_sheet.to_text(), "Start piano-rs with --sequence 2\n  e [tu] | p\n");
+    }
+
+    #[test]
+    fn switch_sequences() {
+        let cheat_sheet = CheatSheet::from_song(&test_song(&[(0, "a-1", 0), (200, "c7", 0)])).unwrap();
+        let sequences: Vec<i8> = cheat_sheet.sections.iter().map(|section| section.sequence).collect();
+        assert_eq!(sequences.len(), 2);
+        assert_eq!(cheat_sheet.sections[0].groups[0].keys.len(), 1);
+        assert!(cheat_sheet.to_text().contains(&format!("Press Right {} times", sequences[1] - sequences[0])));
+    }
+
+    #[test]
+    fn escape_html_keys() {
+        let cheat_sheet = CheatSheet::from_song(&test_song(&[(0, "c3", 0)])).unwrap();
+        let html = cheat_sheet.to_html("<Song>");
+        assert!(html.contains("<title>&lt;Song&gt;</title>"));
+        assert!(html.contains("<kbd>e</kbd>"));
+        assert_eq!(super::key_label(&KeyEvent::Ctrl('k')), "Ctrl+k");
+    }
+}
@@ -0,0 +1,146 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use crate::game::{abc, lilypond, midi, musicxml, sheet};
+use crate::game::lilypond::LilyPondOptions;
+use crate::game::notes_file::{NoteReader, NoteRecorder, Song};
+use crate::game::sheet::SheetOptions;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Yaml,
+    Midi,
+    Abc,
+    MusicXml,
+    Sheet,
+    LilyPond,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    // Formats are named after their file extensions
+    fn from_str(format: &str) -> Result<Format, String> {
+        match format.to_lowercase().as_str() {
+            "yml" | "yaml" => Ok(Format::Yaml),
+            "mid" | "midi" => Ok(Format::Midi),
+            "abc" => Ok(Format::Abc),
+            "musicxml" | "xml" => Ok(Format::MusicXml),
+            "txt" | "sheet" => Ok(Format::Sheet),
+            "ly" | "lilypond" => Ok(Format::LilyPond),
+            "mxl" => Err(String::from(
+                "Compressed MusicXML isn't supported, export the score as uncompressed .musicxml instead"
+            )),
+            _ => Err(format!("Unknown format \"{}\", expected one of yml, mid, abc, musicxml, txt or ly", format)),
+        }
+    }
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Option<Result<Format, String>> {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.parse())
+    }
+
+    // Guesses the format of files with an unknown extension from their content
+    pub fn detect(content: &[u8]) -> Format {
+        if content.starts_with(b"MThd") {
+            return Format::Midi;
+        }
+        let text = String::from_utf8_lossy(content);
+        if text.contains("<score-partwise") || text.contains("<score-timewise") {
+            Format::MusicXml
+        } else if text.lines().any(|line| line.trim_start().starts_with("X:")) {
+            Format::Abc
+        } else if text.trim().is_empty() || text.trim_start().starts_with("---") || text.lines().any(|line| {
+            ["note_", "control_", "marker_", "track_"].iter().any(|entry| line.starts_with(entry))
+        }) {
+            Format::Yaml
+        } else {
+            Format::Sheet
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ConvertOptions {
+    pub sheet: SheetOptions,
+    pub lilypond: LilyPondOptions,
+}
+
+// Reads a song in any format, which is guessed from the file when not given
+pub fn read_song(path: &Path, format: Option<Format>, sheet_options: SheetOptions) -> Result<Song, String> {
+    let content = fs::read(path).map_err(|e| e.to_string())?;
+    let format = match format {
+        Some(format) => format,
+        None => match Format::from_path(path) {
+            Some(Ok(format)) => format,
+            // Compressed MusicXML can't be read at all, while other extensions are guessed from the content
+            Some(Err(e)) if path.extension().is_some_and(|extension| extension == "mxl") => return Err(e),
+            _ => Format::detect(&content),
+        },
+    };
+    let text = || std::str::from_utf8(&content).map_err(|_| String::from("File is not valid UTF-8"));
+
+    match format {
+        Format::Yaml => {
+            let reader = NoteReader::parse(text()?)?;
+            reader.check()?;
+            Ok(reader.parse_song())
+        }
+        Format::Midi => midi::parse_song(&content),
+        Format::Abc => abc::parse_song(text()?),
+        Format::MusicXml => musicxml::parse_song(text()?),
+        Format::Sheet => sheet::parse_song(text()?, sheet_options),
+        Format::LilyPond => Err(String::from("LilyPond files can only be written")),
+    }
+}
+
+// Writes a song in the given format, or the one matching the file's extension and otherwise .yml
+pub fn write_song(path: &Path, format: Option<Format>, song: &Song, options: ConvertOptions) -> Result<(), String> {
+    let format = match format {
+        Some(format) => format,
+        None => Format::from_path(path).transpose()?.unwrap_or(Format::Yaml),
+    };
+    let title = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+
+    let content = match format {
+        Format::Yaml => {
+            NoteRecorder::create(path.to_path_buf()).map_err(|e| e.to_string())?.write_song(song);
+            return Ok(());
+        }
+        Format::Midi => midi::write_song(song)?,
+        Format::Sheet => sheet::write_sheet(song, options.sheet)?.into_bytes(),
+        Format::LilyPond => lilypond::write_score(song, &title, options.lilypond)?.into_bytes(),
+        Format::Abc => return Err(String::from("Songs can't be written as ABC yet")),
+        Format::MusicXml => return Err(String::from("Songs can't be written as MusicXML yet")),
+    };
+    fs::write(path, content).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::Format;
+    use std::path::Path;
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(Format::from_path(Path::new("song.MID")), Some(Ok(Format::Midi)));
+        assert_eq!(Format::from_path(Path::new("song.musicxml")), Some(Ok(Format::MusicXml)));
+        assert!(Format::from_path(Path::new("song.mxl")).unwrap().is_err());
+        assert_eq!(Format::from_path(Path::new("song")), None);
+    }
+
+    #[test]
+    fn detect_format() {
+        assert_eq!(Format::detect(b"MThd\0\0\0\x06"), Format::Midi);
+        assert_eq!(Format::detect(b"<?xml version=\"1.0\"?>\n<score-partwise>"), Format::MusicXml);
+        assert_eq!(Format::detect(b"X:1\nK:C\nCDE"), Format::Abc);
+        assert_eq!(Format::detect(b"---\nnote_1:\n  - 0\n"), Format::Yaml);
+        assert_eq!(Format::detect(b""), Format::Yaml);
+        assert_eq!(Format::detect(b"marker_1:\n  - 0\n  - section\n  - \"Intro\"\n"), Format::Yaml);
+        assert_eq!(Format::detect(b"---\n# Written by hand\n"), Format::Yaml);
+        assert_eq!(Format::detect(b"[tu] y t | 8 9 0"), Format::Sheet);
+    }
+}
@@ -0,0 +1,519 @@
+use std::mem;
+use std::time::Duration;
+use crate::game::notes;
+use crate::game::notes_file::{self, Control, FileControl, FileMarker, FileNote, MarkerKind, Song};
+use crate::game::scale::Scale;
+
+// Songs end once their last note has finished playing
+pub fn song_length(notes: &[FileNote]) -> Duration {
+    notes_file::start_times(notes)
+        .into_iter()
+        .zip(notes)
+        .map(|(time, note)| time + note.duration)
+        .max()
+        .unwrap_or_default()
+}
+
+// Removes the silence before the first note, along with any controls and markers left over
+// after the last note has finished
+pub fn trim(song: &Song) -> Song {
+    let mut notes = song.notes.clone();
+    let silence = match notes.first_mut() {
+        Some(first_note) => mem::take(&mut first_note.delay),
+        None => Duration::from_millis(0),
+    };
+    let length = song_length(&notes);
+    let move_to = |time: Duration| match time.checked_sub(silence).unwrap_or_default() {
+        time if time > length => None,
+        time => Some(time),
+    };
+    Song {
+        notes,
+        controls: move_controls(&song.controls, move_to),
+        markers: move_markers(&song.markers, move_to),
+        ..song.clone()
+    }
+}
+
+// Removes the notes starting within `start..end` and closes the gap they leave behind
+pub fn cut(song: &Song, start: Duration, end: Duration) -> Song {
+    let gap = end.checked_sub(start).unwrap_or_default();
+    let timed_notes = notes_file::start_times(&song.notes)
+        .into_iter()
+        .zip(song.notes.iter().cloned())
+        .filter(|&(time, _)| time < start || time >= end)
+        .map(|(time, note)| if time >= end { (time - gap, note) } else { (time, note) })
+        .collect();
+    // Controls from the removed part still apply to what comes after it
+    let controls = move_controls(&song.controls, |time| match time {
+        time if time >= end => Some(time - gap),
+        time if time >= start => Some(start),
+        time => Some(time),
+    });
+    // While markers from it are removed along with the notes
+    let markers = move_markers(&song.markers, |time| match time {
+        time if time >= end => Some(time - gap),
+        time if time >= start => None,
+        time => Some(time),
+    });
+    Song {
+        notes: from_timeline(timed_notes),
+        controls,
+        markers,
+        ..song.clone()
+    }
+}
+
+// Keeps only the notes starting within `start..end`
+pub fn extract(song: &Song, start: Duration, end: Duration) -> Song {
+    let timed_notes = notes_file::start_times(&song.notes)
+        .into_iter()
+        .zip(song.notes.iter().cloned())
+        .filter(|&(time, _)| time >= start && time < end)
+        .map(|(time, note)| (time - start, note))
+        .collect();
+    // Controls from before the start set up the keyboard for the first note
+    let controls = move_controls(&song.controls, |time| match time {
+        time if time >= end => None,
+        time => Some(time.checked_sub(start).unwrap_or_default()),
+    });
+    let markers = move_markers(&song.markers, |time| match time {
+        time if time >= start && time < end => Some(time - start),
+        _ => None,
+    });
+    Song {
+        notes: from_timeline(timed_notes),
+        controls,
+        markers,
+        ..song.clone()
+    }
+}
+
+// Plays `second` once `first` has finished, after waiting for `gap`
+pub fn concat(first: &Song, second: &Song, gap: Duration) -> Song {
+    overlay(first, second, song_length(&first.notes) + gap)
+}
+
+// Plays `second` on top of `first`, starting `offset` into it
+pub fn overlay(first: &Song, second: &Song, offset: Duration) -> Song {
+    let mut timed_notes: Vec<(Duration, FileNote)> = notes_file::start_times(&first.notes)
+        .into_iter()
+        .zip(first.notes.iter().cloned())
+        .collect();
+    // Tracks of the second song are numbered after the ones in the first
+    let track_offset = first.tracks.len();
+    timed_notes.extend(
+        notes_file::start_times(&second.notes)
+            .into_iter()
+            .map(|time| time + offset)
+            .zip(second.notes.iter().map(|note| FileNote {
+                track: note.track.map(|track| track + track_offset),
+                ..note.clone()
+            }))
+    );
+    // Stable sort keeps chords from the same file together
+    timed_notes.sort_by_key(|&(time, _)| time);
+
+    let mut controls = first.controls.clone();
+    controls.extend(move_controls(&second.controls, |time| Some(time + offset)));
+    controls.sort_by_key(|control| control.time);
+
+    let mut markers = first.markers.clone();
+    markers.extend(move_markers(&second.markers, |time| Some(time + offset)));
+    markers.sort_by_key(|marker| marker.time);
+
+    Song {
+        tracks: first.tracks.iter().chain(second.tracks.iter()).cloned().collect(),
+        notes: from_timeline(timed_notes),
+        controls,
+        markers,
+    }
+}
+
+pub fn transpose(notes: &[FileNote], semitones: i16) -> Result<Vec<FileNote>, String> {
+    notes.iter()
+        .map(|note| match notes::transpose_sound(&note.base_note, semitones) {
+            Some(base_note) => Ok(FileNote {
+                base_note,
+                ..note.clone()
+            }),
+            None => Err(format!("Cannot transpose {} by {} semitones", note.base_note, semitones)),
+        })
+        .collect()
+}
+
+// Mirrors each note around `pivot`, so that a step up becomes a step down
+pub fn invert(notes: &[FileNote], pivot: &str) -> Result<Vec<FileNote>, String> {
+    let pivot_semitone = notes::sound_to_semitone(pivot)
+        .ok_or_else(|| format!("Unknown pivot note \"{}\"", pivot))?;
+    map_pitches(notes, |semitone| 2 * pivot_semitone - semitone)
+        .map_err(|base_note| format!("Cannot invert {} around {}", base_note, pivot))
+}
+
+// Moves each note to the same degree of another scale or mode
+pub fn map_to_scale(notes: &[FileNote], from: &Scale, to: &Scale) -> Result<Vec<FileNote>, String> {
+    map_pitches(notes, |semitone| from.map_to(semitone, to))
+        .map_err(|base_note| format!("Cannot map {} to the new scale", base_note))
+}
+
+// Changes the pitch of every note, failing with the first note that can't be moved
+fn map_pitches<F: Fn(i16) -> i16>(notes: &[FileNote], map: F) -> Result<Vec<FileNote>, String> {
+    notes.iter()
+        .map(|note| notes::sound_to_semitone(&note.base_note)
+            .and_then(|semitone| notes::semitone_to_sound(map(semitone)))
+            .map(|base_note| FileNote {
+                base_note,
+                ..note.clone()
+            })
+            .ok_or_else(|| note.base_note.clone()))
+        .collect()
+}
+
+// Plays the song backwards, starting each note where the matching one used to end
+pub fn retrograde(song: &Song) -> Song {
+    let length = song_length(&song.notes);
+    let mut timed_notes: Vec<(Duration, FileNote)> = notes_file::start_times(&song.notes)
+        .into_iter()
+        .zip(song.notes.iter().cloned())
+        .map(|(time, note)| (mirror(time + note.duration, length), note))
+        .collect();
+    timed_notes.sort_by_key(|&(time, _)| time);
+
+    // Controls and sections last until the next one of their kind, so backwards they start
+    // where the next one used to
+    let mut controls: Vec<FileControl> = song.controls
+        .iter()
+        .map(|control| {
+            let next = song.controls
+                .iter()
+                .filter(|other| mem::discriminant(&other.control) == mem::discriminant(&control.control))
+                .map(|other| other.time)
+                .filter(|&time| time > control.time)
+                .min();
+            FileControl {
+                time: mirror(next.unwrap_or(length), length),
+                ..*control
+            }
+        })
+        .collect();
+    controls.sort_by_key(|control| control.time);
+
+    let mut markers: Vec<FileMarker> = song.markers
+        .iter()
+        .map(|marker| {
+            let time = match marker.kind {
+                MarkerKind::Section => song.markers
+                    .iter()
+                    .filter(|other| other.kind == MarkerKind::Section && other.time > marker.time)
+                    .map(|other| other.time)
+                    .min()
+                    .unwrap_or(length),
+                _ => marker.time,
+            };
+            FileMarker {
+                time: mirror(time, length),
+                ..marker.clone()
+            }
+        })
+        .collect();
+    markers.sort_by_key(|marker| marker.time);
+
+    Song {
+        notes: from_timeline(timed_notes),
+        controls,
+        markers,
+        ..song.clone()
+    }
+}
+
+fn mirror(time: Duration, length: Duration) -> Duration {
+    length.checked_sub(time).unwrap_or_default()
+}
+
+// Stretches every note and gap by `factor`, so 2.0 doubles their lengths and 0.5 halves them
+pub fn augment(song: &Song, factor: f32) -> Result<Song, String> {
+    check_factor(factor)?;
+    scale_tempo(song, 1.0 / factor)
+}
+
+// Speeds up the song by `factor`, the same way --playback-tempo does during playback, and
+// raises its tempos to match
+pub fn scale_tempo(song: &Song, factor: f32) -> Result<Song, String> {
+    check_factor(factor)?;
+    // Start times are scaled rather than the gaps between notes, so that rounding doesn't add
+    // up along the song and pull the notes away from their controls and markers
+    let start_times: Vec<Duration> = notes_file::start_times(&song.notes)
+        .into_iter()
+        .map(|time| scale(time, factor))
+        .collect();
+    let mut notes: Vec<FileNote> = song.notes
+        .iter()
+        .map(|note| FileNote {
+            duration: scale(note.duration, factor),
+            ..note.clone()
+        })
+        .collect();
+    notes_file::set_start_times(&mut notes, &start_times);
+    Ok(Song {
+        notes,
+        controls: move_controls(&song.controls, |time| Some(scale(time, factor)))
+            .into_iter()
+            .map(|control| match control.control {
+                Control::Tempo { bpm, ramp } => FileControl { control: Control::Tempo { bpm: bpm * factor, ramp }, ..control },
+                _ => control,
+            })
+            .collect(),
+        markers: move_markers(&song.markers, |time| Some(scale(time, factor))),
+        ..song.clone()
+    })
+}
+
+fn check_factor(factor: f32) -> Result<(), String> {
+    if factor > 0.0 && factor.is_finite() {
+        Ok(())
+    } else {
+        Err(format!("Cannot scale a song by {}, expected a number greater than 0", factor))
+    }
+}
+
+fn scale(duration: Duration, factor: f32) -> Duration {
+    Duration::from_millis((duration.as_millis() as f64 / factor as f64).round() as u64)
+}
+
+// Moves each control to the time returned for it, dropping the ones it returns None for
+fn move_controls<F: Fn(Duration) -> Option<Duration>>(controls: &[FileControl], move_to: F) -> Vec<FileControl> {
+    controls.iter()
+        .filter_map(|control| move_to(control.time).map(|time| FileControl {
+            time,
+            ..*control
+        }))
+        .collect()
+}
+
+fn move_markers<F: Fn(Duration) -> Option<Duration>>(markers: &[FileMarker], move_to: F) -> Vec<FileMarker> {
+    markers.iter()
+        .filter_map(|marker| move_to(marker.time).map(|time| FileMarker {
+            time,
+            ..marker.clone()
+        }))
+        .collect()
+}
+
+fn from_timeline(timed_notes: Vec<(Duration, FileNote)>) -> Vec<FileNote> {
+    let (start_times, mut notes): (Vec<Duration>, Vec<FileNote>) = timed_notes.into_iter().unzip();
+    notes_file::set_start_times(&mut notes, &start_times);
+    notes
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FileControl, FileMarker, FileNote, Song, Duration};
+    use crate::game::notes_file::{test_song, Control, FileTrack, MarkerKind, NoteSource};
+    use crossterm_style::Color;
+
+    fn summary(notes: &[FileNote]) -> Vec<(u128, &str)> {
+        notes.iter()
+            .map(|note| (note.delay.as_millis(), note.base_note.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn song_length() {
+        let notes = test_song(&[(100, "a2", 500), (200, "b2", 100)]).notes;
+        assert_eq!(super::song_length(&notes), Duration::from_millis(600));
+    }
+
+    fn control_times(song: &Song) -> Vec<u128> {
+        song.controls.iter().map(|control| control.time.as_millis()).collect()
+    }
+
+    fn volume_at(time: u64) -> FileControl {
+        FileControl {
+            time: Duration::from_millis(time),
+            control: Control::Volume(0.5),
+        }
+    }
+
+    #[test]
+    fn trim() {
+        let mut song = test_song(&[(1500, "a2", 0), (200, "b2", 0)]);
+        song.controls = vec![volume_at(1000), volume_at(1600)];
+        let trimmed_song = super::trim(&song);
+        assert_eq!(summary(&trimmed_song.notes), vec![(0, "a2"), (200, "b2")]);
+        assert_eq!(control_times(&trimmed_song), vec![0, 100]);
+    }
+
+    #[test]
+    fn trim_end() {
+        let mut song = test_song(&[(500, "a2", 300), (100, "b2", 100)]);
+        song.controls = vec![volume_at(700), volume_at(900)];
+        song.markers = vec![FileMarker { time: Duration::from_millis(1000), kind: MarkerKind::Lyric, text: String::from("la") }];
+        let trimmed_song = super::trim(&song);
+        assert_eq!(super::song_length(&trimmed_song.notes), Duration::from_millis(300));
+        assert_eq!(control_times(&trimmed_song), vec![200]);
+        assert!(trimmed_song.markers.is_empty());
+    }
+
+    #[test]
+    fn cut() {
+        let mut song = test_song(&[(0, "a2", 0), (100, "b2", 0), (100, "c3", 0), (100, "d3", 0)]);
+        song.controls = vec![volume_at(50), volume_at(200), volume_at(300)];
+        let cut_song = super::cut(&song, Duration::from_millis(100), Duration::from_millis(250));
+        assert_eq!(summary(&cut_song.notes), vec![(0, "a2"), (150, "d3")]);
+        assert_eq!(control_times(&cut_song), vec![50, 100, 150]);
+    }
+
+    #[test]
+    fn extract() {
+        let mut song = test_song(&[(0, "a2", 0), (100, "b2", 0), (100, "c3", 0), (100, "d3", 0)]);
+        song.controls = vec![volume_at(50), volume_at(200), volume_at(300)];
+        let extracted_song = super::extract(&song, Duration::from_millis(150), Duration::from_millis(300));
+        assert_eq!(summary(&extracted_song.notes), vec![(50, "c3")]);
+        assert_eq!(control_times(&extracted_song), vec![0, 50]);
+    }
+
+    #[test]
+    fn move_markers() {
+        let mut song = test_song(&[(0, "a2", 0), (100, "b2", 0), (100, "c3", 0), (100, "d3", 0)]);
+        song.markers = [0, 100, 200, 300]
+            .iter()
+            .map(|&time| FileMarker {
+                time: Duration::from_millis(time),
+                kind: MarkerKind::Lyric,
+                text: time.to_string(),
+            })
+            .collect();
+        let marker_times = |song: &Song| -> Vec<u128> {
+            song.markers.iter().map(|marker| marker.time.as_millis()).collect()
+        };
+
+        let cut_song = super::cut(&song, Duration::from_millis(100), Duration::from_millis(250));
+        assert_eq!(marker_times(&cut_song), vec![0, 150]);
+        let extracted_song = super::extract(&song, Duration::from_millis(150), Duration::from_millis(300));
+        assert_eq!(marker_times(&extracted_song), vec![50]);
+        let concatenated_song = super::concat(&song, &song, Duration::from_millis(0));
+        assert_eq!(marker_times(&concatenated_song), vec![0, 100, 200, 300, 300, 400, 500, 600]);
+    }
+
+    #[test]
+    fn concat() {
+        let first = test_song(&[(0, "a2", 300)]);
+        let mut second = test_song(&[(50, "b2", 0), (0, "c3", 0)]);
+        second.controls = vec![volume_at(0)];
+        let song = super::concat(&first, &second, Duration::from_millis(100));
+        assert_eq!(summary(&song.notes), vec![(0, "a2"), (450, "b2"), (0, "c3")]);
+        assert_eq!(control_times(&song), vec![400]);
+    }
+
+    #[test]
+    fn overlay() {
+        let first = test_song(&[(0, "a2", 0), (200, "b2", 0)]);
+        let second = test_song(&[(0, "c3", 0), (200, "d3", 0)]);
+        let song = super::overlay(&first, &second, Duration::from_millis(100));
+        assert_eq!(summary(&song.notes), vec![(0, "a2"), (100, "c3"), (100, "b2"), (100, "d3")]);
+    }
+
+    #[test]
+    fn overlay_tracks() {
+        let mut first = test_song(&[(0, "a2", 0)]);
+        first.tracks.push(FileTrack::from_source(NoteSource::Local, Color::Blue));
+        first.notes[0].track = Some(1);
+        let mut second = test_song(&[(0, "c3", 0)]);
+        second.tracks.push(FileTrack::from_source(NoteSource::Local, Color::Red));
+        second.notes[0].track = Some(1);
+
+        let song = super::overlay(&first, &second, Duration::from_millis(0));
+        assert_eq!(song.tracks.len(), 2);
+        assert_eq!(song.track(&song.notes[1]).map(|track| track.color), Some(Color::Red));
+    }
+
+    #[test]
+    fn transpose() {
+        let notes = test_song(&[(0, "b2", 0), (0, "a-1", 0)]).notes;
+        let transposed_notes = super::transpose(&notes, 1).unwrap();
+        assert_eq!(summary(&transposed_notes), vec![(0, "c3"), (0, "as-1")]);
+        assert!(super::transpose(&notes, -1).is_err());
+    }
+
+    #[test]
+    fn scale_tempo() {
+        let mut song = test_song(&[(300, "a2", 150)]);
+        song.controls = vec![volume_at(600)];
+        let scaled_song = super::scale_tempo(&song, 1.5).unwrap();
+        assert_eq!(scaled_song.notes[0].delay, Duration::from_millis(200));
+        assert_eq!(scaled_song.notes[0].duration, Duration::from_millis(100));
+        assert_eq!(control_times(&scaled_song), vec![400]);
+        assert!(super::scale_tempo(&song, 0.0).is_err());
+        assert!(super::scale_tempo(&song, -2.0).is_err());
+        assert!(super::scale_tempo(&song, f32::NAN).is_err());
+    }
+
+    #[test]
+    fn scale_song_tempo() {
+        let mut song = test_song(&[(0, "a2", 500)]);
+        song.controls = vec![FileControl { time: Duration::from_millis(1000), control: Control::Tempo { bpm: 90.0, ramp: true } }];
+        let scaled_song = super::scale_tempo(&song, 2.0).unwrap();
+        assert_eq!(scaled_song.controls[0].control, Control::Tempo { bpm: 180.0, ramp: true });
+        assert_eq!(control_times(&scaled_song), vec![500]);
+        let augmented_song = super::augment(&song, 2.0).unwrap();
+        assert_eq!(augmented_song.controls[0].control, Control::Tempo { bpm: 45.0, ramp: true });
+    }
+
+    #[test]
+    fn scale_tempo_keeps_controls_with_notes() {
+        // Ten notes 10ms apart, with a control at the last one
+        let notes: Vec<(u64, &str, u64)> = (0..10).map(|index| (if index == 0 { 0 } else { 10 }, "a2", 10)).collect();
+        let mut song = test_song(&notes);
+        song.controls = vec![volume_at(90)];
+        let scaled_song = super::scale_tempo(&song, 3.0).unwrap();
+        let last_start = scaled_song.notes.iter().map(|note| note.delay).sum::<Duration>();
+        assert_eq!(last_start, Duration::from_millis(30));
+        assert_eq!(control_times(&scaled_song), vec![30]);
+    }
+
+    #[test]
+    fn invert() {
+        let notes = test_song(&[(0, "c3", 0), (0, "e3", 0), (0, "g3", 0)]).notes;
+        let inverted_notes = super::invert(&notes, "e3").unwrap();
+        assert_eq!(summary(&inverted_notes), vec![(0, "gs3"), (0, "e3"), (0, "cs3")]);
+        assert!(super::invert(&notes, "c-1").is_err());
+        assert!(super::invert(&notes, "x3").is_err());
+    }
+
+    #[test]
+    fn map_to_scale() {
+        let notes = test_song(&[(0, "c3", 0), (0, "e3", 0), (0, "b3", 0)]).notes;
+        let (from, to) = ("c major".parse().unwrap(), "c minor".parse().unwrap());
+        let mapped_notes = super::map_to_scale(&notes, &from, &to).unwrap();
+        assert_eq!(summary(&mapped_notes), vec![(0, "c3"), (0, "ds3"), (0, "as3")]);
+    }
+
+    #[test]
+    fn retrograde() {
+        let mut song = test_song(&[(0, "a2", 100), (100, "b2", 300), (100, "c3", 100)]);
+        song.controls = vec![volume_at(0), volume_at(150)];
+        song.markers = vec![
+            FileMarker { time: Duration::from_millis(0), kind: MarkerKind::Section, text: String::from("Verse") },
+            FileMarker { time: Duration::from_millis(200), kind: MarkerKind::Section, text: String::from("Chorus") },
+            FileMarker { time: Duration::from_millis(100), kind: MarkerKind::Lyric, text: String::from("la") },
+        ];
+        let reversed_song = super::retrograde(&song);
+        assert_eq!(summary(&reversed_song.notes), vec![(0, "b2"), (100, "c3"), (200, "a2")]);
+        assert_eq!(control_times(&reversed_song), vec![0, 250]);
+        let markers: Vec<(u128, &str)> = reversed_song.markers
+            .iter()
+            .map(|marker| (marker.time.as_millis(), marker.text.as_str()))
+            .collect();
+        assert_eq!(markers, vec![(0, "Chorus"), (200, "Verse"), (300, "la")]);
+    }
+
+    #[test]
+    fn augment() {
+        let song = test_song(&[(300, "a2", 150)]);
+        let augmented_song = super::augment(&song, 2.0).unwrap();
+        assert_eq!(augmented_song.notes[0].delay, Duration::from_millis(600));
+        assert_eq!(augmented_song.notes[0].duration, Duration::from_millis(300));
+        let diminished_song = super::augment(&song, 0.5).unwrap();
+        assert_eq!(diminished_song.notes[0].duration, Duration::from_millis(75));
+    }
+}
@@ -0,0 +1,185 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crossterm::KeyEvent;
+use crate::game::convert::{self, Format};
+use crate::game::edit;
+use crate::game::sheet::SheetOptions;
+
+// Songs shown at once in the browser
+const VISIBLE_SONGS: usize = 10;
+
+// Songs are kept in the same data directory the sound assets are searched in
+pub fn default_directory() -> PathBuf {
+    home::home_dir()
+        .unwrap_or_default()
+        .join(".local/share/piano-rs/songs/")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LibrarySong {
+    pub path: PathBuf,
+    pub title: String,
+    pub length: Duration,
+    pub notes: usize,
+}
+
+impl LibrarySong {
+    pub fn read(path: &Path, sheet_options: SheetOptions) -> Result<LibrarySong, String> {
+        let song = convert::read_song(path, None, sheet_options)?;
+        Ok(LibrarySong {
+            path: path.to_path_buf(),
+            title: path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().replace('_', " ")),
+            length: edit::song_length(&song.notes),
+            notes: song.notes.len(),
+        })
+    }
+}
+
+// Lists the songs in a directory by title, skipping files that can't be read as songs
+pub fn scan(directory: &Path, sheet_options: SheetOptions) -> Result<Vec<LibrarySong>, String> {
+    let entries = fs::read_dir(directory).map_err(|e| format!("{}: {}", directory.display(), e))?;
+    let mut songs: Vec<LibrarySong> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && Format::from_path(path).is_some_and(|format| format.is_ok()))
+        .filter_map(|path| LibrarySong::read(&path, sheet_options).ok())
+        .collect();
+    songs.sort_by_key(|song| song.title.to_lowercase());
+    Ok(songs)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BrowserAction {
+    Play(PathBuf),
+    Preview(PathBuf),
+    Close,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Browser {
+    songs: Vec<LibrarySong>,
+    selected: usize,
+    // First song in view
+    scroll: usize,
+}
+
+impl Browser {
+    pub fn new(songs: Vec<LibrarySong>) -> Browser {
+        Browser {
+            songs,
+            selected: 0,
+            scroll: 0,
+        }
+    }
+
+    pub fn selected(&self) -> Option<&LibrarySong> {
+        self.songs.get(self.selected)
+    }
+
+    pub fn process_key(&mut self, key: KeyEvent) -> Option<BrowserAction> {
+        match key {
+            KeyEvent::Up => {
+                self.select(self.selected.saturating_sub(1));
+                None
+            }
+            KeyEvent::Down => {
+                self.select(self.selected + 1);
+                None
+            }
+            KeyEvent::Enter => self.selected().map(|song| BrowserAction::Play(song.path.clone())),
+            KeyEvent::Char('p') => self.selected().map(|song| BrowserAction::Preview(song.path.clone())),
+            KeyEvent::Esc | KeyEvent::F(4) => Some(BrowserAction::Close),
+            _ => None,
+        }
+    }
+
+    fn select(&mut self, index: usize) {
+        if self.songs.is_empty() {
+            return;
+        }
+        self.selected = index.min(self.songs.len() - 1);
+        // Scroll just far enough to keep the selected song in view
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + VISIBLE_SONGS {
+            self.scroll = self.selected + 1 - VISIBLE_SONGS;
+        }
+    }
+
+    // Rows of the songs in view, along with which of them is selected
+    pub fn lines(&self) -> (Vec<String>, Option<usize>) {
+        if self.songs.is_empty() {
+            return (vec![String::from("No songs found")], None);
+        }
+        let lines = self.songs
+            .iter()
+            .skip(self.scroll)
+            .take(VISIBLE_SONGS)
+            .map(|song| {
+                let seconds = song.length.as_secs();
+                format!("{:<40} {:>3}:{:02} {:>6} notes", song.title, seconds / 60, seconds % 60, song.notes)
+            })
+            .collect();
+        (lines, Some(self.selected - self.scroll))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Browser, BrowserAction, LibrarySong, KeyEvent, Duration, PathBuf};
+    use crate::game::sheet::SheetOptions;
+    use std::env;
+    use std::fs;
+
+    fn library_song(title: &str) -> LibrarySong {
+        LibrarySong {
+            path: PathBuf::from(format!("{}.yml", title)),
+            title: title.to_string(),
+            length: Duration::from_millis(61500),
+            notes: 12,
+        }
+    }
+
+    #[test]
+    fn scan_directory() {
+        let directory = env::temp_dir().join("piano-rs-scan-directory");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("b_song.txt"), "e t u").unwrap();
+        fs::write(directory.join("A song.yml"), "note_1:\n  - 0\n  - c3\n  - 1500\n").unwrap();
+        fs::write(directory.join("notes.md"), "Not a song").unwrap();
+
+        let songs = super::scan(&directory, SheetOptions::default()).unwrap();
+        fs::remove_dir_all(directory).unwrap();
+        let titles: Vec<&str> = songs.iter().map(|song| song.title.as_str()).collect();
+        assert_eq!(titles, ["A song", "b song"]);
+        assert_eq!(songs[0].length, Duration::from_millis(1500));
+        assert_eq!(songs[1].notes, 3);
+    }
+
+    #[test]
+    fn browse_songs() {
+        let songs = (0..15).map(|number| library_song(&format!("Song {:02}", number))).collect();
+        let mut browser = Browser::new(songs);
+        assert_eq!(browser.process_key(KeyEvent::Up), None);
+        for _ in 0..12 {
+            browser.process_key(KeyEvent::Down);
+        }
+        let (lines, selected) = browser.lines();
+        assert_eq!(lines.len(), 10);
+        assert_eq!(selected, Some(9));
+        assert!(lines[9].starts_with("Song 12"));
+        assert!(lines[9].ends_with("1:01     12 notes"));
+
+        assert_eq!(browser.process_key(KeyEvent::Enter), Some(BrowserAction::Play(PathBuf::from("Song 12.yml"))));
+        assert_eq!(browser.process_key(KeyEvent::Char('p')), Some(BrowserAction::Preview(PathBuf::from("Song 12.yml"))));
+        assert_eq!(browser.process_key(KeyEvent::Esc), Some(BrowserAction::Close));
+    }
+
+    #[test]
+    fn browse_empty_library() {
+        let mut browser = Browser::new(Vec::new());
+        browser.process_key(KeyEvent::Down);
+        assert_eq!(browser.process_key(KeyEvent::Enter), None);
+        assert_eq!(browser.lines(), (vec![String::from("No songs found")], None));
+    }
+}
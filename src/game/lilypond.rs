@@ -0,0 +1,295 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+use crate::game::notes;
+use crate::game::notes_file::{self, Song};
+use crate::game::quantize::Grid;
+use crate::game::tempo::{TempoMap, TimeSignature};
+
+// Notes from middle C (c3) upwards go on the treble staff
+const MIDDLE_C: i16 = 36;
+const LETTERS: [char; 7] = ['c', 'd', 'e', 'f', 'g', 'a', 'b'];
+const NATURALS: [i16; 7] = [0, 2, 4, 5, 7, 9, 11];
+const SHARPS: [char; 7] = ['f', 'c', 'g', 'd', 'a', 'e', 'b'];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LilyPondOptions {
+    // Taken from the song's first tempo when not given
+    pub bpm: Option<f32>,
+    // Note starts and lengths are rounded to this grid
+    pub grid: Grid,
+    pub time_signature: TimeSignature,
+}
+
+impl Default for LilyPondOptions {
+    fn default() -> Self {
+        LilyPondOptions {
+            bpm: None,
+            grid: Grid { division: 16, triplet: false },
+            time_signature: TimeSignature { beats: 4, beat_unit: 4 },
+        }
+    }
+}
+
+// Start and length in steps of the grid, where notes played till their end have no length
+type GridNote = (u64, Option<u64>, i16);
+
+// Notes starting at the same step of the grid on one staff
+struct Chord {
+    start: u64,
+    length: u64,
+    semitones: Vec<i16>,
+}
+
+// Writes the song as a piano score, with every track merged onto a treble and a bass staff
+pub fn write_score(song: &Song, title: &str, options: LilyPondOptions) -> Result<String, String> {
+    let division = options.grid.division as u64;
+    if options.grid.triplet || !options.grid.division.is_power_of_two() {
+        return Err(String::from("Only grids such as 1/8 or 1/16 can be written as sheet music"));
+    }
+    let TimeSignature { beats, beat_unit } = options.time_signature;
+    if !(beats as u64 * division).is_multiple_of(beat_unit as u64) {
+        return Err(format!("A 1/{} grid is too coarse for {}/{} time", division, beats, beat_unit));
+    }
+    let bar = beats as u64 * division / beat_unit as u64;
+    let beat = (division / beat_unit as u64).max(1);
+    let bpm = options.bpm.or_else(|| TempoMap::new(&song.controls).bpm()).unwrap_or(120.0);
+    let step = options.grid.step(bpm).as_secs_f64();
+    let to_steps = |time: Duration| (time.as_secs_f64() / step).round() as u64;
+
+    let mut notes: Vec<GridNote> = Vec::new();
+    for (time, note) in notes_file::start_times(&song.notes).into_iter().zip(&song.notes) {
+        let semitone = notes::sound_to_semitone(&note.base_note)
+            .ok_or_else(|| format!("Unknown note \"{}\"", note.base_note))?;
+        // A duration of 0 plays till the end of the sound, so it's decided by the next chord
+        let length = Some(to_steps(note.duration).max(1)).filter(|_| note.duration > Duration::from_millis(0));
+        notes.push((to_steps(time), length, semitone));
+    }
+
+    let fifths = key_signature(notes.iter().map(|&(_, _, semitone)| semitone));
+    let treble = chords(notes.iter().filter(|&&(_, _, semitone)| semitone >= MIDDLE_C), beat);
+    let bass = chords(notes.iter().filter(|&&(_, _, semitone)| semitone < MIDDLE_C), beat);
+    let end = treble.iter().chain(&bass).map(|chord| chord.start + chord.length).max().unwrap_or(0);
+    let bars = (end.div_ceil(bar)).max(1);
+
+    let key = format!("\\key {} \\major", pitch_name((7 * fifths).rem_euclid(12), fifths));
+    let time = format!("\\time {}/{}", beats, beat_unit);
+    let tempo = format!(" \\tempo 4 = {}", bpm.round());
+    let staff = |clef: &str, tempo: &str, chords: &[Chord]| {
+        format!(
+            "    \\new Staff {{\n      \\clef {} {} {}{}\n      {}\n    }}\n",
+            clef,
+            key,
+            time,
+            tempo,
+            staff_bars(chords, bars, bar, division, fifths).join(" |\n      "),
+        )
+    };
+
+    Ok(format!(
+        "\\version \"2.24.0\"\n\n\\header {{\n  title = \"{}\"\n}}\n\n\\score {{\n  \\new PianoStaff <<\n{}{}  >>\n  \\layout {{ }}\n}}\n",
+        title.replace('\\', "\\\\").replace('"', "\\\""),
+        staff("treble", &tempo, &treble),
+        staff("bass", "", &bass),
+    ))
+}
+
+// Picks the major key with the most notes in its scale, counted in fifths from C major
+fn key_signature(semitones: impl Iterator<Item = i16>) -> i16 {
+    let mut counts = [0; 12];
+    for semitone in semitones {
+        counts[semitone.rem_euclid(12) as usize] += 1;
+    }
+    (-7..=7)
+        .max_by_key(|&fifths| {
+            let in_scale: u32 = NATURALS.iter()
+                .map(|&natural| counts[(natural + 7 * fifths).rem_euclid(12) as usize])
+                .sum();
+            (in_scale, -(fifths as i32).abs())
+        })
+        .unwrap_or(0)
+}
+
+fn chords<'a>(notes: impl Iterator<Item = &'a GridNote>, beat: u64) -> Vec<Chord> {
+    let mut starts: BTreeMap<u64, Vec<(Option<u64>, i16)>> = BTreeMap::new();
+    for &(start, length, semitone) in notes {
+        starts.entry(start).or_default().push((length, semitone));
+    }
+
+    let starts: Vec<_> = starts.into_iter().collect();
+    starts.iter()
+        .enumerate()
+        .map(|(index, (start, notes))| {
+            let next = starts.get(index + 1).map(|(next, _)| next - start);
+            // Notes are cut off by the next chord, since each staff only has a single voice
+            let length = notes.iter()
+                .map(|(length, _)| length.or(next).unwrap_or(beat))
+                .max()
+                .unwrap_or(beat);
+            let mut semitones: Vec<i16> = notes.iter().map(|&(_, semitone)| semitone).collect();
+            semitones.sort_unstable();
+            semitones.dedup();
+            Chord {
+                start: *start,
+                length: next.map_or(length, |next| length.min(next)),
+                semitones,
+            }
+        })
+        .collect()
+}
+
+fn staff_bars(chords: &[Chord], bars: u64, bar: u64, division: u64, fifths: i16) -> Vec<String> {
+    let mut tokens: Vec<Vec<String>> = vec![Vec::new(); bars as usize];
+    let mut time = 0;
+    for chord in chords {
+        push_tokens(&mut tokens, time, chord.start - time, None, bar, division);
+        let pitches: Vec<String> = chord.semitones.iter().map(|&semitone| pitch(semitone, fifths)).collect();
+        let pitches = match pitches.as_slice() {
+            [pitch] => pitch.clone(),
+            pitches => format!("<{}>", pitches.join(" ")),
+        };
+        push_tokens(&mut tokens, chord.start, chord.length, Some(&pitches), bar, division);
+        time = chord.start + chord.length;
+    }
+    push_tokens(&mut tokens, time, bars * bar - time, None, bar, division);
+    tokens.iter().map(|bar| bar.join(" ")).collect()
+}
+
+// Splits a note or rest at the bar lines and into lengths that can be written down,
+// tying notes across them
+fn push_tokens(tokens: &mut [Vec<String>], start: u64, length: u64, pitches: Option<&str>, bar: u64, division: u64) {
+    let mut pieces = Vec::new();
+    let mut time = start;
+    while time < start + length {
+        let bar_end = (time / bar + 1) * bar;
+        let end = bar_end.min(start + length);
+        for value in note_values(end - time, division) {
+            pieces.push((time / bar, value));
+        }
+        time = end;
+    }
+
+    let count = pieces.len();
+    for (index, (bar, value)) in pieces.into_iter().enumerate() {
+        let token = match pitches {
+            Some(pitches) if index + 1 < count => format!("{}{}~", pitches, value),
+            Some(pitches) => format!("{}{}", pitches, value),
+            None => format!("r{}", value),
+        };
+        tokens[bar as usize].push(token);
+    }
+}
+
+// Durations such as "4" or "8." adding up to the given steps of a 1/division grid
+fn note_values(mut steps: u64, division: u64) -> Vec<String> {
+    let mut values: Vec<(u64, String)> = Vec::new();
+    let mut value = 1;
+    while value <= division {
+        let length = division / value;
+        values.push((length, value.to_string()));
+        if length.is_multiple_of(2) {
+            values.push((length * 3 / 2, format!("{}.", value)));
+        }
+        value *= 2;
+    }
+    values.sort_by(|(first, _), (second, _)| second.cmp(first));
+
+    let mut result = Vec::new();
+    for (length, value) in values {
+        while steps >= length {
+            steps -= length;
+            result.push(value.clone());
+        }
+    }
+    result
+}
+
+// Spells a pitch class the way the key signature does, using sharps for the notes outside
+// of sharp keys and flats otherwise
+fn spelling(pitch_class: i16, fifths: i16) -> (usize, i16) {
+    let key_alter = |letter: usize| {
+        let order = SHARPS.iter().position(|&sharp| sharp == LETTERS[letter]).unwrap() as i16;
+        if fifths > order {
+            1
+        } else if fifths < order - 6 {
+            -1
+        } else {
+            0
+        }
+    };
+    let in_key = |pitch_class: i16| (0..LETTERS.len())
+        .find(|&letter| (NATURALS[letter] + key_alter(letter)).rem_euclid(12) == pitch_class.rem_euclid(12));
+
+    match in_key(pitch_class) {
+        Some(letter) => (letter, key_alter(letter)),
+        None if fifths >= 0 => in_key(pitch_class - 1).map(|letter| (letter, key_alter(letter) + 1)).unwrap(),
+        None => in_key(pitch_class + 1).map(|letter| (letter, key_alter(letter) - 1)).unwrap(),
+    }
+}
+
+fn pitch_name(pitch_class: i16, fifths: i16) -> String {
+    let (letter, alter) = spelling(pitch_class, fifths);
+    let letter = LETTERS[letter];
+    let accidental = match alter {
+        alter if alter > 0 => "is".repeat(alter as usize),
+        // LilyPond writes E flat and A flat as "es" and "as"
+        alter if alter < 0 && (letter == 'e' || letter == 'a') => format!("s{}", "es".repeat(-alter as usize - 1)),
+        alter => "es".repeat(-alter as usize),
+    };
+    format!("{}{}", letter, accidental)
+}
+
+// LilyPond's c is the octave below middle C, which is c2 in sound file numbering
+fn pitch(semitone: i16, fifths: i16) -> String {
+    let (_, alter) = spelling(semitone.rem_euclid(12), fifths);
+    let octave = (semitone - alter).div_euclid(12) - 2;
+    let marks = if octave >= 0 { "'".repeat(octave as usize) } else { ",".repeat(-octave as usize) };
+    format!("{}{}", pitch_name(semitone.rem_euclid(12), fifths), marks)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{write_score, pitch, key_signature, note_values, LilyPondOptions, TimeSignature};
+    use crate::game::notes_file::{test_song, Control, FileControl};
+    use std::time::Duration;
+
+    #[test]
+    fn spell_pitches() {
+        assert_eq!(pitch(36, 0), "c'");
+        assert_eq!(pitch(25, 0), "cis");
+        assert_eq!(pitch(25, -2), "des");
+        assert_eq!(pitch(39, -3), "es'");
+        assert_eq!(pitch(-3, 0), "a,,,");
+        // F sharp major spells its seventh as E sharp
+        assert_eq!(pitch(41, 6), "eis'");
+        assert_eq!(key_signature([31, 35, 38, 42].iter().copied()), 1);
+    }
+
+    #[test]
+    fn split_note_values() {
+        assert_eq!(note_values(16, 16), ["1"]);
+        assert_eq!(note_values(7, 16), ["4.", "16"]);
+    }
+
+    #[test]
+    fn write_staves() {
+        // At 120 bpm a sixteenth note lasts 125ms
+        let score = write_score(&test_song(&[(0, "c3", 500), (0, "c2", 2000), (500, "e3", 0), (0, "g3", 0), (1750, "d3", 750)]), "Test", LilyPondOptions::default()).unwrap();
+        assert!(score.contains("title = \"Test\""));
+        assert!(score.contains("\\clef treble \\key c \\major \\time 4/4 \\tempo 4 = 120\n      c'4 <e' g'>2.~ |\n      <e' g'>8 d'4. r2\n"));
+        assert!(score.contains("\\clef bass \\key c \\major \\time 4/4\n      c1 |\n      r1\n"));
+
+        let options = LilyPondOptions { time_signature: TimeSignature { beats: 3, beat_unit: 4 }, ..LilyPondOptions::default() };
+        let score = write_score(&test_song(&[(0, "c3", 2000)]), "", options).unwrap();
+        assert!(score.contains("c'2.~ |\n      c'4 r2\n"));
+    }
+
+    #[test]
+    fn write_song_tempo() {
+        let mut song = test_song(&[(0, "c3", 1000)]);
+        song.controls = vec![FileControl { time: Duration::from_millis(0), control: Control::Tempo { bpm: 60.0, ramp: false } }];
+        let score = write_score(&song, "", LilyPondOptions::default()).unwrap();
+        assert!(score.contains("\\tempo 4 = 60\n      c'4 r2.\n"));
+        let score = write_score(&song, "", LilyPondOptions { bpm: Some(120.0), ..LilyPondOptions::default() }).unwrap();
+        assert!(score.contains("\\tempo 4 = 120\n      c'2 r2\n"));
+    }
+}
@@ -0,0 +1,385 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use crate::game::{self, notes};
+use crate::game::notes_file::{self, FileMarker, FileNote, FileTrack, MarkerKind, Song};
+
+// MIDI numbers notes from C-1, so middle C is 60 rather than c3
+const MIDI_OFFSET: i16 = 24;
+const PERCUSSION_CHANNEL: u8 = 9;
+// Written files tick once per millisecond, with each quarter note lasting a second
+const TICKS_PER_QUARTER: u16 = 1000;
+const MICROSECONDS_PER_QUARTER: u32 = 1_000_000;
+// Notes played till the end of their sound are held for this long
+const OPEN_NOTE_LENGTH: Duration = Duration::from_millis(1000);
+const VELOCITY: u8 = 100;
+
+// A note placed in ticks from the start of the file
+struct TrackNote {
+    start: u64,
+    end: u64,
+    key: u8,
+}
+
+#[derive(Default)]
+struct MidiTrack {
+    name: Option<String>,
+    notes: Vec<TrackNote>,
+}
+
+struct ByteReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let bytes = self.data.get(self.position..self.position + count)
+            .ok_or_else(|| String::from("Unexpected end of file"))?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn number(&mut self, count: usize) -> Result<u32, String> {
+        Ok(self.bytes(count)?.iter().fold(0, |number, &byte| number << 8 | byte as u32))
+    }
+
+    // Variable length quantities keep 7 bits in each byte, with the top bit set on all but the last
+    fn variable(&mut self) -> Result<u32, String> {
+        let mut number = 0;
+        for _ in 0..4 {
+            let byte = self.byte()?;
+            number = number << 7 | (byte & 0x7f) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(number);
+            }
+        }
+        Err(String::from("Invalid variable length number"))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+}
+
+// Reads a Standard MIDI File, with a track for each MIDI track that plays notes.
+// Percussion is skipped since it doesn't map to piano keys.
+pub fn parse_song(data: &[u8]) -> Result<Song, String> {
+    let mut reader = ByteReader { data, position: 0 };
+    if reader.bytes(4).ok() != Some(b"MThd".as_ref()) {
+        return Err(String::from("Not a MIDI file"));
+    }
+    let header_length = reader.number(4)? as usize;
+    let header = reader.bytes(header_length)?;
+    if header.len() < 6 {
+        return Err(String::from("Invalid MIDI header"));
+    }
+    let division = (header[4] as u16) << 8 | header[5] as u16;
+    if division & 0x8000 != 0 {
+        return Err(String::from("MIDI files timed in SMPTE frames are not supported"));
+    }
+
+    let mut midi_tracks = Vec::new();
+    // Tempo changes in microseconds per quarter note, placed in ticks
+    let mut tempos = Vec::new();
+    // Text events from every track, placed in ticks
+    let mut markers = Vec::new();
+    while !reader.is_empty() {
+        let id = reader.bytes(4)?;
+        let length = reader.number(4)? as usize;
+        let chunk = reader.bytes(length)?;
+        if id == b"MTrk" {
+            let number = midi_tracks.len() + 1;
+            midi_tracks.push(read_track(chunk, &mut tempos, &mut markers)
+                .map_err(|e| format!("Track {}: {}", number, e))?);
+        }
+    }
+    tempos.sort_by_key(|&(tick, _)| tick);
+
+    let mut tracks = Vec::new();
+    let mut timed_notes: Vec<(Duration, FileNote)> = Vec::new();
+    for midi_track in midi_tracks.into_iter().filter(|midi_track| !midi_track.notes.is_empty()) {
+        let number = tracks.len() + 1;
+        for note in midi_track.notes {
+            let semitone = note.key as i16 - MIDI_OFFSET;
+            let start = time_at(&tempos, division, note.start);
+            let end = time_at(&tempos, division, note.end);
+            timed_notes.push((start, FileNote {
+                delay: Duration::from_millis(0),
+                base_note: notes::semitone_to_sound(semitone)
+                    .ok_or_else(|| format!("Track {}: note {} is outside of the piano", number, note.key))?,
+                duration: end - start,
+                track: Some(number),
+            }));
+        }
+        tracks.push(FileTrack {
+            name: midi_track.name.unwrap_or_else(|| format!("Track {}", number)),
+            color: game::player_color(number - 1),
+            address: None,
+        });
+    }
+
+    timed_notes.sort_by_key(|&(start, _)| start);
+    let (start_times, mut notes): (Vec<Duration>, Vec<FileNote>) = timed_notes.into_iter().unzip();
+    notes_file::set_start_times(&mut notes, &start_times);
+
+    let mut markers: Vec<FileMarker> = markers.into_iter()
+        .map(|(tick, kind, text)| FileMarker {
+            time: time_at(&tempos, division, tick),
+            kind,
+            text,
+        })
+        .collect();
+    markers.sort_by_key(|marker| marker.time);
+
+    Ok(Song {
+        tracks,
+        notes,
+        markers,
+        ..Song::default()
+    })
+}
+
+fn read_track(data: &[u8], tempos: &mut Vec<(u64, u32)>, markers: &mut Vec<(u64, MarkerKind, String)>) -> Result<MidiTrack, String> {
+    let mut reader = ByteReader { data, position: 0 };
+    let mut track = MidiTrack::default();
+    let mut tick = 0;
+    let mut running_status = None;
+    // Start ticks of the notes being held, by channel and key
+    let mut held: HashMap<(u8, u8), Vec<u64>> = HashMap::new();
+
+    while !reader.is_empty() {
+        tick += reader.variable()? as u64;
+        let mut status = reader.byte()?;
+        match status {
+            0xff => {
+                let kind = reader.byte()?;
+                let length = reader.variable()? as usize;
+                let data = reader.bytes(length)?;
+                match kind {
+                    0x03 if track.name.is_none() => track.name = Some(String::from_utf8_lossy(data).into_owned()),
+                    0x01 => markers.push((tick, MarkerKind::Annotation, String::from_utf8_lossy(data).into_owned())),
+                    0x05 => markers.push((tick, MarkerKind::Lyric, String::from_utf8_lossy(data).into_owned())),
+                    0x06 => markers.push((tick, MarkerKind::Section, String::from_utf8_lossy(data).into_owned())),
+                    0x51 if length == 3 => tempos.push((tick, data.iter().fold(0, |tempo, &byte| tempo << 8 | byte as u32))),
+                    0x2f => break,
+                    _ => {}
+                }
+                continue;
+            }
+            0xf0 | 0xf7 => {
+                let length = reader.variable()? as usize;
+                reader.bytes(length)?;
+                continue;
+            }
+            // Data bytes repeat the last status
+            0x00..=0x7f => {
+                reader.position -= 1;
+                status = running_status.ok_or_else(|| String::from("Missing status byte"))?;
+            }
+            _ => running_status = Some(status),
+        }
+
+        let channel = status & 0x0f;
+        let kind = status & 0xf0;
+        let data = match kind {
+            0xc0 | 0xd0 => reader.bytes(1)?,
+            _ => reader.bytes(2)?,
+        };
+        if channel == PERCUSSION_CHANNEL || (kind != 0x80 && kind != 0x90) {
+            continue;
+        }
+        let (key, velocity) = (data[0], data[1]);
+        let starts = held.entry((channel, key)).or_default();
+        if kind == 0x90 && velocity > 0 {
+            starts.push(tick);
+        } else if !starts.is_empty() {
+            let start = starts.remove(0);
+            track.notes.push(TrackNote { start, end: tick, key });
+        }
+    }
+
+    // Notes still held at the end of the track stop with it
+    for ((_, key), starts) in held {
+        for start in starts {
+            track.notes.push(TrackNote { start, end: tick, key });
+        }
+    }
+    track.notes.sort_by_key(|note| note.start);
+    Ok(track)
+}
+
+// Without any tempo, files are played at 120 quarter notes per minute
+fn time_at(tempos: &[(u64, u32)], division: u16, tick: u64) -> Duration {
+    let mut microseconds = 0;
+    let mut previous = (0, 500_000);
+    for &(time, tempo) in tempos.iter().take_while(|&&(time, _)| time < tick) {
+        microseconds += (time - previous.0) * previous.1 as u64;
+        previous = (time, tempo);
+    }
+    microseconds += (tick - previous.0) * previous.1 as u64;
+    Duration::from_millis((microseconds as f64 / division.max(1) as f64 / 1000.0).round() as u64)
+}
+
+// Writes the song as a Standard MIDI File, with a MIDI track and channel for each track
+pub fn write_song(song: &Song) -> Result<Vec<u8>, String> {
+    let mut track_notes: BTreeMap<Option<usize>, Vec<TrackNote>> = BTreeMap::new();
+    for (time, note) in notes_file::start_times(&song.notes).into_iter().zip(&song.notes) {
+        let key = notes::sound_to_semitone(&note.base_note)
+            .map(|semitone| semitone + MIDI_OFFSET)
+            .filter(|key| (0..=127).contains(key))
+            .ok_or_else(|| format!("Unknown note \"{}\"", note.base_note))?;
+        let duration = if note.duration > Duration::from_millis(0) { note.duration } else { OPEN_NOTE_LENGTH };
+        track_notes.entry(note.track).or_default().push(TrackNote {
+            start: time.as_millis() as u64,
+            end: (time + duration).as_millis() as u64,
+            key: key as u8,
+        });
+    }
+
+    let mut data = Vec::new();
+    data.extend_from_slice(b"MThd");
+    data.extend_from_slice(&6u32.to_be_bytes());
+    data.extend_from_slice(&1u16.to_be_bytes());
+    data.extend_from_slice(&(track_notes.len() as u16 + 1).to_be_bytes());
+    data.extend_from_slice(&TICKS_PER_QUARTER.to_be_bytes());
+
+    // Markers go along with the tempo in the first track
+    let tempo = MICROSECONDS_PER_QUARTER.to_be_bytes();
+    let mut events = vec![(0, vec![0xff, 0x51, 0x03, tempo[1], tempo[2], tempo[3]])];
+    for marker in &song.markers {
+        let kind = match marker.kind {
+            MarkerKind::Annotation => 0x01,
+            MarkerKind::Lyric => 0x05,
+            MarkerKind::Section => 0x06,
+        };
+        events.push((marker.time.as_millis() as u64, meta_event(kind, marker.text.as_bytes())));
+    }
+    events.sort_by_key(|&(tick, _)| tick);
+    push_track(&mut data, events);
+
+    for (index, (track, notes)) in track_notes.into_iter().enumerate() {
+        // Leave the percussion channel alone
+        let channel = (if index >= PERCUSSION_CHANNEL as usize { index + 1 } else { index } % 16) as u8;
+        let mut events = Vec::new();
+        if let Some(track) = track.and_then(|number| song.tracks.get(number - 1)) {
+            events.push((0, meta_event(0x03, track.name.as_bytes())));
+        }
+        for note in notes {
+            events.push((note.start, vec![0x90 | channel, note.key, VELOCITY]));
+            events.push((note.end, vec![0x80 | channel, note.key, 0]));
+        }
+        // Notes are released before the ones starting at the same tick are pressed
+        events.sort_by_key(|(tick, event)| (*tick, event[0] & 0xf0 != 0x80));
+        push_track(&mut data, events);
+    }
+    Ok(data)
+}
+
+fn meta_event(kind: u8, data: &[u8]) -> Vec<u8> {
+    let mut event = vec![0xff, kind];
+    push_variable(&mut event, data.len() as u32);
+    event.extend_from_slice(data);
+    event
+}
+
+fn push_track(data: &mut Vec<u8>, events: Vec<(u64, Vec<u8>)>) {
+    let mut track = Vec::new();
+    let mut previous_tick = 0;
+    for (tick, event) in events {
+        push_variable(&mut track, (tick - previous_tick) as u32);
+        track.extend(event);
+        previous_tick = tick;
+    }
+    track.extend_from_slice(&[0x00, 0xff, 0x2f, 0x00]);
+
+    data.extend_from_slice(b"MTrk");
+    data.extend_from_slice(&(track.len() as u32).to_be_bytes());
+    data.extend(track);
+}
+
+fn push_variable(data: &mut Vec<u8>, number: u32) {
+    let mut bytes = vec![(number & 0x7f) as u8];
+    let mut rest = number >> 7;
+    while rest > 0 {
+        bytes.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    data.extend(bytes.iter().rev());
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_song, write_song, push_variable, ByteReader, Duration};
+    use crate::game::notes_file::{FileMarker, FileNote, FileTrack, MarkerKind, Song};
+    use crossterm_style::Color;
+
+    fn note(delay: u64, base_note: &str, duration: u64, track: Option<usize>) -> FileNote {
+        FileNote {
+            delay: Duration::from_millis(delay),
+            base_note: base_note.to_string(),
+            duration: Duration::from_millis(duration),
+            track,
+        }
+    }
+
+    #[test]
+    fn variable_length_numbers() {
+        for &number in &[0, 0x7f, 0x80, 0x2000, 0x0fff_ffff] {
+            let mut data = Vec::new();
+            push_variable(&mut data, number);
+            assert_eq!(ByteReader { data: &data, position: 0 }.variable(), Ok(number));
+        }
+    }
+
+    #[test]
+    fn write_and_read_midi() {
+        let song = Song {
+            tracks: vec![
+                FileTrack { name: String::from("Right hand"), color: Color::Red, address: None },
+                FileTrack { name: String::from("Left hand"), color: Color::Blue, address: None },
+            ],
+            notes: vec![
+                note(0, "c3", 500, Some(1)),
+                note(0, "c2", 1000, Some(2)),
+                note(500, "e3", 0, Some(1)),
+            ],
+            markers: vec![
+                FileMarker { time: Duration::from_millis(0), kind: MarkerKind::Section, text: String::from("Intro") },
+                FileMarker { time: Duration::from_millis(500), kind: MarkerKind::Lyric, text: String::from("la") },
+            ],
+            ..Song::default()
+        };
+        let markers = song.markers.clone();
+        let song = parse_song(&write_song(&song).unwrap()).unwrap();
+        let names: Vec<&str> = song.tracks.iter().map(|track| track.name.as_str()).collect();
+        assert_eq!(names, ["Right hand", "Left hand"]);
+        assert_eq!(song.notes, vec![
+            note(0, "c3", 500, Some(1)),
+            note(0, "c2", 1000, Some(2)),
+            note(500, "e3", 1000, Some(1)),
+        ]);
+        assert_eq!(song.markers, markers);
+    }
+
+    #[test]
+    fn read_running_status_and_tempo() {
+        let mut data = b"MThd\0\0\0\x06\0\0\0\x01\0\x60MTrk".to_vec();
+        let track = [
+            0x00, 0xff, 0x51, 0x03, 0x0f, 0x42, 0x40,
+            0x00, 0x90, 0x3c, 0x40,
+            0x60, 0x3c, 0x00,
+            0x00, 0x99, 0x24, 0x40,
+            0x00, 0xff, 0x2f, 0x00,
+        ];
+        data.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        data.extend_from_slice(&track);
+
+        let song = parse_song(&data).unwrap();
+        assert_eq!(song.notes, vec![note(0, "c3", 1000, Some(1))]);
+        assert!(parse_song(b"MThd").is_err());
+    }
+}
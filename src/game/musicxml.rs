@@ -0,0 +1,375 @@
+use std::time::Duration;
+use roxmltree::{Document, Node};
+use crate::game::{self, notes};
+use crate::game::notes_file::{self, FileNote, FileTrack, Song};
+
+// A note placed in quarter notes from the start of the score
+#[derive(Debug)]
+struct ScoreNote {
+    start: f64,
+    length: f64,
+    semitone: i16,
+    track: usize,
+}
+
+// Reads a partwise MusicXML score, with one track for each part. Repeats are played
+// as written, and grace notes are skipped since they don't take up any time.
+pub fn parse_song(content: &str) -> Result<Song, String> {
+    let document = Document::parse(content).map_err(|e| format!("Invalid MusicXML: {}", e))?;
+    let score = document.root_element();
+    if score.has_tag_name("score-timewise") {
+        return Err(String::from("Timewise MusicXML scores are not supported"));
+    }
+    if !score.has_tag_name("score-partwise") {
+        return Err(format!("Expected a MusicXML score, found <{}>", score.tag_name().name()));
+    }
+
+    let part_names: Vec<(&str, &str)> = children(score, "part-list")
+        .flat_map(|part_list| children(part_list, "score-part"))
+        .map(|score_part| (
+            score_part.attribute("id").unwrap_or_default(),
+            child_text(score_part, "part-name").unwrap_or_default(),
+        ))
+        .collect();
+
+    let mut tracks = Vec::new();
+    let mut score_notes = Vec::new();
+    // Tempo changes in quarter notes per minute, placed in quarter notes
+    let mut tempos = Vec::new();
+    for (index, part) in children(score, "part").enumerate() {
+        let id = part.attribute("id").unwrap_or_default();
+        let name = part_names.iter()
+            .find(|&&(part_id, _)| part_id == id)
+            .map_or(id, |&(_, name)| name);
+        tracks.push(FileTrack {
+            name: name.to_string(),
+            color: game::player_color(index),
+            address: None,
+        });
+        read_part(part, index + 1, &mut score_notes, &mut tempos)
+            .map_err(|e| format!("Part \"{}\": {}", name, e))?;
+    }
+
+    // Stable sort keeps chords in the order they were written
+    score_notes.sort_by(|first, second| first.start.partial_cmp(&second.start).unwrap());
+    tempos.sort_by(|first: &(f64, f64), second| first.0.partial_cmp(&second.0).unwrap());
+
+    let timed_notes: Vec<(Duration, FileNote)> = score_notes
+        .iter()
+        .map(|note| {
+            let start = to_millis(seconds_at(&tempos, note.start));
+            let end = to_millis(seconds_at(&tempos, note.start + note.length));
+            (start, FileNote {
+                delay: Duration::from_millis(0),
+                base_note: notes::semitone_to_sound(note.semitone).unwrap(),
+                duration: end - start,
+                track: Some(note.track),
+            })
+        })
+        .collect();
+    let (start_times, mut notes): (Vec<Duration>, Vec<FileNote>) = timed_notes.into_iter().unzip();
+    notes_file::set_start_times(&mut notes, &start_times);
+
+    Ok(Song {
+        tracks,
+        notes,
+        ..Song::default()
+    })
+}
+
+fn read_part(part: Node, track: usize, score_notes: &mut Vec<ScoreNote>, tempos: &mut Vec<(f64, f64)>) -> Result<(), String> {
+    let mut divisions = None;
+    let mut time = 0.0;
+    let mut last_start = 0.0;
+    // Notes waiting for the rest of their tie, by semitone
+    let mut open_ties: Vec<(i16, usize)> = Vec::new();
+
+    for measure in children(part, "measure") {
+        let number = measure.attribute("number").unwrap_or_default();
+        let in_measure = |e: String| format!("measure {}: {}", number, e);
+
+        for element in measure.children().filter(|node| node.is_element()) {
+            match element.tag_name().name() {
+                "attributes" => {
+                    if let Some(value) = child_text(element, "divisions") {
+                        divisions = Some(value.parse::<f64>()
+                            .ok()
+                            .filter(|&divisions| divisions > 0.0 && divisions.is_finite())
+                            .ok_or_else(|| in_measure(format!("Invalid divisions \"{}\"", value)))?);
+                    }
+                }
+                "direction" | "sound" => {
+                    if let Some(tempo) = read_tempo(element) {
+                        tempos.push((time, tempo));
+                    }
+                }
+                "backup" | "forward" => {
+                    let length = read_duration(element, divisions).map_err(in_measure)?;
+                    if element.has_tag_name("backup") {
+                        time -= length;
+                    } else {
+                        time += length;
+                    }
+                }
+                "note" => {
+                    if children(element, "grace").next().is_some() || children(element, "cue").next().is_some() {
+                        continue;
+                    }
+                    let length = read_duration(element, divisions).map_err(in_measure)?;
+                    // Chord notes start along with the note before them
+                    let start = if children(element, "chord").next().is_some() {
+                        last_start
+                    } else {
+                        let start = time;
+                        time += length;
+                        start
+                    };
+                    last_start = start;
+
+                    if children(element, "rest").next().is_some() {
+                        continue;
+                    }
+                    if children(element, "unpitched").next().is_some() {
+                        return Err(in_measure(String::from("Unpitched notes are not supported")));
+                    }
+                    let semitone = match children(element, "pitch").next() {
+                        Some(pitch) => read_pitch(pitch).map_err(in_measure)?,
+                        None => return Err(in_measure(String::from("Note without a <pitch>"))),
+                    };
+
+                    let tie_types: Vec<&str> = children(element, "tie")
+                        .filter_map(|tie| tie.attribute("type"))
+                        .collect();
+                    let tied_from = open_ties.iter()
+                        .position(|&(open, _)| open == semitone)
+                        .filter(|_| tie_types.contains(&"stop"))
+                        .map(|position| open_ties.remove(position).1);
+                    let index = match tied_from {
+                        Some(index) => {
+                            let note = &mut score_notes[index];
+                            note.length = start + length - note.start;
+                            index
+                        }
+                        None => {
+                            score_notes.push(ScoreNote {
+                                start,
+                                length,
+                                semitone,
+                                track,
+                            });
+                            score_notes.len() - 1
+                        }
+                    };
+                    if tie_types.contains(&"start") {
+                        open_ties.push((semitone, index));
+                    }
+                }
+                _ => { },
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_duration(element: Node, divisions: Option<f64>) -> Result<f64, String> {
+    let value = match child_text(element, "duration") {
+        Some(value) => value,
+        None => return Err(format!("<{}> without a <duration>", element.tag_name().name())),
+    };
+    let duration: f64 = value.parse()
+        .ok()
+        .filter(|&duration: &f64| duration >= 0.0 && duration.is_finite())
+        .ok_or_else(|| format!("Invalid duration \"{}\"", value))?;
+    let divisions = divisions.ok_or_else(|| String::from("<duration> used before <divisions> was set"))?;
+    Ok(duration / divisions)
+}
+
+fn read_pitch(pitch: Node) -> Result<i16, String> {
+    let step = child_text(pitch, "step").unwrap_or_default();
+    let octave = child_text(pitch, "octave").unwrap_or_default();
+    let alter: f64 = child_text(pitch, "alter").unwrap_or("0").parse()
+        .map_err(|_| String::from("Invalid <alter>"))?;
+    if alter.fract() != 0.0 {
+        return Err(String::from("Microtonal alterations are not supported"));
+    }
+
+    let written = format!("{}{}", step, octave);
+    let octave: i16 = octave.parse().map_err(|_| format!("Invalid pitch {}", written))?;
+    let mut step_chars = step.chars();
+    let semitone = match (step_chars.next(), step_chars.next()) {
+        (Some(step), None) => notes::pitch_to_semitone(step, octave),
+        _ => None,
+    };
+    semitone
+        .map(|semitone| semitone + alter as i16)
+        .filter(|&semitone| notes::semitone_to_sound(semitone).is_some())
+        .ok_or_else(|| format!("Note {} is outside the range of the piano", written))
+}
+
+// Tempos are given either for playback with <sound tempo="..."/>, or as a metronome marking
+fn read_tempo(element: Node) -> Option<f64> {
+    let sound_tempo = element.descendants()
+        .filter(|node| node.has_tag_name("sound"))
+        .find_map(|sound| sound.attribute("tempo"))
+        .and_then(|tempo| tempo.parse::<f64>().ok());
+    if sound_tempo.is_some() {
+        return sound_tempo.filter(|&tempo| tempo > 0.0);
+    }
+
+    let metronome = element.descendants().find(|node| node.has_tag_name("metronome"))?;
+    let per_minute: f64 = child_text(metronome, "per-minute")?.parse().ok()?;
+    let mut beat = match child_text(metronome, "beat-unit")? {
+        "whole" => 4.0,
+        "half" => 2.0,
+        "quarter" => 1.0,
+        "eighth" => 0.5,
+        "16th" => 0.25,
+        _ => return None,
+    };
+    if children(metronome, "beat-unit-dot").next().is_some() {
+        beat *= 1.5;
+    }
+    Some(per_minute * beat).filter(|&tempo| tempo > 0.0)
+}
+
+// Without any tempo, scores are played at 120 quarter notes per minute
+fn seconds_at(tempos: &[(f64, f64)], position: f64) -> f64 {
+    let mut seconds = 0.0;
+    let mut previous = (0.0, 120.0);
+    for &(time, tempo) in tempos.iter().take_while(|&&(time, _)| time < position) {
+        seconds += (time - previous.0).max(0.0) * 60.0 / previous.1;
+        previous = (time.max(previous.0), tempo);
+    }
+    seconds + (position - previous.0).max(0.0) * 60.0 / previous.1
+}
+
+fn children<'a, 'input>(node: Node<'a, 'input>, name: &'a str) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(move |child| child.has_tag_name(name))
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &'a str) -> Option<&'a str> {
+    children(node, name).next().and_then(|child| child.text()).map(|text| text.trim())
+}
+
+fn to_millis(seconds: f64) -> Duration {
+    Duration::from_millis((seconds * 1000.0).round() as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_song;
+
+    fn score(parts: &[(&str, &str)]) -> String {
+        let part_list: String = parts.iter()
+            .enumerate()
+            .map(|(index, (name, _))| format!(
+                "<score-part id=\"P{}\"><part-name>{}</part-name></score-part>", index + 1, name
+            ))
+            .collect();
+        let part_content: String = parts.iter()
+            .enumerate()
+            .map(|(index, (_, measures))| format!("<part id=\"P{}\">{}</part>", index + 1, measures))
+            .collect();
+        format!(
+            "<?xml version=\"1.0\"?><score-partwise version=\"3.1\"><part-list>{}</part-list>{}</score-partwise>",
+            part_list, part_content,
+        )
+    }
+
+    fn note(step: &str, octave: i8, duration: u32, extra: &str) -> String {
+        format!(
+            "<note>{}<pitch><step>{}</step><octave>{}</octave></pitch><duration>{}</duration></note>",
+            extra, step, octave, duration,
+        )
+    }
+
+    // Start times, sounds, durations and tracks of the notes, with times in milliseconds
+    fn timeline(content: &str) -> Vec<(u128, String, u128, usize)> {
+        let song = parse_song(content).unwrap();
+        let mut time = 0;
+        song.notes
+            .iter()
+            .map(|note| {
+                time += note.delay.as_millis();
+                (time, note.base_note.clone(), note.duration.as_millis(), note.track.unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn read_notes_chords_and_rests() {
+        let measure = format!(
+            "<measure number=\"1\"><attributes><divisions>2</divisions></attributes>\
+             <direction><sound tempo=\"60\"/></direction>{}{}<note><rest/><duration>1</duration></note>{}</measure>",
+            note("C", 4, 2, ""),
+            note("E", 4, 2, "<chord/>"),
+            "<note><pitch><step>F</step><alter>1</alter><octave>4</octave></pitch><duration>4</duration></note>",
+        );
+        assert_eq!(timeline(&score(&[("Piano", &measure)])), vec![
+            (0, String::from("c3"), 1000, 1),
+            (0, String::from("e3"), 1000, 1),
+            (1500, String::from("fs3"), 2000, 1),
+        ]);
+    }
+
+    #[test]
+    fn join_tied_notes() {
+        let measures = format!(
+            "<measure number=\"1\"><attributes><divisions>1</divisions></attributes>{}</measure>\
+             <measure number=\"2\">{}{}</measure>",
+            note("G", 4, 2, "<tie type=\"start\"/>"),
+            note("G", 4, 1, "<tie type=\"stop\"/>"),
+            note("G", 4, 1, ""),
+        );
+        assert_eq!(timeline(&score(&[("Piano", &measures)])), vec![
+            (0, String::from("g3"), 1500, 1),
+            (1500, String::from("g3"), 500, 1),
+        ]);
+    }
+
+    #[test]
+    fn read_parts_as_tracks() {
+        let treble = format!(
+            "<measure number=\"1\"><attributes><divisions>1</divisions></attributes>\
+             <direction><direction-type><metronome><beat-unit>half</beat-unit><per-minute>30</per-minute>\
+             </metronome></direction-type></direction>{}{}</measure>",
+            note("A", 4, 1, ""),
+            note("B", 4, 1, ""),
+        );
+        let bass = format!(
+            "<measure number=\"1\"><attributes><divisions>1</divisions></attributes>{}<backup><duration>1</duration></backup>{}</measure>",
+            note("C", 3, 2, ""),
+            note("E", 3, 1, ""),
+        );
+        let content = score(&[("Right hand", &treble), ("Left hand", &bass)]);
+        let song = parse_song(&content).unwrap();
+        assert_eq!(song.tracks.len(), 2);
+        assert_eq!(song.tracks[1].name, "Left hand");
+        assert_eq!(timeline(&content), vec![
+            (0, String::from("a3"), 1000, 1),
+            (0, String::from("c2"), 2000, 2),
+            (1000, String::from("b3"), 1000, 1),
+            (1000, String::from("e2"), 1000, 2),
+        ]);
+    }
+
+    #[test]
+    fn invalid_scores() {
+        assert!(parse_song("<score-partwise>").unwrap_err().starts_with("Invalid MusicXML"));
+        assert!(parse_song("<score-timewise/>").unwrap_err().contains("Timewise"));
+
+        let measure = format!("<measure number=\"3\">{}</measure>", note("C", 4, 1, ""));
+        assert_eq!(
+            parse_song(&score(&[("Piano", &measure)])).unwrap_err(),
+            "Part \"Piano\": measure 3: <duration> used before <divisions> was set",
+        );
+        let measure = format!("<measure number=\"1\"><attributes><divisions>1</divisions></attributes>{}</measure>", note("C", 9, 1, ""));
+        assert!(parse_song(&score(&[("Piano", &measure)])).unwrap_err().contains("C9 is outside"));
+
+        let measure = format!("<measure number=\"1\"><attributes><divisions>1</divisions></attributes>{}</measure>", note("C", 4, 1, ""));
+        for (valid, invalid) in [("<duration>1</duration>", "<duration>-1</duration>"), ("<duration>1</duration>", "<duration>NaN</duration>"), ("<divisions>1</divisions>", "<divisions>inf</divisions>")] {
+            let measure = measure.replace(valid, invalid);
+            assert!(parse_song(&score(&[("Piano", &measure)])).unwrap_err().contains("Invalid"));
+        }
+    }
+}
@@ -91,6 +91,43 @@ impl Note {
     }
 }
 
+const OCTAVE_SOUNDS: [&str; 12] = ["c", "cs", "d", "ds", "e", "f", "fs", "g", "gs", "a", "as", "b"];
+
+// Sound assets range from a-1 to b7
+const LOWEST_SEMITONE: i16 = -3;
+const HIGHEST_SEMITONE: i16 = 95;
+
+// Counts semitones from c0, with the octave number changing at each c
+pub fn sound_to_semitone(sound: &str) -> Option<i16> {
+    match Note::extract_base_sound_and_frequency(sound) {
+        (Ok(base_sound), Ok(frequency)) => OCTAVE_SOUNDS.iter()
+            .position(|&octave_sound| octave_sound == base_sound)
+            .map(|index| frequency as i16 * 12 + index as i16),
+        _ => None,
+    }
+}
+
+pub fn semitone_to_sound(semitone: i16) -> Option<String> {
+    if !(LOWEST_SEMITONE..=HIGHEST_SEMITONE).contains(&semitone) {
+        return None;
+    }
+    let frequency = semitone.div_euclid(12);
+    let base_sound = OCTAVE_SOUNDS[semitone.rem_euclid(12) as usize];
+    Some(format!("{}{}", base_sound, frequency))
+}
+
+pub fn transpose_sound(sound: &str, semitones: i16) -> Option<String> {
+    sound_to_semitone(sound).and_then(|semitone| semitone_to_sound(semitone + semitones))
+}
+
+// Sound files are numbered an octave below scientific pitch notation, so middle C (C4) is c3
+pub fn pitch_to_semitone(step: char, octave: i16) -> Option<i16> {
+    let step = step.to_ascii_lowercase().to_string();
+    OCTAVE_SOUNDS.iter()
+        .position(|&octave_sound| octave_sound == step)
+        .map(|index| (octave - 1) * 12 + index as i16)
+}
+
 pub fn key_to_base_note(mut key: KeyEvent, sequence: i8) -> Option<String> {
     let mut offset: i8 = 0;
 
@@ -164,6 +201,16 @@ pub fn key_to_base_note(mut key: KeyEvent, sequence: i8) -> Option<String> {
     note
 }
 
+// Every key that plays `sound` at the given sequence, trying out all the keys `key_to_base_note` knows of
+pub fn base_note_to_keys(sound: &str, sequence: i8) -> Vec<KeyEvent> {
+    let characters = (' '..='~').map(KeyEvent::Char);
+    let controls = ('a'..='z').map(KeyEvent::Ctrl);
+    characters
+        .chain(controls)
+        .filter(|key| key_to_base_note(key.clone(), sequence).is_some_and(|note| note == sound))
+        .collect()
+}
+
 #[cfg(test)]
 mod test {
     #[test]
@@ -225,6 +272,35 @@ mod test {
         assert!(note.is_err());
     }
 
+    #[test]
+    fn sound_to_semitone() {
+        assert_eq!(super::sound_to_semitone("c0"), Some(0));
+        assert_eq!(super::sound_to_semitone("a-1"), Some(-3));
+        assert_eq!(super::sound_to_semitone("fs2"), Some(30));
+        assert_eq!(super::sound_to_semitone("h2"), None);
+    }
+
+    #[test]
+    fn semitone_to_sound() {
+        assert_eq!(super::semitone_to_sound(-3), Some("a-1".to_string()));
+        assert_eq!(super::semitone_to_sound(30), Some("fs2".to_string()));
+        assert_eq!(super::semitone_to_sound(96), None);
+    }
+
+    #[test]
+    fn transpose_sound() {
+        assert_eq!(super::transpose_sound("b2", 1), Some("c3".to_string()));
+        assert_eq!(super::transpose_sound("c0", -2), Some("as-1".to_string()));
+        assert_eq!(super::transpose_sound("a-1", -1), None);
+    }
+
+    #[test]
+    fn pitch_to_semitone() {
+        assert_eq!(super::pitch_to_semitone('C', 4), super::sound_to_semitone("c3"));
+        assert_eq!(super::pitch_to_semitone('a', 0), Some(-3));
+        assert_eq!(super::pitch_to_semitone('H', 4), None);
+    }
+
     #[test]
     fn key_to_base_note() {
         let base_note = super::key_to_base_note(super::KeyEvent::Char('a'), 2);
@@ -234,6 +310,14 @@ mod test {
         }
     }
 
+    #[test]
+    fn base_note_to_keys() {
+        let keys = super::base_note_to_keys("gs1", 2);
+        assert_eq!(keys, vec![super::KeyEvent::Char('a'), super::KeyEvent::Ctrl('k')]);
+        let keys = super::base_note_to_keys("c4", 2);
+        assert_eq!(keys, vec![super::KeyEvent::Char('?'), super::KeyEvent::Char('E'), super::KeyEvent::Char('p')]);
+    }
+
     #[test]
     fn key_to_base_note_none() {
         let base_note = super::key_to_base_note(super::KeyEvent::Char('~'), 2);
@@ -2,15 +2,139 @@ use std::time::{Instant, Duration};
 use std::path::PathBuf;
 use std::fs::{OpenOptions, File};
 use std::io::Write;
+use std::iter::Peekable;
+use std::net::SocketAddr;
 use yaml_rust::{YamlLoader, Yaml};
-use crate::game::Note;
+use crossterm_style::Color;
+use crate::game::{self, Note};
+use crate::game::quantize::Quantizer;
+use crate::game::tempo::TimeSignature;
 use std::io::{Error, Read};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct FileNote {
     pub delay: Duration,
     pub base_note: String,
     pub duration: Duration,
+    pub track: Option<usize>,
+}
+
+// Settings changed while playing, which affect the notes that follow. Tempo and time signature
+// changes are followed by the playback rather than the keyboard.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Control {
+    Volume(f32),
+    Sequence(i8),
+    SoundDuration(Duration),
+    // A ramp reaches its tempo gradually from the tempo before it
+    Tempo { bpm: f32, ramp: bool },
+    TimeSignature(TimeSignature),
+}
+
+// Unlike notes, controls are kept against the start of the song
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileControl {
+    pub time: Duration,
+    pub control: Control,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MarkerKind {
+    // Names a part of the song, such as "Verse" or "Bar 9"
+    Section,
+    Lyric,
+    Annotation,
+}
+
+// Text shown while the song plays, kept against the start of the song like controls
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileMarker {
+    pub time: Duration,
+    pub kind: MarkerKind,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoteSource {
+    Local,
+    Peer(SocketAddr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileTrack {
+    pub name: String,
+    pub color: Color,
+    pub address: Option<SocketAddr>,
+}
+
+impl FileTrack {
+    pub fn from_source(source: NoteSource, color: Color) -> FileTrack {
+        match source {
+            NoteSource::Local => FileTrack {
+                name: String::from("Local"),
+                color,
+                address: None,
+            },
+            NoteSource::Peer(address) => FileTrack {
+                name: game::player_id(color)
+                    .map_or_else(|| String::from("Peer"), |id| format!("Peer {}", id)),
+                color,
+                address: Some(address),
+            },
+        }
+    }
+}
+
+// Tracks are numbered from 1 in the order they appear in `tracks`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Song {
+    pub tracks: Vec<FileTrack>,
+    pub notes: Vec<FileNote>,
+    pub controls: Vec<FileControl>,
+    pub markers: Vec<FileMarker>,
+}
+
+impl Song {
+    pub fn track(&self, note: &FileNote) -> Option<&FileTrack> {
+        note.track
+            .and_then(|number| number.checked_sub(1))
+            .and_then(|index| self.tracks.get(index))
+    }
+}
+
+// Song for tests, given the delay, note and duration in milliseconds of each of its notes
+#[cfg(test)]
+pub fn test_song(notes: &[(u64, &str, u64)]) -> Song {
+    Song {
+        notes: notes.iter()
+            .map(|&(delay, base_note, duration)| FileNote {
+                delay: Duration::from_millis(delay),
+                base_note: base_note.to_string(),
+                duration: Duration::from_millis(duration),
+                track: None,
+            })
+            .collect(),
+        ..Song::default()
+    }
+}
+
+pub fn start_times(notes: &[FileNote]) -> Vec<Duration> {
+    let mut time = Duration::from_millis(0);
+    notes.iter()
+        .map(|note| {
+            time += note.delay;
+            time
+        })
+        .collect()
+}
+
+// Rewrites each delay so that the notes begin at the given start times
+pub fn set_start_times(notes: &mut [FileNote], start_times: &[Duration]) {
+    let mut previous_time = Duration::from_millis(0);
+    for (note, &time) in notes.iter_mut().zip(start_times) {
+        note.delay = time.checked_sub(previous_time).unwrap_or_default();
+        previous_time = previous_time.max(time);
+    }
 }
 
 #[derive(Debug)]
@@ -23,49 +147,249 @@ impl NoteReader {
         let mut file = File::open(filename).unwrap();
         let mut content = String::new();
         file.read_to_string(&mut content).unwrap();
-        let yaml_content = YamlLoader::load_from_str(&content).unwrap();
+        Self::parse(&content).unwrap()
+    }
+
+    pub fn parse(content: &str) -> Result<NoteReader, String> {
+        let yaml_content = YamlLoader::load_from_str(content).map_err(|e| e.to_string())?;
 
-        NoteReader {
+        Ok(NoteReader {
             yaml: yaml_content,
+        })
+    }
+
+    pub fn parse_song(&self) -> Song {
+        let (notes, controls, markers) = self.parse_entries();
+        Song {
+            tracks: self.parse_tracks(),
+            notes,
+            controls,
+            markers,
+        }
+    }
+
+    pub fn parse_tracks(&self) -> Vec<FileTrack> {
+        let mut counter = 1;
+        let mut tracks: Vec<FileTrack> = Vec::new();
+        while let Ok(v) = self.parse_track_entry(counter) {
+            tracks.push(v);
+            counter += 1;
         }
+        tracks
     }
 
     pub fn parse_notes(&self) -> Vec<FileNote> {
+        self.parse_entries().0
+    }
+
+    pub fn parse_controls(&self) -> Vec<FileControl> {
+        self.parse_entries().1
+    }
+
+    pub fn parse_markers(&self) -> Vec<FileMarker> {
+        self.parse_entries().2
+    }
+
+    // Notes, controls and markers share one numbering, each delay counting from the entry before it
+    fn parse_entries(&self) -> (Vec<FileNote>, Vec<FileControl>, Vec<FileMarker>) {
         let mut counter = 1;
+        let mut time = Duration::from_millis(0);
+        let mut previous_note_time = time;
         let mut file_base_notes: Vec<FileNote> = Vec::new();
-        while let Ok(v) = self.parse_yaml_entry(counter) {
-            file_base_notes.push(v);
+        let mut file_controls: Vec<FileControl> = Vec::new();
+        let mut file_markers: Vec<FileMarker> = Vec::new();
+        loop {
+            if let Ok(mut v) = self.parse_yaml_entry(counter) {
+                time += v.delay;
+                v.delay = time - previous_note_time;
+                previous_note_time = time;
+                file_base_notes.push(v);
+            } else if let Ok((delay, control)) = self.parse_control_entry(counter) {
+                time += delay;
+                file_controls.push(FileControl {
+                    time,
+                    control,
+                });
+            } else if let Ok((delay, kind, text)) = self.parse_marker_entry(counter) {
+                time += delay;
+                file_markers.push(FileMarker {
+                    time,
+                    kind,
+                    text,
+                });
+            } else {
+                break;
+            }
             counter += 1;
         }
-        file_base_notes
+        (file_base_notes, file_controls, file_markers)
+    }
+
+    // Entries are read until the numbering stops, so point out any entry that would stop it early
+    pub fn check(&self) -> Result<(), String> {
+        let document = match self.yaml.first() {
+            Some(Yaml::Hash(document)) => document,
+            Some(Yaml::Null) | None => return Ok(()),
+            Some(_) => return Err(String::from("Song files should be made of note_, control_ and marker_ entries")),
+        };
+        let (file_notes, file_controls, file_markers) = self.parse_entries();
+        // Playback expects every note to be one it can show on the keyboard
+        let is_playable = |file_note: &&FileNote| Note::from(&file_note.base_note, Color::Blue, file_note.duration).is_some();
+        if let Some(file_note) = file_notes.iter().find(|file_note| !is_playable(file_note)) {
+            return Err(format!("Unknown note \"{}\"", file_note.base_note));
+        }
+
+        let next = (file_notes.len() + file_controls.len() + file_markers.len() + 1) as u32;
+        let numbers = document.keys()
+            .filter_map(|key| key.as_str())
+            .filter_map(|key| ["note_", "control_", "marker_"].iter().find_map(|prefix| key.strip_prefix(prefix)))
+            .filter_map(|number| number.parse::<u32>().ok());
+        if numbers.max().is_some_and(|last| last >= next) {
+            // Either the entry is there but can't be read, or it's missing altogether
+            let error = [
+                self.parse_yaml_entry(next).err(),
+                self.parse_control_entry(next).err(),
+                self.parse_marker_entry(next).err(),
+            ]
+                .iter()
+                .flatten()
+                .find(|error| !error.starts_with("Could not parse"))
+                .cloned();
+            return Err(error.unwrap_or_else(|| format!("Entry number {} is missing, so the entries after it can't be read", next)));
+        }
+        Ok(())
     }
 
     pub fn parse_yaml_entry(&self, id: u32) -> Result<FileNote, String> {
         let note_id = format!("note_{}", id);
 
-        let future_base_note = match self.yaml[0][note_id.as_str()] {
+        // Files without any notes don't contain a YAML document at all
+        let entry = match self.yaml.first() {
+            Some(document) => &document[note_id.as_str()],
+            None => return Err(String::from("Could not parse note")),
+        };
+
+        match entry {
             Yaml::Array(ref x) => {
-                let delay = Duration::from_millis(x[0].as_i64().unwrap() as u64);
-                let base_note = x[1].as_str().unwrap();
-                let duration = Duration::from_millis(x[2].as_i64().unwrap() as u64);
-                Ok(FileNote {
-                    delay,
-                    base_note: base_note.to_string(),
-                    duration,
-                })
+                let delay = x.first().and_then(Yaml::as_i64);
+                let base_note = x.get(1).and_then(Yaml::as_str);
+                let duration = x.get(2).and_then(Yaml::as_i64);
+                // Files recorded before tracks existed only have three fields
+                let track = x.get(3)
+                    .and_then(|track| track.as_i64())
+                    .map(|track| track as usize);
+                match (delay, base_note, duration) {
+                    (Some(delay), Some(base_note), Some(duration)) => Ok(FileNote {
+                        delay: Duration::from_millis(delay as u64),
+                        base_note: base_note.to_string(),
+                        duration: Duration::from_millis(duration as u64),
+                        track,
+                    }),
+                    _ => Err(format!("{} should be a delay, a note and a duration", note_id)),
+                }
             },
             _ => Err(String::from("Could not parse note")),
+        }
+    }
+
+    pub fn parse_control_entry(&self, id: u32) -> Result<(Duration, Control), String> {
+        let control_id = format!("control_{}", id);
+
+        let entry = match self.yaml.first() {
+            Some(document) => &document[control_id.as_str()],
+            None => return Err(String::from("Could not parse control")),
         };
 
-        future_base_note
+        match entry {
+            Yaml::Array(ref x) if x.len() >= 3 => {
+                let delay = x[0].as_i64().map(|delay| Duration::from_millis(delay as u64));
+                let control = match x[1].as_str() {
+                    Some("volume") => x[2].as_f64()
+                        .or_else(|| x[2].as_i64().map(|volume| volume as f64))
+                        .map(|volume| Control::Volume(volume as f32)),
+                    Some("sequence") => x[2].as_i64()
+                        .map(|sequence| Control::Sequence(sequence as i8)),
+                    Some("sound_duration") => x[2].as_i64()
+                        .map(|duration| Control::SoundDuration(Duration::from_millis(duration as u64))),
+                    Some(name @ "tempo") | Some(name @ "tempo_ramp") => x[2].as_f64()
+                        .or_else(|| x[2].as_i64().map(|bpm| bpm as f64))
+                        .map(|bpm| Control::Tempo { bpm: bpm as f32, ramp: name == "tempo_ramp" }),
+                    Some("time_signature") => x[2].as_str()
+                        .and_then(|time| time.parse().ok())
+                        .map(Control::TimeSignature),
+                    _ => None,
+                };
+                delay.zip(control)
+                    .ok_or_else(|| format!("{} should be a delay, a setting and its value", control_id))
+            },
+            _ => Err(String::from("Could not parse control")),
+        }
+    }
+
+    pub fn parse_marker_entry(&self, id: u32) -> Result<(Duration, MarkerKind, String), String> {
+        let marker_id = format!("marker_{}", id);
+
+        let entry = match self.yaml.first() {
+            Some(document) => &document[marker_id.as_str()],
+            None => return Err(String::from("Could not parse marker")),
+        };
+
+        match entry {
+            Yaml::Array(ref x) if x.len() >= 3 => {
+                let delay = x[0].as_i64().map(|delay| Duration::from_millis(delay as u64));
+                let kind = match x[1].as_str() {
+                    Some("section") => Some(MarkerKind::Section),
+                    Some("lyric") => Some(MarkerKind::Lyric),
+                    Some("annotation") => Some(MarkerKind::Annotation),
+                    _ => None,
+                };
+                // Plain numbers such as bar numbers are read as text too
+                let text = match &x[2] {
+                    Yaml::String(text) => Some(text.clone()),
+                    Yaml::Integer(number) => Some(number.to_string()),
+                    Yaml::Real(number) => Some(number.clone()),
+                    _ => None,
+                };
+                match (delay, kind, text) {
+                    (Some(delay), Some(kind), Some(text)) => Ok((delay, kind, text)),
+                    _ => Err(format!("{} should be a delay, section, lyric or annotation and its text", marker_id)),
+                }
+            },
+            _ => Err(String::from("Could not parse marker")),
+        }
+    }
+
+    pub fn parse_track_entry(&self, id: u32) -> Result<FileTrack, String> {
+        let track_id = format!("track_{}", id);
+
+        let entry = match self.yaml.first() {
+            Some(document) => &document[track_id.as_str()],
+            None => return Err(String::from("Could not parse track")),
+        };
+
+        match entry {
+            Yaml::Hash(_) => Ok(FileTrack {
+                name: entry["name"].as_str().unwrap_or_default().to_string(),
+                color: entry["color"].as_str()
+                    .and_then(|color| color.parse().ok())
+                    .unwrap_or(Color::Blue),
+                address: entry["address"].as_str()
+                    .and_then(|address| address.parse().ok()),
+            }),
+            _ => Err(String::from("Could not parse track")),
+        }
     }
 }
 
 #[derive(Debug)]
 pub struct NoteRecorder {
     pub record_file: Option<File>,
-    note_number: usize,
+    entry_number: usize,
     previous_note_time: Instant,
+    start_time: Instant,
+    previous_note_position: Duration,
+    quantizer: Option<Quantizer>,
+    track_sources: Vec<NoteSource>,
 }
 
 impl NoteRecorder {
@@ -76,35 +400,217 @@ impl NoteRecorder {
     pub fn from(filename: PathBuf) -> NoteRecorder {
         NoteRecorder {
             record_file: Self::open_file(filename).ok(),
-            note_number: 1,
+            entry_number: 1,
             previous_note_time: Instant::now(),
+            start_time: Instant::now(),
+            previous_note_position: Duration::from_millis(0),
+            quantizer: None,
+            track_sources: Vec::new(),
         }
     }
 
+    pub fn create(filename: PathBuf) -> Result<NoteRecorder, Error> {
+        let record_file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(filename)?;
+
+        Ok(NoteRecorder {
+            record_file: Some(record_file),
+            ..Self::new()
+        })
+    }
+
     pub fn set_file(&mut self, filename: PathBuf) {
         self.record_file = Self::open_file(filename).ok();
     }
 
-    pub fn write_note(&mut self, note: Note) {
-        let delay_gap = self.previous_note_time
-            .elapsed()
-            .as_millis();
-        let current_time = Instant::now();
-        self.set_previous_note_time(current_time);
+    pub fn set_quantizer(&mut self, quantizer: Quantizer) {
+        self.quantizer = Some(quantizer);
+    }
 
-        let note_details = format!(
+    pub fn write_note(&mut self, note: Note, source: NoteSource) {
+        let track = match self.track_sources.iter().position(|&track_source| track_source == source) {
+            Some(index) => index + 1,
+            None => {
+                self.track_sources.push(source);
+                let track = FileTrack::from_source(source, note.color);
+                self.write_track(self.track_sources.len(), &track);
+                self.track_sources.len()
+            }
+        };
+
+        let delay_gap = self.next_delay();
+        let duration = match self.quantizer {
+            Some(quantizer) => quantizer.quantize_duration(note.duration),
+            None => note.duration,
+        };
+
+        self.write_entry(delay_gap, &note.sound, duration, Some(track));
+    }
+
+    pub fn write_control(&mut self, control: Control) {
+        let delay_gap = self.next_delay();
+        self.write_control_entry(delay_gap, control);
+    }
+
+    pub fn write_song(&mut self, song: &Song) {
+        for (index, track) in song.tracks.iter().enumerate() {
+            self.write_track(index + 1, track);
+        }
+
+        let mut file_controls = song.controls.clone();
+        file_controls.sort_by_key(|file_control| file_control.time);
+        let mut file_controls = file_controls.into_iter().peekable();
+        let mut file_markers = song.markers.clone();
+        file_markers.sort_by_key(|file_marker| file_marker.time);
+        let mut file_markers = file_markers.into_iter().peekable();
+        let mut previous_time = Duration::from_millis(0);
+
+        // Controls and markers are written before notes starting at the same time, so that they apply to them
+        for (file_note, time) in song.notes.iter().zip(start_times(&song.notes)) {
+            self.write_timed_entries(&mut file_controls, &mut file_markers, Some(time), &mut previous_time);
+            self.write_entry(time - previous_time, &file_note.base_note, file_note.duration, file_note.track);
+            previous_time = time;
+        }
+        self.write_timed_entries(&mut file_controls, &mut file_markers, None, &mut previous_time);
+    }
+
+    // Writes the controls and markers up to `until` in the order they happen
+    fn write_timed_entries<C, M>(&mut self, controls: &mut Peekable<C>, markers: &mut Peekable<M>, until: Option<Duration>, previous_time: &mut Duration)
+    where
+        C: Iterator<Item = FileControl>,
+        M: Iterator<Item = FileMarker>,
+    {
+        let is_due = |time: Duration| until.is_none_or(|until| time <= until);
+        loop {
+            let control_time = controls.peek().map(|file_control| file_control.time).filter(|&time| is_due(time));
+            let marker_time = markers.peek().map(|file_marker| file_marker.time).filter(|&time| is_due(time));
+            match (control_time, marker_time) {
+                (Some(control_time), marker_time) if marker_time.is_none_or(|marker_time| control_time <= marker_time) => {
+                    let file_control = controls.next().unwrap();
+                    self.write_control_entry(file_control.time - *previous_time, file_control.control);
+                    *previous_time = file_control.time;
+                }
+                (_, Some(_)) => {
+                    let file_marker = markers.next().unwrap();
+                    self.write_marker_entry(file_marker.time - *previous_time, &file_marker);
+                    *previous_time = file_marker.time;
+                }
+                _ => break,
+            }
+        }
+    }
+
+    pub fn write_file_note(&mut self, file_note: &FileNote) {
+        self.write_entry(file_note.delay, &file_note.base_note, file_note.duration, file_note.track);
+    }
+
+    pub fn write_file_notes(&mut self, file_notes: &[FileNote]) {
+        for file_note in file_notes {
+            self.write_file_note(file_note);
+        }
+    }
+
+    fn write_track(&mut self, number: usize, track: &FileTrack) {
+        let mut track_details = format!(
+            "track_{}:\n  name: {}\n  color: {}\n",
+            number,
+            quoted(&track.name),
+            format!("{:?}", track.color).to_lowercase(),
+        );
+        if let Some(address) = track.address {
+            track_details.push_str(&format!("  address: \"{}\"\n", address));
+        }
+
+        if let Err(e) = writeln!(self.record_file.as_ref().unwrap(), "{}", track_details) {
+            panic!("Couldn't write track to file: {}", e);
+        }
+    }
+
+    fn write_entry(&mut self, delay: Duration, sound: &str, duration: Duration, track: Option<usize>) {
+        let mut note_details = format!(
             "note_{}:\n  - {}\n  - {}\n  - {}\n",
-            self.note_number,
-            delay_gap,
-            note.sound,
-            note.duration.as_millis(),
+            self.entry_number,
+            delay.as_millis(),
+            sound,
+            duration.as_millis(),
         );
+        if let Some(track) = track {
+            note_details.push_str(&format!("  - {}\n", track));
+        }
 
         if let Err(e) = writeln!(self.record_file.as_ref().unwrap(), "{}", note_details) {
             panic!("Couldn't write note to file: {}", e);
         }
 
-        self.increment_note();
+        self.increment_entry();
+    }
+
+    fn write_control_entry(&mut self, delay: Duration, control: Control) {
+        let (name, value) = match control {
+            Control::Volume(volume) => ("volume", format!("{:?}", volume)),
+            Control::Sequence(sequence) => ("sequence", sequence.to_string()),
+            Control::SoundDuration(duration) => ("sound_duration", duration.as_millis().to_string()),
+            Control::Tempo { bpm, ramp: false } => ("tempo", format!("{:?}", bpm)),
+            Control::Tempo { bpm, ramp: true } => ("tempo_ramp", format!("{:?}", bpm)),
+            Control::TimeSignature(time_signature) => ("time_signature", format!("\"{}\"", time_signature)),
+        };
+        let control_details = format!(
+            "control_{}:\n  - {}\n  - {}\n  - {}\n",
+            self.entry_number,
+            delay.as_millis(),
+            name,
+            value,
+        );
+
+        if let Err(e) = writeln!(self.record_file.as_ref().unwrap(), "{}", control_details) {
+            panic!("Couldn't write control to file: {}", e);
+        }
+
+        self.increment_entry();
+    }
+
+    fn write_marker_entry(&mut self, delay: Duration, file_marker: &FileMarker) {
+        let kind = match file_marker.kind {
+            MarkerKind::Section => "section",
+            MarkerKind::Lyric => "lyric",
+            MarkerKind::Annotation => "annotation",
+        };
+        let marker_details = format!(
+            "marker_{}:\n  - {}\n  - {}\n  - {}\n",
+            self.entry_number,
+            delay.as_millis(),
+            kind,
+            quoted(&file_marker.text),
+        );
+
+        if let Err(e) = writeln!(self.record_file.as_ref().unwrap(), "{}", marker_details) {
+            panic!("Couldn't write marker to file: {}", e);
+        }
+
+        self.increment_entry();
+    }
+
+    fn next_delay(&mut self) -> Duration {
+        match self.quantizer {
+            Some(quantizer) => {
+                // Snap against the start of the recording so the grid doesn't drift
+                let position = quantizer
+                    .quantize_time(self.start_time.elapsed())
+                    .max(self.previous_note_position);
+                let delay_gap = position - self.previous_note_position;
+                self.previous_note_position = position;
+                delay_gap
+            }
+            None => {
+                let delay_gap = self.previous_note_time.elapsed();
+                let current_time = Instant::now();
+                self.set_previous_note_time(current_time);
+                delay_gap
+            }
+        }
     }
 
     fn open_file(filename: PathBuf) -> Result<File, Error> {
@@ -115,8 +621,8 @@ impl NoteRecorder {
             .open(filename)
     }
 
-    fn increment_note(&mut self) {
-        self.note_number += 1;
+    fn increment_entry(&mut self) {
+        self.entry_number += 1;
     }
 
     fn set_previous_note_time(&mut self, time: Instant) {
@@ -130,3 +636,207 @@ impl Default for NoteRecorder {
     }
 }
 
+// Text written as a double-quoted YAML string, which can hold quotes and backslashes of its own
+fn quoted(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Control,
+        FileControl,
+        FileMarker,
+        FileNote,
+        FileTrack,
+        MarkerKind,
+        NoteReader,
+        NoteRecorder,
+        NoteSource,
+        Song,
+        TimeSignature,
+        Color,
+        Duration,
+    };
+    use std::env;
+    use std::fs;
+
+    fn file_notes() -> Vec<FileNote> {
+        [(100, "a2"), (0, "b2"), (250, "c3")]
+            .iter()
+            .map(|&(delay, base_note)| FileNote {
+                delay: Duration::from_millis(delay),
+                base_note: base_note.to_string(),
+                duration: Duration::from_millis(0),
+                track: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn start_times() {
+        let start_times = super::start_times(&file_notes());
+        assert_eq!(start_times, vec![
+            Duration::from_millis(100),
+            Duration::from_millis(100),
+            Duration::from_millis(350),
+        ]);
+    }
+
+    #[test]
+    fn set_start_times() {
+        let mut notes = file_notes();
+        super::set_start_times(&mut notes, &[
+            Duration::from_millis(0),
+            Duration::from_millis(50),
+            Duration::from_millis(50),
+        ]);
+        let delays: Vec<u128> = notes.iter().map(|note| note.delay.as_millis()).collect();
+        assert_eq!(delays, vec![0, 50, 0]);
+    }
+
+    #[test]
+    fn write_and_read_file_notes() {
+        let path = env::temp_dir().join("piano-rs-write-and-read-file-notes.yml");
+        NoteRecorder::create(path.clone()).unwrap().write_file_notes(&file_notes());
+        let read_notes = NoteReader::from(path.clone()).parse_notes();
+        fs::remove_file(path).unwrap();
+        assert_eq!(read_notes, file_notes());
+    }
+
+    #[test]
+    fn read_empty_file() {
+        let path = env::temp_dir().join("piano-rs-read-empty-file.yml");
+        NoteRecorder::create(path.clone()).unwrap();
+        let read_notes = NoteReader::from(path.clone()).parse_notes();
+        fs::remove_file(path).unwrap();
+        assert!(read_notes.is_empty());
+    }
+
+    #[test]
+    fn write_and_read_song() {
+        let mut notes = file_notes();
+        notes[0].track = Some(1);
+        notes[1].track = Some(2);
+        let song = Song {
+            tracks: vec![
+                FileTrack::from_source(NoteSource::Local, Color::Blue),
+                FileTrack::from_source(NoteSource::Peer("10.0.0.2:9999".parse().unwrap()), Color::Red),
+            ],
+            notes,
+            controls: Vec::new(),
+            markers: Vec::new(),
+        };
+
+        let path = env::temp_dir().join("piano-rs-write-and-read-song.yml");
+        NoteRecorder::create(path.clone()).unwrap().write_song(&song);
+        let read_song = NoteReader::from(path.clone()).parse_song();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(read_song, song);
+        assert_eq!(read_song.tracks[1].name, "Peer 1");
+        assert_eq!(read_song.track(&read_song.notes[1]), Some(&song.tracks[1]));
+        assert_eq!(read_song.track(&read_song.notes[2]), None);
+    }
+
+    #[test]
+    fn write_and_read_track_names() {
+        let mut track = FileTrack::from_source(NoteSource::Local, Color::Green);
+        track.name = String::from("Piano \"RH\" C:\\");
+        let song = Song {
+            tracks: vec![track],
+            ..Song::default()
+        };
+
+        let path = env::temp_dir().join("piano-rs-write-and-read-track-names.yml");
+        NoteRecorder::create(path.clone()).unwrap().write_song(&song);
+        let read_song = NoteReader::from(path.clone()).parse_song();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(read_song.tracks[0].name, "Piano \"RH\" C:\\");
+    }
+
+    #[test]
+    fn write_and_read_controls() {
+        let song = Song {
+            notes: file_notes(),
+            controls: vec![
+                FileControl { time: Duration::from_millis(0), control: Control::Sequence(3) },
+                FileControl { time: Duration::from_millis(100), control: Control::Volume(1.0) },
+                FileControl { time: Duration::from_millis(200), control: Control::Volume(0.7) },
+                FileControl {
+                    time: Duration::from_millis(500),
+                    control: Control::SoundDuration(Duration::from_millis(250)),
+                },
+                FileControl { time: Duration::from_millis(500), control: Control::Tempo { bpm: 90.0, ramp: false } },
+                FileControl { time: Duration::from_millis(800), control: Control::Tempo { bpm: 60.5, ramp: true } },
+                FileControl {
+                    time: Duration::from_millis(800),
+                    control: Control::TimeSignature(TimeSignature { beats: 6, beat_unit: 8 }),
+                },
+            ],
+            ..Song::default()
+        };
+
+        let path = env::temp_dir().join("piano-rs-write-and-read-controls.yml");
+        NoteRecorder::create(path.clone()).unwrap().write_song(&song);
+        let reader = NoteReader::from(path.clone());
+        fs::remove_file(path).unwrap();
+
+        // Controls in between notes don't change the delays of the notes themselves
+        assert_eq!(reader.parse_notes(), file_notes());
+        assert_eq!(reader.parse_controls(), song.controls);
+        assert!(reader.parse_yaml_entry(1).is_err());
+        assert!(reader.parse_control_entry(2).is_ok());
+    }
+
+    #[test]
+    fn write_and_read_markers() {
+        let marker = |time, kind, text: &str| FileMarker {
+            time: Duration::from_millis(time),
+            kind,
+            text: text.to_string(),
+        };
+        let song = Song {
+            notes: file_notes(),
+            controls: vec![FileControl { time: Duration::from_millis(100), control: Control::Volume(0.5) }],
+            markers: vec![
+                marker(0, MarkerKind::Section, "Verse \"1\""),
+                marker(100, MarkerKind::Lyric, "Hel-"),
+                marker(350, MarkerKind::Lyric, "lo"),
+                marker(900, MarkerKind::Annotation, "Slow down"),
+            ],
+            ..Song::default()
+        };
+
+        let path = env::temp_dir().join("piano-rs-write-and-read-markers.yml");
+        NoteRecorder::create(path.clone()).unwrap().write_song(&song);
+        let reader = NoteReader::from(path.clone());
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(reader.parse_song(), song);
+        // Controls come before markers at the same time, and both before the notes
+        assert!(reader.parse_control_entry(2).is_ok());
+        assert!(reader.parse_marker_entry(3).is_ok());
+
+        let reader = NoteReader::parse("marker_1:\n  - 0\n  - section\n  - 9\n").unwrap();
+        assert_eq!(reader.parse_markers(), vec![marker(0, MarkerKind::Section, "9")]);
+    }
+
+    #[test]
+    fn check_entries() {
+        let check = |content: &str| NoteReader::parse(content).unwrap().check();
+        assert_eq!(check(""), Ok(()));
+        assert_eq!(check("track_1:\n  name: \"Local\"\nnote_1:\n  - 0\n  - c3\n  - 0\n"), Ok(()));
+        assert_eq!(
+            check("note_1:\n  - 0\n  - c3\nnote_2:\n  - 0\n  - d3\n  - 0\n"),
+            Err(String::from("note_1 should be a delay, a note and a duration")),
+        );
+        assert_eq!(
+            check("note_1:\n  - 0\n  - c3\n  - 0\nnote_3:\n  - 0\n  - d3\n  - 0\n"),
+            Err(String::from("Entry number 2 is missing, so the entries after it can't be read")),
+        );
+        assert_eq!(check("note_1:\n  - 0\n  - x3\n  - 0\n"), Err(String::from("Unknown note \"x3\"")));
+        assert!(check("- c3\n- d3\n").is_err());
+    }
+}
@@ -0,0 +1,752 @@
+use std::mem;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+use serde_derive::{Serialize, Deserialize};
+use crate::game::{edit, notes_file, FileNote, Scheduler, Song};
+use crate::game::notes_file::{Control, FileMarker, MarkerKind};
+use crate::game::tempo::{TempoMap, TimeSignature};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PlaybackCommand {
+    TogglePause,
+    SeekForward,
+    SeekBackward,
+    NextBar,
+    PreviousBar,
+    NextMarker,
+    PreviousMarker,
+    SetLoopStart,
+    SetLoopEnd,
+    ClearLoop,
+    IncreaseTempo,
+    DecreaseTempo,
+    ResetTempo,
+    NextSong,
+    PreviousSong,
+    // Sent when the file being played has changed
+    Reload,
+}
+
+// Why playback stopped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaybackEnd {
+    Finished,
+    NextSong,
+    PreviousSong,
+    Reload,
+    Stopped,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum PlaybackEvent {
+    Note(FileNote),
+    Control(Control),
+}
+
+#[derive(Debug)]
+pub struct Playback {
+    scheduler: Scheduler<PlaybackEvent>,
+    length: Duration,
+    tempo: f32,
+    speed: f32,
+    loop_start: Option<Duration>,
+    loop_end: Option<Duration>,
+    seek_step: Duration,
+    bar_length: Duration,
+    bpm: f32,
+    tempo_map: TempoMap,
+    time_signatures: Vec<(Duration, TimeSignature)>,
+    markers: Vec<FileMarker>,
+    // Notes along with when they start, kept for showing the ones coming up
+    notes: Vec<(Duration, FileNote)>,
+    muted_tracks: Vec<usize>,
+    solo_tracks: Vec<usize>,
+    pending_controls: Vec<Control>,
+    skip: Option<PlaybackEnd>,
+}
+
+impl Playback {
+    pub fn new(song: &Song, tempo: f32) -> Playback {
+        let length = edit::song_length(&song.notes);
+        let tempo_map = TempoMap::new(&song.controls);
+        // Controls come first so that they already apply to notes starting at the same time
+        let mut timed_events: Vec<(Duration, PlaybackEvent)> = song.controls
+            .iter()
+            .filter(|control| !matches!(control.control, Control::Tempo { .. } | Control::TimeSignature(_)))
+            .map(|control| (control.time, PlaybackEvent::Control(control.control)))
+            .collect();
+        timed_events.extend(
+            notes_file::start_times(&song.notes)
+                .into_iter()
+                .zip(song.notes.iter().cloned().map(PlaybackEvent::Note))
+        );
+        // The scheduler runs on the time each event is played at, following the tempo map
+        let timed_events = timed_events
+            .into_iter()
+            .map(|(time, event)| (tempo_map.clock_time(time), event))
+            .collect();
+        let time_signatures = song.controls
+            .iter()
+            .filter_map(|control| match control.control {
+                Control::TimeSignature(time_signature) => Some((control.time, time_signature)),
+                _ => None,
+            })
+            .collect();
+        let mut markers = song.markers.clone();
+        markers.sort_by_key(|marker| marker.time);
+        let notes = notes_file::start_times(&song.notes)
+            .into_iter()
+            .zip(song.notes.iter().cloned())
+            .collect();
+
+        Playback {
+            scheduler: Scheduler::new(timed_events, tempo),
+            length,
+            tempo,
+            speed: 1.0,
+            loop_start: None,
+            loop_end: None,
+            seek_step: Duration::from_secs(5),
+            bar_length: Duration::from_secs(2),
+            bpm: 120.0,
+            tempo_map,
+            time_signatures,
+            markers,
+            notes,
+            muted_tracks: Vec::new(),
+            solo_tracks: Vec::new(),
+            pending_controls: Vec::new(),
+            skip: None,
+        }
+    }
+
+    pub fn set_seek_step(&mut self, seek_step: Duration) {
+        self.seek_step = seek_step;
+    }
+
+    pub fn set_bar_length(&mut self, bar_length: Duration) {
+        self.bar_length = bar_length;
+    }
+
+    // Tempo used for bars in songs without a tempo of their own
+    pub fn set_bpm(&mut self, bpm: f32) {
+        self.bpm = bpm;
+    }
+
+    // The tempo the song is written at
+    pub fn bpm(&self) -> f32 {
+        self.tempo_map.bpm().unwrap_or(self.bpm)
+    }
+
+    pub fn set_muted_tracks(&mut self, muted_tracks: Vec<usize>) {
+        self.muted_tracks = muted_tracks;
+    }
+
+    pub fn set_solo_tracks(&mut self, solo_tracks: Vec<usize>) {
+        self.solo_tracks = solo_tracks;
+    }
+
+    // Soloing any track mutes every track that isn't soloed
+    pub fn is_audible(&self, note: &FileNote) -> bool {
+        match note.track {
+            Some(track) => {
+                !self.muted_tracks.contains(&track)
+                    && (self.solo_tracks.is_empty() || self.solo_tracks.contains(&track))
+            }
+            None => self.solo_tracks.is_empty(),
+        }
+    }
+
+    pub fn position(&self) -> Duration {
+        self.tempo_map.position(self.scheduler.position()).min(self.length)
+    }
+
+    pub fn tempo(&self) -> f32 {
+        self.tempo * self.speed
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.scheduler.is_paused()
+    }
+
+    // Audible notes playing now or starting within `window`, with how long until each of them
+    // starts and ends
+    pub fn upcoming_notes(&self, window: Duration) -> Vec<(Duration, Duration, &FileNote)> {
+        let position = self.position();
+        self.notes
+            .iter()
+            .filter(|(start, note)| *start < position + window && *start + note.duration > position && self.is_audible(note))
+            .map(|(start, note)| (start.checked_sub(position).unwrap_or_default(), *start + note.duration - position, note))
+            .collect()
+    }
+
+    // `show` is handed the playback whenever its status, song text and the notes coming up
+    // should be shown
+    pub fn run<F, G, H>(&mut self, commands: &Receiver<PlaybackCommand>, mut play: F, mut apply_control: G, mut show: H) -> PlaybackEnd
+    where
+        F: FnMut(&[FileNote]),
+        G: FnMut(Control),
+        H: FnMut(&Playback),
+    {
+        // Redraw the position at least this often while waiting for the next notes
+        let refresh = Duration::from_millis(100);
+
+        self.scheduler.start();
+        loop {
+            if let Some(skip) = self.skip.take() {
+                return skip;
+            }
+            for control in self.pending_controls.drain(..) {
+                apply_control(control);
+            }
+            show(self);
+
+            if self.is_paused() {
+                match commands.recv() {
+                    Ok(command) => self.apply(command),
+                    Err(_) => return PlaybackEnd::Stopped,
+                }
+                continue;
+            }
+
+            let looping = self.loop_start.and(self.loop_end);
+            if let (Some(start), Some(end)) = (self.loop_start, self.loop_end) {
+                if self.tempo_map.position(self.scheduler.position()) >= end {
+                    self.seek(start);
+                    continue;
+                }
+            }
+
+            let looping = looping.map(|end| self.tempo_map.clock_time(end));
+            let wait = match (self.scheduler.time_until_next(), looping) {
+                (Some(next), Some(end)) => next.min(self.scheduler.time_until(end)),
+                (Some(next), None) => next,
+                (None, Some(end)) => self.scheduler.time_until(end),
+                (None, None) => return PlaybackEnd::Finished,
+            };
+
+            match commands.recv_timeout(wait.min(refresh)) {
+                Ok(command) => self.apply(command),
+                Err(RecvTimeoutError::Timeout) => {
+                    if looping.is_some_and(|end| self.scheduler.position() >= end) {
+                        continue;
+                    }
+                    let batch = match self.scheduler.poll() {
+                        Some(batch) => batch.to_vec(),
+                        None => continue,
+                    };
+                    let mut audible_notes: Vec<FileNote> = Vec::new();
+                    for event in batch {
+                        match event {
+                            PlaybackEvent::Control(control) => apply_control(control),
+                            PlaybackEvent::Note(note) => {
+                                if self.is_audible(&note) {
+                                    audible_notes.push(note);
+                                }
+                            }
+                        }
+                    }
+                    if !audible_notes.is_empty() {
+                        play(&audible_notes);
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => return PlaybackEnd::Stopped,
+            }
+        }
+    }
+
+    pub fn apply(&mut self, command: PlaybackCommand) {
+        match command {
+            PlaybackCommand::TogglePause => {
+                if self.is_paused() {
+                    self.scheduler.resume();
+                } else {
+                    self.scheduler.pause();
+                }
+            }
+            PlaybackCommand::SeekForward => {
+                self.seek(self.position() + self.seek_step);
+            }
+            PlaybackCommand::SeekBackward => {
+                self.seek(self.position().checked_sub(self.seek_step).unwrap_or_default());
+            }
+            PlaybackCommand::NextBar => {
+                let position = self.position();
+                let next = self.bar_starts().into_iter().find(|&start| start > position);
+                self.seek(next.unwrap_or(self.length));
+            }
+            PlaybackCommand::PreviousBar => {
+                // Jump to the start of the current bar unless we're already close to it
+                let position = self.position();
+                let starts: Vec<Duration> = self.bar_starts()
+                    .into_iter()
+                    .take_while(|&start| start <= position)
+                    .collect();
+                let bar = match starts.as_slice() {
+                    [.., previous, current] if position - *current < Duration::from_millis(500) => *previous,
+                    [.., current] => *current,
+                    [] => Duration::from_millis(0),
+                };
+                self.seek(bar);
+            }
+            PlaybackCommand::NextMarker => {
+                let position = self.position();
+                if let Some(next) = self.section_starts().into_iter().find(|&start| start > position) {
+                    self.seek(next);
+                }
+            }
+            PlaybackCommand::PreviousMarker => {
+                // Like bars, go back to the start of the current section unless it has only just begun
+                let position = self.position();
+                let starts: Vec<Duration> = self.section_starts()
+                    .into_iter()
+                    .take_while(|&start| start <= position)
+                    .collect();
+                let section = match starts.as_slice() {
+                    [.., previous, current] if position - *current < Duration::from_millis(500) => *previous,
+                    [.., current] => *current,
+                    [] => Duration::from_millis(0),
+                };
+                self.seek(section);
+            }
+            PlaybackCommand::SetLoopStart => {
+                let position = self.position();
+                self.loop_start = Some(position);
+                if self.loop_end.is_some_and(|end| end <= position) {
+                    self.loop_end = None;
+                }
+            }
+            PlaybackCommand::SetLoopEnd => {
+                let position = self.position();
+                if self.loop_start.is_none_or(|start| start < position) {
+                    self.loop_end = Some(position);
+                    if self.loop_start.is_none() {
+                        self.loop_start = Some(Duration::from_millis(0));
+                    }
+                }
+            }
+            PlaybackCommand::ClearLoop => {
+                self.loop_start = None;
+                self.loop_end = None;
+            }
+            PlaybackCommand::IncreaseTempo => {
+                if self.speed < 4.0 {
+                    self.speed += 0.1;
+                    self.scheduler.set_tempo(self.tempo());
+                }
+            }
+            PlaybackCommand::DecreaseTempo => {
+                if self.speed > 0.2 {
+                    self.speed -= 0.1;
+                    self.scheduler.set_tempo(self.tempo());
+                }
+            }
+            PlaybackCommand::ResetTempo => {
+                self.speed = 1.0;
+                self.scheduler.set_tempo(self.tempo());
+            }
+            PlaybackCommand::NextSong => self.skip = Some(PlaybackEnd::NextSong),
+            PlaybackCommand::PreviousSong => self.skip = Some(PlaybackEnd::PreviousSong),
+            PlaybackCommand::Reload => self.skip = Some(PlaybackEnd::Reload),
+        }
+    }
+
+    pub fn seek(&mut self, position: Duration) {
+        self.scheduler.seek(self.tempo_map.clock_time(position.min(self.length)));
+        // Seeking skips over controls, so bring back the ones in effect at the new position
+        self.pending_controls = self.controls_in_effect();
+    }
+
+    fn controls_in_effect(&self) -> Vec<Control> {
+        let mut controls: Vec<Control> = Vec::new();
+        for event in self.scheduler.played().iter().rev() {
+            if let PlaybackEvent::Control(control) = event {
+                if !controls.iter().any(|applied| mem::discriminant(applied) == mem::discriminant(control)) {
+                    controls.push(*control);
+                }
+            }
+        }
+        controls
+    }
+
+    pub fn status(&self) -> String {
+        let state = if self.is_paused() { "Paused " } else { "Playing" };
+        let mut status = format!(
+            "{} {} / {}  tempo x{:.2}",
+            state,
+            format_position(self.position()),
+            format_position(self.length),
+            self.tempo(),
+        );
+        if let Some(bpm) = self.tempo_map.bpm_at(self.position()) {
+            status.push_str(&format!(" ({:.0} bpm)", bpm * self.tempo()));
+        }
+        if let Some(start) = self.loop_start {
+            let end = self.loop_end.map_or_else(|| String::from("..."), format_position);
+            status.push_str(&format!("  loop {} - {}", format_position(start), end));
+        }
+        status
+    }
+
+    // Start of the section being played, if the song has any sections
+    pub fn section_start(&self) -> Option<Duration> {
+        let position = self.position();
+        self.section_starts().into_iter().rev().find(|&start| start <= position)
+    }
+
+    fn section_starts(&self) -> Vec<Duration> {
+        self.markers
+            .iter()
+            .filter(|marker| marker.kind == MarkerKind::Section)
+            .map(|marker| marker.time)
+            .collect()
+    }
+
+    // The current section along with its latest annotation, and the lyrics of the section split
+    // into what has been sung so far and what is still to come. Songs without markers have none.
+    pub fn song_text(&self) -> Option<(String, String, String)> {
+        if self.markers.is_empty() {
+            return None;
+        }
+        let position = self.position();
+        let starts = self.section_starts();
+        let section_start = starts.iter().rev().find(|&&start| start <= position).copied();
+        let section_end = starts.iter().find(|&&start| start > position).copied();
+        let in_section = |marker: &&FileMarker| {
+            section_start.is_none_or(|start| marker.time >= start) && section_end.is_none_or(|end| marker.time < end)
+        };
+
+        let section = self.markers.iter()
+            .rev()
+            .find(|marker| marker.kind == MarkerKind::Section && marker.time <= position);
+        let annotation = self.markers.iter()
+            .rev()
+            .filter(in_section)
+            .find(|marker| marker.kind == MarkerKind::Annotation && marker.time <= position);
+        let heading = match (section, annotation) {
+            (Some(section), Some(annotation)) => format!("[{}]  {}", section.text, annotation.text),
+            (Some(section), None) => format!("[{}]", section.text),
+            (None, Some(annotation)) => annotation.text.clone(),
+            (None, None) => String::new(),
+        };
+
+        let (sung, upcoming): (Vec<&FileMarker>, Vec<&FileMarker>) = self.markers.iter()
+            .filter(in_section)
+            .filter(|marker| marker.kind == MarkerKind::Lyric)
+            .partition(|marker| marker.time <= position);
+        let mut sung = join_syllables(&sung);
+        let mut upcoming = join_syllables(&upcoming);
+        // A hyphen joins a syllable to the next one, even across the two parts
+        if sung.ends_with('-') {
+            sung.pop();
+        } else if !sung.is_empty() && !upcoming.is_empty() {
+            upcoming.insert(0, ' ');
+        }
+        Some((heading, sung, upcoming))
+    }
+
+    // Start of every bar up to the end of the song. Bars follow the time signatures in the song,
+    // and otherwise the bar length, while each new time signature starts a new bar.
+    fn bar_starts(&self) -> Vec<Duration> {
+        let bpm = self.bpm();
+        let mut bar_length = self.bar_length;
+        let mut changes = self.time_signatures.iter().peekable();
+        let mut starts = Vec::new();
+        let mut time = Duration::from_millis(0);
+        loop {
+            while let Some((_, time_signature)) = changes.next_if(|&&(change, _)| change <= time) {
+                bar_length = time_signature.bar_length(bpm);
+            }
+            starts.push(time);
+            if time >= self.length || bar_length == Duration::from_millis(0) {
+                break;
+            }
+            time = match changes.peek() {
+                Some(&&(change, _)) => (time + bar_length).min(change),
+                None => time + bar_length,
+            };
+        }
+        starts
+    }
+}
+
+// Syllables ending with a hyphen run into the next one, while the rest are separate words
+fn join_syllables(syllables: &[&FileMarker]) -> String {
+    let mut text = String::new();
+    for syllable in syllables {
+        if text.ends_with('-') {
+            text.pop();
+        } else if !text.is_empty() {
+            text.push(' ');
+        }
+        text.push_str(&syllable.text);
+    }
+    text
+}
+
+fn format_position(position: Duration) -> String {
+    let millis = position.as_millis();
+    format!("{:02}:{:02}.{}", millis / 60_000, (millis / 1000) % 60, (millis / 100) % 10)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Playback, PlaybackCommand, PlaybackEnd, PlaybackEvent, FileNote, Song, Control, Duration};
+    use std::sync::mpsc;
+    use crate::game::notes_file::{FileControl, FileMarker, MarkerKind};
+    use crate::game::tempo::TimeSignature;
+
+    fn file_notes() -> Vec<FileNote> {
+        [(0, "a2"), (1000, "b2"), (0, "c3"), (2000, "d3")]
+            .iter()
+            .map(|&(delay, base_note)| FileNote {
+                delay: Duration::from_millis(delay),
+                base_note: base_note.to_string(),
+                duration: Duration::from_millis(500),
+                track: None,
+            })
+            .collect()
+    }
+
+    fn song() -> Song {
+        Song {
+            notes: file_notes(),
+            ..Song::default()
+        }
+    }
+
+    #[test]
+    fn new_playback() {
+        let mut song = song();
+        song.controls.push(FileControl {
+            time: Duration::from_millis(1000),
+            control: Control::Sequence(4),
+        });
+        let playback = Playback::new(&song, 1.0);
+        let events: Vec<String> = playback.scheduler.events()
+            .iter()
+            .map(|event| match event {
+                PlaybackEvent::Note(note) => note.base_note.clone(),
+                PlaybackEvent::Control(_) => String::from("control"),
+            })
+            .collect();
+        assert_eq!(events, ["a2", "control", "b2", "c3", "d3"]);
+        assert_eq!(playback.length, Duration::from_millis(3500));
+    }
+
+    #[test]
+    fn seek_restores_controls() {
+        let mut song = song();
+        song.controls = [(0, Control::Volume(0.2)), (500, Control::Sequence(4)), (1500, Control::Volume(0.8))]
+            .iter()
+            .map(|&(time, control)| FileControl {
+                time: Duration::from_millis(time),
+                control,
+            })
+            .collect();
+        let mut playback = Playback::new(&song, 1.0);
+
+        playback.seek(Duration::from_millis(2000));
+        assert_eq!(playback.pending_controls, vec![Control::Volume(0.8), Control::Sequence(4)]);
+        playback.seek(Duration::from_millis(0));
+        assert!(playback.pending_controls.is_empty());
+    }
+
+    #[test]
+    fn seek_past_end() {
+        let mut playback = Playback::new(&song(), 1.0);
+        playback.seek(Duration::from_millis(10000));
+        assert_eq!(playback.position(), Duration::from_millis(3500));
+        assert!(playback.scheduler.is_finished());
+    }
+
+    #[test]
+    fn show_upcoming_notes() {
+        let mut playback = Playback::new(&song(), 1.0);
+        let upcoming = |playback: &Playback, window: u64| -> Vec<(u128, u128, String)> {
+            playback.upcoming_notes(Duration::from_millis(window))
+                .into_iter()
+                .map(|(starts_in, ends_in, note)| (starts_in.as_millis(), ends_in.as_millis(), note.base_note.clone()))
+                .collect()
+        };
+        playback.seek(Duration::from_millis(800));
+        assert_eq!(upcoming(&playback, 2000), vec![(200, 700, "b2".to_string()), (200, 700, "c3".to_string())]);
+        assert!(upcoming(&playback, 200).is_empty());
+        // Notes already playing have started
+        playback.seek(Duration::from_millis(3200));
+        assert_eq!(upcoming(&playback, 1000), vec![(0, 300, "d3".to_string())]);
+    }
+
+    #[test]
+    fn seek_by_step_and_bar() {
+        let mut playback = Playback::new(&song(), 1.0);
+        playback.set_seek_step(Duration::from_millis(1500));
+        playback.set_bar_length(Duration::from_millis(1000));
+
+        playback.apply(PlaybackCommand::SeekForward);
+        assert_eq!(playback.position(), Duration::from_millis(1500));
+        playback.apply(PlaybackCommand::NextBar);
+        assert_eq!(playback.position(), Duration::from_millis(2000));
+        playback.apply(PlaybackCommand::PreviousBar);
+        assert_eq!(playback.position(), Duration::from_millis(1000));
+        playback.apply(PlaybackCommand::SeekBackward);
+        assert_eq!(playback.position(), Duration::from_millis(0));
+    }
+
+    #[test]
+    fn follow_tempo_map() {
+        let mut song = song();
+        // Half as fast from the second note onwards, in bars of 3/4 from the third
+        song.controls = [
+            (0, Control::Tempo { bpm: 120.0, ramp: false }),
+            (1000, Control::Tempo { bpm: 60.0, ramp: false }),
+            (1000, Control::TimeSignature(TimeSignature { beats: 3, beat_unit: 4 })),
+        ]
+            .iter()
+            .map(|&(time, control)| FileControl {
+                time: Duration::from_millis(time),
+                control,
+            })
+            .collect();
+        let mut playback = Playback::new(&song, 2.0);
+        playback.set_bar_length(Duration::from_millis(800));
+        assert_eq!(playback.scheduler.events().len(), 4);
+
+        playback.seek(Duration::from_millis(3000));
+        assert_eq!(playback.scheduler.position(), Duration::from_millis(5000));
+        assert_eq!(playback.position(), Duration::from_millis(3000));
+        assert!(playback.status().contains("(120 bpm)"));
+
+        playback.seek(Duration::from_millis(0));
+        let starts: Vec<u128> = playback.bar_starts().iter().map(|start| start.as_millis()).collect();
+        assert_eq!(starts, [0, 800, 1000, 2500, 4000]);
+        playback.apply(PlaybackCommand::NextBar);
+        playback.apply(PlaybackCommand::NextBar);
+        assert_eq!(playback.position(), Duration::from_millis(1000));
+    }
+
+    fn marked_playback() -> Playback {
+        let mut song = song();
+        song.markers = [
+            (0, MarkerKind::Section, "Verse"),
+            (0, MarkerKind::Lyric, "Hel-"),
+            (1000, MarkerKind::Lyric, "lo"),
+            (1000, MarkerKind::Annotation, "Softly"),
+            (2000, MarkerKind::Lyric, "world"),
+            (3000, MarkerKind::Section, "Chorus"),
+            (3000, MarkerKind::Lyric, "La"),
+        ]
+            .iter()
+            .map(|&(time, kind, text)| FileMarker {
+                time: Duration::from_millis(time),
+                kind,
+                text: text.to_string(),
+            })
+            .collect();
+        Playback::new(&song, 1.0)
+    }
+
+    #[test]
+    fn seek_between_markers() {
+        let mut playback = marked_playback();
+        playback.apply(PlaybackCommand::NextMarker);
+        assert_eq!(playback.position(), Duration::from_millis(3000));
+        playback.apply(PlaybackCommand::NextMarker);
+        assert_eq!(playback.position(), Duration::from_millis(3000));
+        playback.apply(PlaybackCommand::PreviousMarker);
+        assert_eq!(playback.position(), Duration::from_millis(0));
+
+        playback.seek(Duration::from_millis(3200));
+        playback.apply(PlaybackCommand::PreviousMarker);
+        assert_eq!(playback.position(), Duration::from_millis(0));
+        playback.seek(Duration::from_millis(3500));
+        playback.apply(PlaybackCommand::PreviousMarker);
+        assert_eq!(playback.position(), Duration::from_millis(3000));
+    }
+
+    #[test]
+    fn find_section_start() {
+        let mut playback = marked_playback();
+        playback.seek(Duration::from_millis(3200));
+        assert_eq!(playback.section_start(), Some(Duration::from_millis(3000)));
+        assert_eq!(Playback::new(&song(), 1.0).section_start(), None);
+    }
+
+    #[test]
+    fn show_song_text() {
+        let mut playback = marked_playback();
+        assert_eq!(playback.song_text(), Some((String::from("[Verse]"), String::from("Hel"), String::from("lo world"))));
+        playback.seek(Duration::from_millis(1500));
+        assert_eq!(playback.song_text(), Some((String::from("[Verse]  Softly"), String::from("Hello"), String::from(" world"))));
+        playback.seek(Duration::from_millis(3000));
+        assert_eq!(playback.song_text(), Some((String::from("[Chorus]"), String::from("La"), String::new())));
+        assert_eq!(Playback::new(&song(), 1.0).song_text(), None);
+    }
+
+    #[test]
+    fn set_loop_points() {
+        let mut playback = Playback::new(&song(), 1.0);
+        playback.seek(Duration::from_millis(1000));
+        playback.apply(PlaybackCommand::SetLoopStart);
+        playback.seek(Duration::from_millis(500));
+        playback.apply(PlaybackCommand::SetLoopEnd);
+        assert_eq!(playback.loop_end, None);
+
+        playback.seek(Duration::from_millis(3000));
+        playback.apply(PlaybackCommand::SetLoopEnd);
+        assert_eq!(playback.loop_start, Some(Duration::from_millis(1000)));
+        assert_eq!(playback.loop_end, Some(Duration::from_millis(3000)));
+
+        playback.apply(PlaybackCommand::ClearLoop);
+        assert_eq!(playback.loop_start, None);
+        assert_eq!(playback.loop_end, None);
+    }
+
+    #[test]
+    fn change_tempo() {
+        let mut playback = Playback::new(&song(), 2.0);
+        playback.apply(PlaybackCommand::IncreaseTempo);
+        assert!((playback.tempo() - 2.2).abs() < 0.001);
+        assert_eq!(playback.scheduler.tempo(), playback.tempo());
+        playback.apply(PlaybackCommand::ResetTempo);
+        assert_eq!(playback.tempo(), 2.0);
+    }
+
+    #[test]
+    fn mute_and_solo_tracks() {
+        let mut playback = Playback::new(&song(), 1.0);
+        let mut note = file_notes().remove(0);
+        assert!(playback.is_audible(&note));
+
+        note.track = Some(2);
+        playback.set_muted_tracks(vec![2]);
+        assert!(!playback.is_audible(&note));
+
+        playback.set_muted_tracks(Vec::new());
+        playback.set_solo_tracks(vec![1]);
+        assert!(!playback.is_audible(&note));
+        note.track = Some(1);
+        assert!(playback.is_audible(&note));
+    }
+
+    #[test]
+    fn skip_songs() {
+        let (sender, commands) = mpsc::channel();
+        let mut playback = Playback::new(&song(), 1.0);
+        playback.apply(PlaybackCommand::PreviousSong);
+        assert_eq!(playback.run(&commands, |_| {}, |_| {}, |_| {}), PlaybackEnd::PreviousSong);
+        sender.send(PlaybackCommand::NextSong).unwrap();
+        assert_eq!(playback.run(&commands, |_| {}, |_| {}, |_| {}), PlaybackEnd::NextSong);
+        drop(sender);
+        assert_eq!(playback.run(&commands, |_| {}, |_| {}, |_| {}), PlaybackEnd::Stopped);
+    }
+
+    #[test]
+    fn toggle_pause() {
+        let mut playback = Playback::new(&song(), 1.0);
+        assert!(playback.is_paused());
+        assert!(playback.status().starts_with("Paused"));
+        playback.apply(PlaybackCommand::TogglePause);
+        assert!(!playback.is_paused());
+    }
+}
@@ -0,0 +1,235 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::game::convert::Format;
+
+// Songs listed after the current one on screen
+const QUEUE_LENGTH: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Repeat {
+    Off,
+    One,
+    All,
+}
+
+impl FromStr for Repeat {
+    type Err = String;
+
+    fn from_str(repeat: &str) -> Result<Repeat, String> {
+        match repeat {
+            "off" => Ok(Repeat::Off),
+            "one" => Ok(Repeat::One),
+            "all" => Ok(Repeat::All),
+            _ => Err(format!("Invalid repeat mode \"{}\", expected off, one or all", repeat)),
+        }
+    }
+}
+
+// Turns files, directories of songs and .m3u playlists into the songs they list
+pub fn expand(paths: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+    let mut songs = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let entries = fs::read_dir(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let mut files: Vec<PathBuf> = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_file() && Format::from_path(path).is_some_and(|format| format.is_ok()))
+                .collect();
+            files.sort();
+            songs.extend(files);
+        } else if path.extension().is_some_and(|extension| extension == "m3u" || extension == "m3u8") {
+            let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            // Entries are relative to the playlist, and lines starting with # are comments
+            let directory = path.parent().unwrap_or_else(|| Path::new(""));
+            songs.extend(content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| directory.join(line)));
+        } else {
+            songs.push(path.clone());
+        }
+    }
+    if songs.is_empty() {
+        return Err(String::from("No songs to play"));
+    }
+    Ok(songs)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Playlist {
+    songs: Vec<PathBuf>,
+    // Indexes into `songs` in the order they are played
+    order: Vec<usize>,
+    position: usize,
+    repeat: Repeat,
+}
+
+impl Playlist {
+    pub fn new(songs: Vec<PathBuf>, repeat: Repeat) -> Playlist {
+        Playlist {
+            order: (0..songs.len()).collect(),
+            songs,
+            position: 0,
+            repeat,
+        }
+    }
+
+    // Shuffles with a generator seeded from the clock, which is random enough to pick songs
+    pub fn shuffle(&mut self) {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(1, |time| time.as_nanos() as u64);
+        self.shuffle_with(seed);
+    }
+
+    fn shuffle_with(&mut self, seed: u64) {
+        let mut state = seed | 1;
+        for index in (1..self.order.len()).rev() {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            self.order.swap(index, (state % (index as u64 + 1)) as usize);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    pub fn current(&self) -> Option<&Path> {
+        self.order.get(self.position).map(|&index| self.songs[index].as_path())
+    }
+
+    // Moves on once the current song has finished, returning false at the end of the playlist
+    pub fn advance(&mut self) -> bool {
+        match self.repeat {
+            Repeat::One => true,
+            _ => self.skip_next(),
+        }
+    }
+
+    // Skips to the next song, wrapping around only when repeating the whole playlist
+    pub fn skip_next(&mut self) -> bool {
+        if self.position + 1 < self.order.len() {
+            self.position += 1;
+            true
+        } else if self.repeat == Repeat::All {
+            self.position = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn skip_previous(&mut self) {
+        if self.position > 0 {
+            self.position -= 1;
+        } else if self.repeat == Repeat::All {
+            self.position = self.order.len() - 1;
+        }
+    }
+
+    // The current song and the ones after it, named after their files
+    pub fn status(&self) -> String {
+        let name = |index: usize| self.songs[self.order[index]]
+            .file_stem()
+            .map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+        let mut status = format!("Song {}/{}: {}", self.position + 1, self.order.len(), name(self.position));
+        let mut queue: Vec<usize> = (self.position + 1..self.order.len()).collect();
+        if self.repeat == Repeat::All {
+            queue.extend(0..self.position);
+        }
+        if !queue.is_empty() {
+            let names: Vec<String> = queue.into_iter().take(QUEUE_LENGTH).map(name).collect();
+            status.push_str(&format!("  next: {}", names.join(", ")));
+        }
+        match self.repeat {
+            Repeat::Off => {}
+            Repeat::One => status.push_str("  (repeat one)"),
+            Repeat::All => status.push_str("  (repeat all)"),
+        }
+        status
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Playlist, Repeat, PathBuf};
+    use std::env;
+    use std::fs;
+
+    fn playlist(repeat: Repeat) -> Playlist {
+        Playlist::new(vec![PathBuf::from("a.yml"), PathBuf::from("b.mid"), PathBuf::from("c.abc")], repeat)
+    }
+
+    #[test]
+    fn expand_paths() {
+        let directory = env::temp_dir().join("piano-rs-expand-paths");
+        fs::create_dir_all(directory.join("songs")).unwrap();
+        fs::write(directory.join("songs/b.yml"), "").unwrap();
+        fs::write(directory.join("songs/a.txt"), "").unwrap();
+        fs::write(directory.join("songs/cover.png"), "").unwrap();
+        fs::write(directory.join("list.m3u"), "#EXTM3U\nsongs/b.yml\n\n/tmp/c.mid\n").unwrap();
+
+        let songs = super::expand(&[directory.join("songs"), directory.join("list.m3u"), PathBuf::from("d.abc")]);
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(songs, Ok(vec![
+            directory.join("songs/a.txt"),
+            directory.join("songs/b.yml"),
+            directory.join("songs/b.yml"),
+            PathBuf::from("/tmp/c.mid"),
+            PathBuf::from("d.abc"),
+        ]));
+        assert!(super::expand(&[]).is_err());
+    }
+
+    #[test]
+    fn play_in_order() {
+        let mut playlist = playlist(Repeat::Off);
+        assert_eq!(playlist.status(), "Song 1/3: a  next: b, c");
+        assert!(playlist.advance());
+        assert!(playlist.advance());
+        assert_eq!(playlist.current(), Some(PathBuf::from("c.abc").as_path()));
+        assert!(!playlist.advance());
+        playlist.skip_previous();
+        assert_eq!(playlist.status(), "Song 2/3: b  next: c");
+    }
+
+    #[test]
+    fn repeat_songs() {
+        let mut playlist = playlist(Repeat::One);
+        assert!(playlist.advance());
+        assert_eq!(playlist.current(), Some(PathBuf::from("a.yml").as_path()));
+        assert!(playlist.skip_next());
+
+        let mut playlist = super::Playlist { repeat: Repeat::All, ..playlist };
+        playlist.skip_previous();
+        playlist.skip_previous();
+        assert_eq!(playlist.current(), Some(PathBuf::from("c.abc").as_path()));
+        assert_eq!(playlist.status(), "Song 3/3: c  next: a, b  (repeat all)");
+        assert!(playlist.advance());
+        assert_eq!(playlist.current(), Some(PathBuf::from("a.yml").as_path()));
+    }
+
+    #[test]
+    fn shuffle_songs() {
+        let mut playlist = playlist(Repeat::Off);
+        playlist.shuffle_with(42);
+        let mut order = playlist.order.clone();
+        order.sort();
+        assert_eq!(order, [0, 1, 2]);
+        assert_eq!(playlist.position, 0);
+    }
+
+    #[test]
+    fn parse_repeat() {
+        assert_eq!("all".parse(), Ok(Repeat::All));
+        assert!("twice".parse::<Repeat>().is_err());
+    }
+}
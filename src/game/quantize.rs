@@ -0,0 +1,164 @@
+use std::str::FromStr;
+use std::time::Duration;
+use crate::game::notes_file::{self, FileNote};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Grid {
+    pub division: u32,
+    pub triplet: bool,
+}
+
+impl Grid {
+    pub fn step(&self, bpm: f32) -> Duration {
+        // A whole note lasts four beats
+        let whole_note = 4.0 * 60.0 / bpm;
+        let mut step = whole_note / self.division as f32;
+        if self.triplet {
+            step *= 2.0 / 3.0;
+        }
+        Duration::from_secs_f32(step)
+    }
+}
+
+impl FromStr for Grid {
+    type Err = String;
+
+    // Parses grids written as "1/8", "1/16" or "1/8t" for triplets
+    fn from_str(grid: &str) -> Result<Grid, String> {
+        let (fraction, triplet) = match grid.strip_suffix('t') {
+            Some(fraction) => (fraction, true),
+            None => (grid, false),
+        };
+        let division = fraction.strip_prefix("1/")
+            .and_then(|division| division.parse::<u32>().ok())
+            .filter(|&division| division > 0)
+            .ok_or_else(|| format!("Invalid grid \"{}\", expected something like 1/8, 1/16 or 1/8t", grid))?;
+
+        Ok(Grid {
+            division,
+            triplet,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quantizer {
+    step: Duration,
+    strength: f32,
+}
+
+impl Quantizer {
+    pub fn new(grid: Grid, bpm: f32, strength: f32) -> Quantizer {
+        Quantizer {
+            step: grid.step(bpm),
+            strength: (strength / 100.0).clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn quantize_time(&self, time: Duration) -> Duration {
+        let snapped = self.snap(time);
+        let time = time.as_secs_f64();
+        let offset = (snapped.as_secs_f64() - time) * self.strength as f64;
+        to_millis(time + offset)
+    }
+
+    pub fn quantize_duration(&self, duration: Duration) -> Duration {
+        // A duration of 0 means the note plays till its end, so leave it as is
+        if duration == Duration::from_millis(0) {
+            return duration;
+        }
+        let snapped = self.snap(duration).max(self.step);
+        let duration = duration.as_secs_f64();
+        let offset = (snapped.as_secs_f64() - duration) * self.strength as f64;
+        to_millis(duration + offset)
+    }
+
+    pub fn quantize_notes(&self, notes: &[FileNote]) -> Vec<FileNote> {
+        let start_times: Vec<Duration> = notes_file::start_times(notes)
+            .into_iter()
+            .map(|time| self.quantize_time(time))
+            .collect();
+
+        let mut quantized_notes: Vec<FileNote> = notes
+            .iter()
+            .map(|note| FileNote {
+                duration: self.quantize_duration(note.duration),
+                ..note.clone()
+            })
+            .collect();
+        notes_file::set_start_times(&mut quantized_notes, &start_times);
+        quantized_notes
+    }
+
+    fn snap(&self, time: Duration) -> Duration {
+        if self.step == Duration::from_millis(0) {
+            return time;
+        }
+        let steps = (time.as_secs_f64() / self.step.as_secs_f64()).round();
+        self.step.mul_f64(steps)
+    }
+}
+
+// Song files store whole milliseconds
+fn to_millis(seconds: f64) -> Duration {
+    Duration::from_millis((seconds * 1000.0).round() as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Grid, Quantizer, FileNote, Duration};
+
+    #[test]
+    fn parse_grid() {
+        assert_eq!("1/16".parse(), Ok(Grid { division: 16, triplet: false }));
+        assert_eq!("1/8t".parse(), Ok(Grid { division: 8, triplet: true }));
+        assert!("1/0".parse::<Grid>().is_err());
+        assert!("eighth".parse::<Grid>().is_err());
+    }
+
+    #[test]
+    fn grid_step() {
+        let grid = Grid { division: 8, triplet: false };
+        assert_eq!(grid.step(120.0), Duration::from_millis(250));
+        let grid = Grid { division: 8, triplet: true };
+        assert_eq!(grid.step(120.0).as_millis(), 166);
+    }
+
+    #[test]
+    fn quantize_time() {
+        let quantizer = Quantizer::new("1/8".parse().unwrap(), 120.0, 100.0);
+        assert_eq!(quantizer.quantize_time(Duration::from_millis(260)).as_millis(), 250);
+        assert_eq!(quantizer.quantize_time(Duration::from_millis(390)).as_millis(), 500);
+
+        let quantizer = Quantizer::new("1/8".parse().unwrap(), 120.0, 50.0);
+        assert_eq!(quantizer.quantize_time(Duration::from_millis(300)).as_millis(), 275);
+    }
+
+    #[test]
+    fn quantize_duration() {
+        let quantizer = Quantizer::new("1/8".parse().unwrap(), 120.0, 100.0);
+        assert_eq!(quantizer.quantize_duration(Duration::from_millis(0)).as_millis(), 0);
+        assert_eq!(quantizer.quantize_duration(Duration::from_millis(40)).as_millis(), 250);
+        assert_eq!(quantizer.quantize_duration(Duration::from_millis(480)).as_millis(), 500);
+    }
+
+    #[test]
+    fn quantize_notes() {
+        let quantizer = Quantizer::new("1/16".parse().unwrap(), 120.0, 100.0);
+        let notes: Vec<FileNote> = [(10, "a2", 120), (130, "b2", 0), (0, "c3", 110)]
+            .iter()
+            .map(|&(delay, base_note, duration)| FileNote {
+                delay: Duration::from_millis(delay),
+                base_note: base_note.to_string(),
+                duration: Duration::from_millis(duration),
+                track: None,
+            })
+            .collect();
+
+        let quantized: Vec<(u128, u128)> = quantizer.quantize_notes(&notes)
+            .iter()
+            .map(|note| (note.delay.as_millis(), note.duration.as_millis()))
+            .collect();
+        assert_eq!(quantized, vec![(0, 125), (125, 0), (0, 125)]);
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+use crossterm_style::Color;
+use crate::game::screen::{self, pianokeys, FallingNote, KeyboardLayout, Renderer, StatusBar, TerminalRenderer, TextRows};
+
+// Frames are drawn at most this often, however many notes are played in between
+const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+
+// Keys are told apart by their position on the full size keyboard and whether they are white
+type Key = (i16, bool);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Highlight {
+    color: Color,
+    expires: Instant,
+}
+
+// What the keyboard should look like right now
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Frame {
+    highlights: HashMap<Key, Vec<Highlight>>,
+}
+
+impl Frame {
+    pub fn mark(&mut self, position: i16, white: bool, color: Color, expires: Instant) {
+        self.highlights
+            .entry((position, white))
+            .or_default()
+            .push(Highlight { color, expires });
+    }
+
+    pub fn expire(&mut self, now: Instant) {
+        for highlights in self.highlights.values_mut() {
+            highlights.retain(|highlight| highlight.expires > now);
+        }
+        self.highlights.retain(|_, highlights| !highlights.is_empty());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.highlights.is_empty()
+    }
+
+    // Overlapping notes on a key show the one played last, and the key stays marked until
+    // every one of them has run out
+    pub fn key_colors(&self) -> HashMap<Key, Color> {
+        self.highlights
+            .iter()
+            .filter_map(|(&key, highlights)| highlights.last().map(|highlight| (key, highlight.color)))
+            .collect()
+    }
+}
+
+pub enum RenderEvent {
+    Mark {
+        position: i16,
+        white: bool,
+        color: Color,
+        duration: Duration,
+    },
+    // Draws the keyboard with a new layout, along with the keys marked on it
+    Draw(KeyboardLayout),
+    // Draws the whole screen again, such as after the terminal has been resized
+    Redraw(KeyboardLayout),
+    StatusBar(StatusBar),
+    // Notes coming up in the song being played, which replace the ones sent before
+    FallingNotes(Vec<FallingNote>),
+    PlaybackStatus(String),
+    PlaylistStatus(String),
+    // The current section and its lyrics, split into what has been sung and what is to come
+    SongText {
+        heading: String,
+        sung: String,
+        upcoming: String,
+    },
+    Library {
+        heading: String,
+        lines: Vec<String>,
+        selected: Option<usize>,
+    },
+    CloseLibrary,
+}
+
+// Hands drawing over to a single thread, which stops once this is dropped
+pub struct RenderThread {
+    events: Sender<RenderEvent>,
+}
+
+impl RenderThread {
+    pub fn start(layout: KeyboardLayout) -> RenderThread {
+        let (events, receiver) = mpsc::channel();
+        thread::spawn(move || render_loop(&receiver, layout, &mut TerminalRenderer::new()));
+        RenderThread { events }
+    }
+
+    pub fn send(&self, event: RenderEvent) {
+        // The render thread only stops once we're gone
+        self.events.send(event).unwrap_or_default();
+    }
+}
+
+// Everything the render thread keeps track of between frames
+struct RenderState {
+    frame: Frame,
+    layout: KeyboardLayout,
+    // Colors of the marked keys as of the last frame
+    drawn: HashMap<Key, Color>,
+    status_bar: Option<StatusBar>,
+    falling_notes: Vec<FallingNote>,
+    text_rows: TextRows,
+}
+
+impl RenderState {
+    fn new(layout: KeyboardLayout) -> RenderState {
+        RenderState {
+            frame: Frame::default(),
+            layout,
+            drawn: HashMap::new(),
+            status_bar: None,
+            falling_notes: Vec::new(),
+            text_rows: TextRows::default(),
+        }
+    }
+
+    fn apply(&mut self, event: RenderEvent, renderer: &mut dyn Renderer) {
+        match event {
+            RenderEvent::Mark { position, white, color, duration } => {
+                self.frame.mark(position, white, color, Instant::now() + duration);
+            }
+            RenderEvent::Draw(layout) => {
+                self.layout = layout;
+                pianokeys::draw(renderer, &self.layout).unwrap_or_default();
+                // Drawing the keys wipes out their marks, so all of them need painting again
+                self.drawn.clear();
+            }
+            RenderEvent::Redraw(layout) => {
+                self.layout = layout;
+                screen::redraw(renderer, &self.layout, &self.text_rows).unwrap_or_default();
+                if let Some(ref status_bar) = self.status_bar {
+                    screen::draw_status_bar(renderer, &self.layout, status_bar).unwrap_or_default();
+                }
+                self.draw_falling_notes(renderer);
+                self.drawn.clear();
+            }
+            RenderEvent::StatusBar(status_bar) => {
+                screen::draw_status_bar(renderer, &self.layout, &status_bar).unwrap_or_default();
+                self.status_bar = Some(status_bar);
+            }
+            RenderEvent::FallingNotes(falling_notes) => {
+                self.falling_notes = falling_notes;
+                self.draw_falling_notes(renderer);
+            }
+            RenderEvent::PlaybackStatus(status) => {
+                screen::draw_playback_status(renderer, &self.layout, &status).unwrap_or_default();
+                self.text_rows.playback_status = Some(status);
+            }
+            RenderEvent::PlaylistStatus(status) => {
+                screen::draw_playlist_status(renderer, &self.layout, &status).unwrap_or_default();
+                self.text_rows.playlist_status = Some(status);
+            }
+            RenderEvent::SongText { heading, sung, upcoming } => {
+                screen::draw_song_text(renderer, &heading, &sung, &upcoming).unwrap_or_default();
+                self.text_rows.song_text = Some((heading, sung, upcoming));
+            }
+            RenderEvent::Library { heading, lines, selected } => {
+                screen::draw_library(renderer, &self.layout, &heading, &lines, selected).unwrap_or_default();
+                self.text_rows.library = Some((heading, lines, selected));
+            }
+            RenderEvent::CloseLibrary => {
+                screen::clear_library(renderer, &self.layout).unwrap_or_default();
+                self.text_rows.library = None;
+            }
+        }
+    }
+
+    fn draw_falling_notes(&self, renderer: &mut dyn Renderer) {
+        if self.layout.falling_rows() > 0 {
+            screen::draw_falling_notes(renderer, &self.layout, &self.falling_notes).unwrap_or_default();
+        }
+    }
+
+    fn draw_frame(&mut self, renderer: &mut dyn Renderer) {
+        self.frame.expire(Instant::now());
+        let colors = self.frame.key_colors();
+        for (&(position, white), &color) in &colors {
+            if self.drawn.get(&(position, white)) != Some(&color) {
+                screen::paint_key(renderer, &self.layout, position, white, Some(color)).unwrap_or_default();
+            }
+        }
+        for &(position, white) in self.drawn.keys() {
+            if !colors.contains_key(&(position, white)) {
+                screen::paint_key(renderer, &self.layout, position, white, None).unwrap_or_default();
+            }
+        }
+        renderer.flush().unwrap_or_default();
+        self.drawn = colors;
+    }
+}
+
+fn render_loop(events: &Receiver<RenderEvent>, layout: KeyboardLayout, renderer: &mut dyn Renderer) {
+    let mut state = RenderState::new(layout);
+    loop {
+        let frame_start = Instant::now();
+        // Nothing will change on screen until the next event when no keys are marked
+        if state.frame.is_empty() && state.drawn.is_empty() {
+            match events.recv() {
+                Ok(event) => state.apply(event, renderer),
+                Err(_) => return,
+            }
+        }
+        loop {
+            match events.try_recv() {
+                Ok(event) => state.apply(event, renderer),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+        state.draw_frame(renderer);
+
+        thread::sleep(FRAME_INTERVAL.checked_sub(frame_start.elapsed()).unwrap_or_default());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Frame, RenderEvent, RenderState, Color, Duration, Instant};
+    use crate::game::screen::{CellGrid, KeyboardLayout};
+
+    #[test]
+    fn overlapping_marks() {
+        let start = Instant::now();
+        let mut frame = Frame::default();
+        frame.mark(64, true, Color::Blue, start + Duration::from_millis(500));
+        frame.mark(64, true, Color::Red, start + Duration::from_millis(200));
+        frame.mark(66, false, Color::Green, start + Duration::from_millis(100));
+        assert_eq!(frame.key_colors().get(&(64, true)), Some(&Color::Red));
+        assert_eq!(frame.key_colors().get(&(66, false)), Some(&Color::Green));
+
+        // The earlier note keeps the key marked after the later one has run out
+        frame.expire(start + Duration::from_millis(300));
+        assert_eq!(frame.key_colors().get(&(64, true)), Some(&Color::Blue));
+        assert_eq!(frame.key_colors().get(&(66, false)), None);
+
+        frame.expire(start + Duration::from_millis(500));
+        assert!(frame.is_empty());
+    }
+
+    #[test]
+    fn redraw_text_rows() {
+        let layout = KeyboardLayout::fit(200, 40, 2);
+        let mut grid = CellGrid::new(200, 40);
+        let mut state = RenderState::new(layout);
+        let shows = |grid: &CellGrid, text: &str| (0..40).any(|y| grid.row(y).contains(text));
+
+        state.apply(RenderEvent::PlaybackStatus(String::from("Paused 00:01.0")), &mut grid);
+        state.apply(RenderEvent::Library {
+            heading: String::from("Songs"),
+            lines: vec![String::from("song.yml")],
+            selected: Some(0),
+        }, &mut grid);
+        state.apply(RenderEvent::Redraw(layout), &mut grid);
+        assert!(shows(&grid, "Paused 00:01.0"));
+        assert!(shows(&grid, "> song.yml"));
+
+        state.apply(RenderEvent::CloseLibrary, &mut grid);
+        state.apply(RenderEvent::Redraw(layout), &mut grid);
+        assert!(shows(&grid, "Paused 00:01.0"));
+        assert!(!shows(&grid, "song.yml"));
+    }
+}
@@ -0,0 +1,115 @@
+use std::str::FromStr;
+use crate::game::notes;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Major,
+    Minor,
+    HarmonicMinor,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Locrian,
+}
+
+impl Mode {
+    // Semitones from the tonic to each degree of the scale
+    fn steps(self) -> [i16; 7] {
+        match self {
+            Mode::Major => [0, 2, 4, 5, 7, 9, 11],
+            Mode::Minor => [0, 2, 3, 5, 7, 8, 10],
+            Mode::HarmonicMinor => [0, 2, 3, 5, 7, 8, 11],
+            Mode::Dorian => [0, 2, 3, 5, 7, 9, 10],
+            Mode::Phrygian => [0, 1, 3, 5, 7, 8, 10],
+            Mode::Lydian => [0, 2, 4, 6, 7, 9, 11],
+            Mode::Mixolydian => [0, 2, 4, 5, 7, 9, 10],
+            Mode::Locrian => [0, 1, 3, 5, 6, 8, 10],
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Mode, String> {
+        match mode.to_lowercase().as_str() {
+            "major" | "ionian" => Ok(Mode::Major),
+            "minor" | "aeolian" => Ok(Mode::Minor),
+            "harmonic_minor" | "harmonic-minor" => Ok(Mode::HarmonicMinor),
+            "dorian" => Ok(Mode::Dorian),
+            "phrygian" => Ok(Mode::Phrygian),
+            "lydian" => Ok(Mode::Lydian),
+            "mixolydian" => Ok(Mode::Mixolydian),
+            "locrian" => Ok(Mode::Locrian),
+            _ => Err(format!("Unknown mode \"{}\"", mode)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scale {
+    // Semitones of the tonic above c
+    pub tonic: i16,
+    pub mode: Mode,
+}
+
+impl Scale {
+    // Moves a note to the same degree of another scale. Notes outside this scale keep their
+    // distance from the degree below them.
+    pub fn map_to(&self, semitone: i16, to: &Scale) -> i16 {
+        let relative = semitone - self.tonic;
+        let (octave, pitch) = (relative.div_euclid(12), relative.rem_euclid(12));
+        let from_steps = self.mode.steps();
+        let degree = from_steps.iter().rposition(|&step| step <= pitch).unwrap_or_default();
+        to.tonic + octave * 12 + to.mode.steps()[degree] + pitch - from_steps[degree]
+    }
+}
+
+impl FromStr for Scale {
+    type Err = String;
+
+    // Scales are written as a tonic and a mode, such as "c major" or "fs dorian"
+    fn from_str(scale: &str) -> Result<Scale, String> {
+        let mut parts = scale.split_whitespace();
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(tonic), Some(mode), None) => {
+                let tonic = notes::sound_to_semitone(&format!("{}0", tonic.to_lowercase()))
+                    .ok_or_else(|| format!("Unknown tonic \"{}\"", tonic))?;
+                Ok(Scale {
+                    tonic,
+                    mode: mode.parse()?,
+                })
+            }
+            _ => Err(format!("Invalid scale \"{}\", expected something like \"c major\" or \"a dorian\"", scale)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Mode, Scale};
+
+    #[test]
+    fn parse_scale() {
+        assert_eq!("fs dorian".parse(), Ok(Scale { tonic: 6, mode: Mode::Dorian }));
+        assert_eq!("A Minor".parse(), Ok(Scale { tonic: 9, mode: Mode::Minor }));
+        assert!("h major".parse::<Scale>().is_err());
+        assert!("c".parse::<Scale>().is_err());
+        assert!("c bebop".parse::<Scale>().is_err());
+    }
+
+    #[test]
+    fn map_between_scales() {
+        let c_major: Scale = "c major".parse().unwrap();
+        let c_minor: Scale = "c minor".parse().unwrap();
+        let d_dorian: Scale = "d dorian".parse().unwrap();
+        // e3 is the third degree of c major
+        assert_eq!(c_major.map_to(40, &c_minor), 39);
+        assert_eq!(c_major.map_to(40, &d_dorian), 41);
+        // fs3 sits a semitone above the fourth degree
+        assert_eq!(c_major.map_to(42, &c_minor), 42);
+        // b2 belongs to the octave below
+        assert_eq!(c_major.map_to(35, &c_minor), 34);
+    }
+}
@@ -0,0 +1,208 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Plays back timestamped events against the wall clock. Every deadline is computed from
+// a single anchor point, so time spent handling one batch never delays the ones after it.
+// A new scheduler stays paused at the beginning until `start` is called.
+#[derive(Debug)]
+pub struct Scheduler<T> {
+    times: Vec<Duration>,
+    events: Vec<T>,
+    index: usize,
+    tempo: f32,
+    anchor: Instant,
+    anchor_position: Duration,
+    paused: bool,
+}
+
+impl<T> Scheduler<T> {
+    pub fn new(mut timed_events: Vec<(Duration, T)>, tempo: f32) -> Scheduler<T> {
+        // Stable sort keeps simultaneous events in the order they were given
+        timed_events.sort_by_key(|&(time, _)| time);
+        let (times, events) = timed_events.into_iter().unzip();
+
+        Scheduler {
+            times,
+            events,
+            index: 0,
+            tempo,
+            anchor: Instant::now(),
+            anchor_position: Duration::from_millis(0),
+            paused: true,
+        }
+    }
+
+    pub fn start(&mut self) {
+        self.resume();
+    }
+
+    pub fn position(&self) -> Duration {
+        if self.paused {
+            return self.anchor_position;
+        }
+        self.anchor_position + self.anchor.elapsed().mul_f32(self.tempo)
+    }
+
+    pub fn tempo(&self) -> f32 {
+        self.tempo
+    }
+
+    pub fn set_tempo(&mut self, tempo: f32) {
+        let position = self.position();
+        self.tempo = tempo;
+        self.anchor_at(position);
+    }
+
+    pub fn seek(&mut self, position: Duration) {
+        self.anchor_at(position);
+        self.index = self.times
+            .iter()
+            .position(|&time| time >= position)
+            .unwrap_or(self.times.len());
+    }
+
+    pub fn pause(&mut self) {
+        if !self.paused {
+            let position = self.position();
+            self.paused = true;
+            self.anchor_at(position);
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if self.paused {
+            self.paused = false;
+            self.anchor = Instant::now();
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.index >= self.times.len()
+    }
+
+    pub fn events(&self) -> &[T] {
+        &self.events
+    }
+
+    // Events before the current position
+    pub fn played(&self) -> &[T] {
+        &self.events[..self.index]
+    }
+
+    pub fn time_until(&self, position: Duration) -> Duration {
+        match self.deadline(position) {
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+            None => Duration::from_millis(0),
+        }
+    }
+
+    // When the given position is reached, unless it has already been passed
+    fn deadline(&self, position: Duration) -> Option<Instant> {
+        position
+            .checked_sub(self.anchor_position)
+            .map(|song_time| self.anchor + song_time.div_f32(self.tempo))
+    }
+
+    pub fn time_until_next(&self) -> Option<Duration> {
+        self.times.get(self.index).map(|&time| self.time_until(time))
+    }
+
+    // Returns every event sharing the earliest pending timestamp, once its deadline has passed
+    pub fn poll(&mut self) -> Option<&[T]> {
+        if self.paused || self.time_until_next()? > Duration::from_millis(0) {
+            return None;
+        }
+        Some(self.take_batch())
+    }
+
+    // Blocks until the next batch is due. Returns None once all events have been played.
+    pub fn wait(&mut self) -> Option<&[T]> {
+        thread::sleep(self.time_until_next()?);
+        Some(self.take_batch())
+    }
+
+    fn take_batch(&mut self) -> &[T] {
+        let start = self.index;
+        let time = self.times[start];
+        while self.times.get(self.index) == Some(&time) {
+            self.index += 1;
+        }
+        &self.events[start..self.index]
+    }
+
+    fn anchor_at(&mut self, position: Duration) {
+        self.anchor = Instant::now();
+        self.anchor_position = position;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Scheduler, Duration};
+
+    fn scheduler() -> Scheduler<&'static str> {
+        Scheduler::new(vec![
+            (Duration::from_millis(20), "c3"),
+            (Duration::from_millis(0), "a2"),
+            (Duration::from_millis(20), "e3"),
+            (Duration::from_millis(40), "g3"),
+        ], 1.0)
+    }
+
+    #[test]
+    fn sort_events() {
+        let scheduler = scheduler();
+        assert_eq!(scheduler.events(), ["a2", "c3", "e3", "g3"]);
+    }
+
+    #[test]
+    fn poll_batches() {
+        let mut scheduler = scheduler();
+        scheduler.start();
+        assert_eq!(scheduler.poll(), Some(&["a2"][..]));
+        scheduler.seek(Duration::from_millis(20));
+        assert_eq!(scheduler.poll(), Some(&["c3", "e3"][..]));
+        scheduler.seek(Duration::from_millis(40));
+        assert_eq!(scheduler.poll(), Some(&["g3"][..]));
+        assert_eq!(scheduler.poll(), None);
+        assert!(scheduler.is_finished());
+    }
+
+    #[test]
+    fn poll_before_deadline() {
+        let mut scheduler = scheduler();
+        scheduler.start();
+        scheduler.seek(Duration::from_millis(1));
+        scheduler.set_tempo(0.001);
+        assert_eq!(scheduler.poll(), None);
+        assert_eq!(scheduler.played(), ["a2"]);
+    }
+
+    #[test]
+    fn seek_and_pause() {
+        let mut scheduler = scheduler();
+        scheduler.start();
+        scheduler.pause();
+        scheduler.seek(Duration::from_millis(20));
+        assert_eq!(scheduler.position(), Duration::from_millis(20));
+        assert_eq!(scheduler.played(), ["a2"]);
+        assert_eq!(scheduler.poll(), None);
+
+        scheduler.resume();
+        assert_eq!(scheduler.poll(), Some(&["c3", "e3"][..]));
+    }
+
+    #[test]
+    fn deadline_with_tempo() {
+        let mut scheduler = scheduler();
+        scheduler.set_tempo(2.0);
+        scheduler.seek(Duration::from_millis(1000));
+        assert_eq!(scheduler.deadline(Duration::from_millis(3000)), Some(scheduler.anchor + Duration::from_millis(1000)));
+        assert_eq!(scheduler.deadline(Duration::from_millis(500)), None);
+        assert_eq!(scheduler.time_until(Duration::from_millis(500)), Duration::from_millis(0));
+    }
+}
@@ -1,155 +1,689 @@
-use std::{thread, time};
+pub mod renderer;
 
 use crossterm::{
-    style,
     queue,
-    Colorize,
-    Goto,
-    PrintStyledFont,
+    RawScreen,
+    Result,
+    Show,
 };
 
 use crossterm_style::Color;
 
 use std::io::{stdout, Write};
+use std::str::FromStr;
+use std::time::Duration;
+use crossterm::KeyEvent;
+use crate::game::notes;
+pub use renderer::{Cell, CellGrid, Renderer, TerminalRenderer};
 
 /*
 █▒
 */
 
-pub mod pianokeys {
-    use crossterm::{
-        queue,
-        Colorize,
-        Goto,
-        PrintStyledFont,
-        Result,
-    };
+// Rows above the keyboard are left for the song's section and lyrics
+const KEYBOARD_TOP: u16 = 2;
+// Song time each row of falling notes stands for
+pub const FALLING_NOTE_ROW: Duration = Duration::from_millis(100);
+// Rows from the top of the keyboard down to the playlist status, which have to stay on screen
+const KEYBOARD_ROWS: u16 = 19;
+
+// White keys from a-1 to b7, each drawn as a border followed by the key itself
+const WHITE_KEYS: u16 = 58;
+// Columns of a white key in `Note::position`, which counts from the full size keyboard
+const POSITION_KEY_WIDTH: i16 = 3;
+const WHITE_KEY_NAMES: [&str; 7] = ["a", "b", "c", "d", "e", "f", "g"];
+
+// What to write on each piano key
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyLabels {
+    None,
+    // The PC keys that play it
+    Keys,
+    Notes,
+    Both,
+}
+
+impl FromStr for KeyLabels {
+    type Err = String;
+
+    fn from_str(labels: &str) -> std::result::Result<KeyLabels, String> {
+        match labels {
+            "none" => Ok(KeyLabels::None),
+            "keys" => Ok(KeyLabels::Keys),
+            "notes" => Ok(KeyLabels::Notes),
+            "both" => Ok(KeyLabels::Both),
+            _ => Err(format!("Invalid key labels \"{}\", expected none, keys, notes or both", labels)),
+        }
+    }
+}
+
+// Sound of a white key counted from a-1, with the octave number changing at each c
+fn white_key_sound(key: u16) -> String {
+    let octave = key as i16 / 7 - if key % 7 < 2 { 1 } else { 0 };
+    format!("{}{}", WHITE_KEY_NAMES[key as usize % 7], octave)
+}
+
+// Sound of the black key on the border to the left of a white key
+fn black_key_sound(border: u16) -> String {
+    let white_key = white_key_sound(border - 1);
+    format!("{}s{}", &white_key[..1], &white_key[1..])
+}
+
+// Keys on the keyboard's own layer come first, then the ones played with Shift and with Ctrl
+fn key_layer(key: &KeyEvent) -> u8 {
+    match *key {
+        KeyEvent::Ctrl(_) => 2,
+        KeyEvent::Char(c) if c.is_ascii_uppercase() || (c.is_ascii_punctuation() && !",./'[]".contains(c)) => 1,
+        _ => 0,
+    }
+}
 
-    use std::io::{stdout, Stdout, Write};
+// Whether a sound is played at `sequence` without holding Shift or Ctrl
+fn is_playable(sound: &str, sequence: i8) -> bool {
+    notes::base_note_to_keys(sound, sequence).iter().any(|key| key_layer(key) == 0)
+}
+
+// Which part of the keyboard fits into the terminal, and how wide its keys are drawn
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyboardLayout {
+    width: u16,
+    height: u16,
+    key_width: u16,
+    first_key: u16,
+    visible_keys: u16,
+    sequence: i8,
+    labels: KeyLabels,
+    // Rows between the lyrics and the keyboard where upcoming notes fall, if any
+    falling_rows: u16,
+}
+
+impl KeyboardLayout {
+    // Narrows the keys when the whole keyboard doesn't fit, and shows only the octaves around
+    // `sequence` when even that doesn't
+    pub fn fit(width: u16, height: u16, sequence: i8) -> KeyboardLayout {
+        for &key_width in &[3, 2] {
+            if WHITE_KEYS * key_width < width {
+                return KeyboardLayout {
+                    width,
+                    height,
+                    key_width,
+                    first_key: 0,
+                    visible_keys: WHITE_KEYS,
+                    sequence,
+                    labels: KeyLabels::None,
+                    falling_rows: 0,
+                };
+            }
+        }
+        // Leave a column on each side for the scroll indicators, and one for the closing border
+        let visible_keys = (width.saturating_sub(3) / 2).clamp(1, WHITE_KEYS);
+        // Keys played at `sequence` run from its a-1 to e+2, so center on the b in between
+        let center = 7 * sequence.max(0) as u16 + 9;
+        let first_key = center.saturating_sub(visible_keys / 2).min(WHITE_KEYS - visible_keys);
+        KeyboardLayout {
+            width,
+            height,
+            key_width: 2,
+            first_key,
+            visible_keys,
+            sequence,
+            labels: KeyLabels::None,
+            falling_rows: 0,
+        }
+    }
+
+    pub fn with_labels(self, labels: KeyLabels) -> KeyboardLayout {
+        KeyboardLayout { labels, ..self }
+    }
+
+    // Takes only as many falling rows as leave the keyboard and the status rows under it in view
+    pub fn with_falling_rows(self, rows: u16) -> KeyboardLayout {
+        let falling_rows = rows.min(self.height.saturating_sub(KEYBOARD_TOP + KEYBOARD_ROWS));
+        KeyboardLayout { falling_rows, ..self }
+    }
+
+    // Fits the same terminal again after the sequence has changed
+    pub fn refit(&self, sequence: i8) -> KeyboardLayout {
+        KeyboardLayout::fit(self.width, self.height, sequence)
+            .with_labels(self.labels)
+            .with_falling_rows(self.falling_rows)
+    }
+
+    pub fn falling_rows(&self) -> u16 {
+        self.falling_rows
+    }
+
+    fn keyboard_top(&self) -> u16 {
+        KEYBOARD_TOP + self.falling_rows
+    }
+
+    fn is_scrolled(&self) -> bool {
+        self.visible_keys < WHITE_KEYS
+    }
+
+    // Column where the border on the left of a white key is drawn
+    fn key_column(&self, key: u16) -> u16 {
+        let left = if self.is_scrolled() { 1 } else { 0 };
+        left + (key - self.first_key) * self.key_width
+    }
+
+    // Column to mark a note at, if it's in view. White keys are marked right of their border,
+    // while black keys sit on the border between two white keys.
+    pub fn note_column(&self, position: i16, white: bool) -> Option<u16> {
+        let last_key = self.first_key + self.visible_keys;
+        if white {
+            let key = (position - 1).div_euclid(POSITION_KEY_WIDTH);
+            if key >= self.first_key as i16 && key < last_key as i16 {
+                return Some(self.key_column(key as u16) + 1);
+            }
+        } else {
+            let border = position.div_euclid(POSITION_KEY_WIDTH);
+            if border > self.first_key as i16 && border < last_key as i16 {
+                return Some(self.key_column(border as u16));
+            }
+        }
+        None
+    }
+
+    fn white_key_fill(&self) -> &'static str {
+        if self.key_width == 3 { "██" } else { "█" }
+    }
+
+    // Whenever keys are labelled, the ones that can't be played at the current sequence without
+    // Shift or Ctrl are dimmed to show the range that can
+    fn is_dimmed(&self, sound: &str) -> bool {
+        self.labels != KeyLabels::None && !is_playable(sound, self.sequence)
+    }
+
+    fn white_key_color(&self, key: u16) -> Color {
+        if self.is_dimmed(&white_key_sound(key)) { Color::Grey } else { Color::White }
+    }
+
+    fn black_key_color(&self, border: u16) -> Color {
+        if self.is_dimmed(&black_key_sound(border)) { Color::DarkGrey } else { Color::Black }
+    }
+
+    // Characters written down a key, paired with whether they belong to the note name
+    fn key_label(&self, sound: &str) -> Vec<(char, bool)> {
+        let mut label = Vec::new();
+        if self.labels == KeyLabels::Notes || self.labels == KeyLabels::Both {
+            label.extend(sound.chars().map(|symbol| (symbol, true)));
+        }
+        if self.labels == KeyLabels::Keys || self.labels == KeyLabels::Both {
+            let mut keys = notes::base_note_to_keys(sound, self.sequence);
+            keys.sort_by_key(key_layer);
+            for key in keys {
+                match key {
+                    KeyEvent::Ctrl(c) => label.extend(&[('^', false), (c, false)]),
+                    KeyEvent::Char(c) => label.push((c, false)),
+                    _ => {}
+                }
+            }
+        }
+        label
+    }
+}
+
+pub mod pianokeys {
+    use crossterm::Result;
+    use crossterm_style::Color;
+    use super::{KeyboardLayout, KeyLabels, Renderer};
 
     struct Point {
         x: u16,
         y: u16,
     }
 
-    pub fn draw() -> Result<()> {
-        let mut stdout = stdout();
-        print_whites(&mut stdout)?;
-        print_blacks(&mut stdout)?;
-        stdout.flush()?;
+    pub fn draw(renderer: &mut dyn Renderer, layout: &KeyboardLayout) -> Result<()> {
+        print_whites(layout, renderer)?;
+        print_blacks(layout, renderer)?;
+        print_scroll_indicators(layout, renderer)?;
+        if layout.labels != KeyLabels::None {
+            print_labels(layout, renderer)?;
+        }
+        renderer.flush()?;
         Ok(())
     }
 
-    fn print_whitekey(initial_point: Point, stdout: &mut Stdout) -> Result<()> {
+    fn print_whitekey(initial_point: Point, layout: &KeyboardLayout, color: Color, renderer: &mut dyn Renderer) -> Result<()> {
         let key_height: u16 = 16;
 
         for column_height in 0..key_height {
-            queue!(
-                stdout,
-                Goto(initial_point.x, initial_point.y + column_height),
-                PrintStyledFont("|".black().on_white())
-            )?;
-            queue!(
-                stdout,
-                Goto(initial_point.x + 1, initial_point.y + column_height),
-                PrintStyledFont("██".white())
-            )?;
-            queue!(
-                stdout,
-                Goto(initial_point.x + 3, initial_point.y + column_height),
-                PrintStyledFont("|".black())
-            )?;
+            let y = initial_point.y + column_height;
+            renderer.print(initial_point.x, y, "|", Some(Color::Black), Some(Color::White))?;
+            renderer.print(initial_point.x + 1, y, layout.white_key_fill(), Some(color), None)?;
+            renderer.print(initial_point.x + layout.key_width, y, "|", Some(Color::Black), None)?;
         }
         Ok(())
     }
 
-    fn print_whites(stdout: &mut Stdout) -> Result<()> {
-        for key in 0..58 {
-            let initial_point = Point { x: key * 3, y: 0 };
-            print_whitekey(initial_point, stdout)?;
+    fn print_whites(layout: &KeyboardLayout, renderer: &mut dyn Renderer) -> Result<()> {
+        for key in layout.first_key..layout.first_key + layout.visible_keys {
+            let initial_point = Point { x: layout.key_column(key), y: layout.keyboard_top() };
+            print_whitekey(initial_point, layout, layout.white_key_color(key), renderer)?;
         }
         Ok(())
     }
 
-    fn print_blackkey(initial_point: Point, stdout: &mut Stdout) -> Result<()> {
+    fn print_blackkey(initial_point: Point, color: Color, renderer: &mut dyn Renderer) -> Result<()> {
         let key_height = 9;
         for column_height in 0..key_height {
-            queue!(
-                stdout,
-                Goto(initial_point.x, initial_point.y + column_height),
-                PrintStyledFont("█".black())
-            )?;
+            renderer.print(initial_point.x, initial_point.y + column_height, "█", Some(color), None)?;
         }
         Ok(())
     }
 
-    fn print_blacks(stdout: &mut Stdout) -> Result<()> {
-        // First black key is lonely
-        let mut initial_point = Point { x: 3, y: 0 };
-        print_blackkey(initial_point, stdout)?;
+    fn print_blacks(layout: &KeyboardLayout, renderer: &mut dyn Renderer) -> Result<()> {
+        // Black keys follow a, c, d, f and g, which are the white keys 0, 2, 3, 5 and 6 of
+        // each group of seven starting from a-1
+        for key in layout.first_key..layout.first_key + layout.visible_keys {
+            let border = key as i16 + 1;
+            if [0, 2, 3, 5, 6].contains(&(key % 7)) && border < super::WHITE_KEYS as i16 {
+                if let Some(x) = layout.note_column(border * super::POSITION_KEY_WIDTH, false) {
+                    print_blackkey(Point { x, y: layout.keyboard_top() }, layout.black_key_color(border as u16), renderer)?;
+                }
+            }
+        }
+        Ok(())
+    }
 
-        for x in 0..8 {
-            let g1k1 = x * 21 + 9;
-            let g1k2 = g1k1 + 3;
-            initial_point = Point { x: g1k1, y: 0 };
-            print_blackkey(initial_point, stdout)?;
-            initial_point = Point { x: g1k2, y: 0 };
-            print_blackkey(initial_point, stdout)?;
+    // Labels run down the keys, below the black keys on white keys and above the marks on both
+    fn print_labels(layout: &KeyboardLayout, renderer: &mut dyn Renderer) -> Result<()> {
+        for key in layout.first_key..layout.first_key + layout.visible_keys {
+            let x = layout.key_column(key) + 1;
+            let background = layout.white_key_color(key);
+            for (row, (symbol, name)) in layout.key_label(&super::white_key_sound(key)).into_iter().take(6).enumerate() {
+                let foreground = if name { Color::DarkGrey } else { Color::Black };
+                renderer.print(x, layout.keyboard_top() + 9 + row as u16, &symbol.to_string(), Some(foreground), Some(background))?;
+            }
 
-            let g2k1 = g1k2 + 6;
-            let g2k2 = g2k1 + 3;
-            let g2k3 = g2k2 + 3;
-            initial_point = Point { x: g2k1, y: 0 };
-            print_blackkey(initial_point, stdout)?;
-            initial_point = Point { x: g2k2, y: 0 };
-            print_blackkey(initial_point, stdout)?;
-            initial_point = Point { x: g2k3, y: 0 };
-            print_blackkey(initial_point, stdout)?;
+            let border = key + 1;
+            if [0, 2, 3, 5, 6].contains(&(key % 7)) && border < super::WHITE_KEYS {
+                if let Some(x) = layout.note_column(border as i16 * super::POSITION_KEY_WIDTH, false) {
+                    for (row, (symbol, name)) in layout.key_label(&super::black_key_sound(border)).into_iter().take(8).enumerate() {
+                        let foreground = if name { Color::Grey } else { Color::White };
+                        renderer.print(x, layout.keyboard_top() + row as u16, &symbol.to_string(), Some(foreground), Some(layout.black_key_color(border)))?;
+                    }
+                }
+            }
         }
+        Ok(())
+    }
 
+    // Arrows beside the keyboard show that there are more keys in that direction
+    fn print_scroll_indicators(layout: &KeyboardLayout, renderer: &mut dyn Renderer) -> Result<()> {
+        if !layout.is_scrolled() {
+            return Ok(());
+        }
+        let right = layout.key_column(layout.first_key + layout.visible_keys) + 1;
+        let left_indicator = if layout.first_key > 0 { "<" } else { " " };
+        let right_indicator = if layout.first_key + layout.visible_keys < super::WHITE_KEYS { ">" } else { " " };
+        for row in 0..16 {
+            renderer.print(0, layout.keyboard_top() + row, left_indicator, None, None)?;
+            renderer.print(right, layout.keyboard_top() + row, right_indicator, None, None)?;
+        }
         Ok(())
     }
 }
 
-pub fn mark_note(pos: i16, white: bool, color: Color, duration: time::Duration) {
-    if white {
-        // This causes a compiler panic!
-        /* queue!( */
-        /*     stdout(), */
-        /*     Goto(pos as u16, 15), */
-        /*     PrintStyledFont(StyledObject("██").with(color)) */
-        /* ).unwrap(); */
+// Paints the bottom of a played key in `color`, or back in the key's own color once no longer
+// played. Output is held back until the renderer is flushed.
+pub fn paint_key(renderer: &mut dyn Renderer, layout: &KeyboardLayout, position: i16, white: bool, color: Option<Color>) -> Result<()> {
+    match layout.note_column(position, white) {
+        Some(column) if white => {
+            let key = (position - 1).div_euclid(POSITION_KEY_WIDTH) as u16;
+            let color = color.unwrap_or_else(|| layout.white_key_color(key));
+            renderer.print(column, layout.keyboard_top() + 15, layout.white_key_fill(), Some(color), None)
+        }
+        Some(column) => {
+            let border = position.div_euclid(POSITION_KEY_WIDTH) as u16;
+            let color = color.unwrap_or_else(|| layout.black_key_color(border));
+            renderer.print(column, layout.keyboard_top() + 8, "█", Some(color), None)
+        }
+        // Scrolled out of view
+        None => Ok(()),
+    }
+}
+
+// A note on its way down to its key, which has started already once `starts_in` is zero
+#[derive(Debug, Clone, PartialEq)]
+pub struct FallingNote {
+    pub position: i16,
+    pub white: bool,
+    pub color: Color,
+    pub starts_in: Duration,
+    pub ends_in: Duration,
+}
 
-        queue!(
-            stdout(),
-            Goto(pos as u16, 15),
-            PrintStyledFont(style("██").with(color))
-        ).unwrap();
+// Draws notes as bars falling towards their keys, each as long as the note plays for. Notes
+// reach the keyboard when they start and are gone once they end.
+pub fn draw_falling_notes(renderer: &mut dyn Renderer, layout: &KeyboardLayout, notes: &[FallingNote]) -> Result<()> {
+    let rows = layout.falling_rows;
+    for row in 0..rows {
+        renderer.clear_row(KEYBOARD_TOP + row)?;
+    }
+    let row_millis = FALLING_NOTE_ROW.as_millis();
+    for note in notes {
+        let column = match layout.note_column(note.position, note.white) {
+            Some(column) => column,
+            None => continue,
+        };
+        let fill = if note.white { layout.white_key_fill() } else { "█" };
+        // Counting rows up from the keyboard, with even the shortest notes taking up one
+        let first = note.starts_in.as_millis() / row_millis;
+        let last = note.ends_in.as_millis().div_ceil(row_millis).max(first + 1);
+        for row in first..last.min(rows as u128) {
+            renderer.print(column, KEYBOARD_TOP + rows - 1 - row as u16, fill, Some(note.color), None)?;
+        }
+    }
+    renderer.flush()
+}
 
-    /* println!("{} Red foreground text", Colored::Fg(Color::Red)); */
-    } else {
-        queue!(
-            stdout(),
-            Goto(pos as u16, 8),
-            PrintStyledFont(style("█").with(color))
-        ).unwrap();
+// Settings shown in the status bar under the keyboard
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusBar {
+    pub volume: f32,
+    pub sequence: i8,
+    pub sound_duration: Duration,
+    pub recording: bool,
+    pub players: usize,
+    pub color: Color,
+}
+
+pub fn draw_status_bar(renderer: &mut dyn Renderer, layout: &KeyboardLayout, status_bar: &StatusBar) -> Result<()> {
+    let row = layout.keyboard_top() + 16;
+    // Notes without a duration play until their sound file ends
+    let sound_duration = match status_bar.sound_duration.as_millis() {
+        0 => String::from("full"),
+        millis => format!("{}ms", millis),
+    };
+    let settings = format!(
+        "Volume {:.1}  Sequence {}  Note duration {}  Players {}  Color ",
+        status_bar.volume,
+        status_bar.sequence,
+        sound_duration,
+        status_bar.players,
+    );
+    renderer.clear_row(row)?;
+    renderer.print(0, row, &settings, None, None)?;
+    let mut x = settings.chars().count() as u16;
+    renderer.print(x, row, "██", Some(status_bar.color), None)?;
+    x += 2;
+    if status_bar.recording {
+        renderer.print(x, row, "  ● REC", Some(Color::Red), None)?;
     }
+    renderer.flush()
+}
 
-    thread::spawn(move || {
-        thread::sleep(duration);
-        if white {
-        queue!(
-            stdout(),
-            Goto(pos as u16, 15),
-            PrintStyledFont("██".white())
-        ).unwrap();
+// Text shown around the keyboard, kept so that it can be drawn again after the terminal is resized
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextRows {
+    pub playback_status: Option<String>,
+    pub playlist_status: Option<String>,
+    pub song_text: Option<(String, String, String)>,
+    pub library: Option<(String, Vec<String>, Option<usize>)>,
+}
+
+pub fn draw_playback_status(renderer: &mut dyn Renderer, layout: &KeyboardLayout, status: &str) -> Result<()> {
+    draw_status(renderer, layout.keyboard_top() + 17, status)
+}
+
+// Shows the song being played from a playlist below the playback status
+pub fn draw_playlist_status(renderer: &mut dyn Renderer, layout: &KeyboardLayout, status: &str) -> Result<()> {
+    draw_status(renderer, layout.keyboard_top() + 18, status)
+}
+
+fn draw_status(renderer: &mut dyn Renderer, row: u16, status: &str) -> Result<()> {
+    renderer.clear_row(row)?;
+    renderer.print(0, row, status, None, None)
+}
+
+// Shows the current section above the keyboard, with the lyrics sung so far highlighted
+pub fn draw_song_text(renderer: &mut dyn Renderer, heading: &str, sung: &str, upcoming: &str) -> Result<()> {
+    renderer.clear_row(0)?;
+    renderer.print(0, 0, heading, None, None)?;
+    renderer.clear_row(1)?;
+    renderer.print(0, 1, sung, Some(Color::Yellow), None)?;
+    renderer.print(sung.chars().count() as u16, 1, upcoming, None, None)
+}
+
+// Lists songs below the playback status, with the selected one highlighted
+pub fn draw_library(renderer: &mut dyn Renderer, layout: &KeyboardLayout, heading: &str, lines: &[String], selected: Option<usize>) -> Result<()> {
+    clear_library(renderer, layout)?;
+    renderer.print(0, layout.keyboard_top() + 19, heading, None, None)?;
+    for (index, line) in lines.iter().enumerate() {
+        let y = layout.keyboard_top() + 20 + index as u16;
+        if Some(index) == selected {
+            renderer.print(0, y, &format!("> {}", line), Some(Color::Black), Some(Color::White))?;
         } else {
-        queue!(
-            stdout(),
-            Goto(pos as u16, 8),
-            PrintStyledFont("█".black())
-        ).unwrap();
+            renderer.print(0, y, &format!("  {}", line), None, None)?;
         }
-    });
+    }
+    Ok(())
 }
 
+pub fn clear_library(renderer: &mut dyn Renderer, layout: &KeyboardLayout) -> Result<()> {
+    renderer.clear_below(layout.keyboard_top() + 19)
+}
+
+// Draws everything again from scratch, such as after the terminal has been resized
+pub fn redraw(renderer: &mut dyn Renderer, layout: &KeyboardLayout, text_rows: &TextRows) -> Result<()> {
+    renderer.clear()?;
+    pianokeys::draw(renderer, layout)?;
+    if let Some((ref heading, ref sung, ref upcoming)) = text_rows.song_text {
+        draw_song_text(renderer, heading, sung, upcoming)?;
+    }
+    if let Some(ref status) = text_rows.playback_status {
+        draw_playback_status(renderer, layout, status)?;
+    }
+    if let Some(ref status) = text_rows.playlist_status {
+        draw_playlist_status(renderer, layout, status)?;
+    }
+    if let Some((ref heading, ref lines, selected)) = text_rows.library {
+        draw_library(renderer, layout, heading, lines, selected)?;
+    }
+    renderer.flush()
+}
+
+// Puts the terminal back the way the game found it. This is safe to call more than once, so it
+// can be used on quitting as well as from a panic or a signal.
+pub fn restore_terminal() {
+    let mut stdout = stdout();
+    // Leave the alternate screen
+    write!(stdout, "\x1B[?1049l").unwrap_or_default();
+    queue!(stdout, Show).unwrap_or_default();
+    stdout.flush().unwrap_or_default();
+    RawScreen::disable_raw_mode().unwrap_or_default();
+}
+
+#[cfg(test)]
+mod test {
+    use super::{pianokeys, CellGrid, FallingNote, KeyboardLayout, KeyLabels, Renderer, KEYBOARD_TOP};
+    use crate::game::Note;
+    use crossterm_style::Color;
+    use std::time::Duration;
+
+    fn note_column(layout: &KeyboardLayout, sound: &str) -> Option<u16> {
+        let note = Note::from(sound, Color::Blue, Duration::from_millis(0)).unwrap();
+        layout.note_column(note.position, note.white)
+    }
+
+    #[test]
+    fn fit_whole_keyboard() {
+        let layout = KeyboardLayout::fit(200, 40, 2);
+        assert_eq!(layout, KeyboardLayout { width: 200, height: 40, key_width: 3, first_key: 0, visible_keys: 58, sequence: 2, labels: KeyLabels::None, falling_rows: 0 });
+        assert_eq!(note_column(&layout, "a-1"), Some(1));
+        assert_eq!(note_column(&layout, "a2"), Some(64));
+        assert_eq!(note_column(&layout, "cs0"), Some(9));
+
+        let layout = KeyboardLayout::fit(120, 40, 2);
+        assert_eq!(layout, KeyboardLayout { width: 120, height: 40, key_width: 2, first_key: 0, visible_keys: 58, sequence: 2, labels: KeyLabels::None, falling_rows: 0 });
+        assert_eq!(note_column(&layout, "a2"), Some(43));
+        assert_eq!(note_column(&layout, "b7"), Some(115));
+        assert_eq!(note_column(&layout, "as7"), Some(114));
+    }
+
+    #[test]
+    fn scroll_to_sequence() {
+        let layout = KeyboardLayout::fit(80, 40, 2);
+        assert_eq!(layout, KeyboardLayout { width: 80, height: 40, key_width: 2, first_key: 4, visible_keys: 38, sequence: 2, labels: KeyLabels::None, falling_rows: 0 });
+        // The keys played at sequence 2 stay in view
+        assert_eq!(note_column(&layout, "a1"), Some(22));
+        assert_eq!(note_column(&layout, "as1"), Some(23));
+        assert_eq!(note_column(&layout, "e4"), Some(58));
+        assert_eq!(note_column(&layout, "a-1"), None);
+
+        assert_eq!(KeyboardLayout::fit(80, 40, 0).first_key, 0);
+        assert_eq!(KeyboardLayout::fit(80, 40, 6).first_key, 20);
+    }
+
+    #[test]
+    fn fit_falling_rows() {
+        // The keyboard and the status rows under it take up 21 of the 30 rows
+        let layout = KeyboardLayout::fit(200, 30, 2).with_labels(KeyLabels::Keys).with_falling_rows(50);
+        assert_eq!(layout.falling_rows(), 9);
+        assert_eq!(layout.refit(3).falling_rows(), 9);
+        assert_eq!(KeyboardLayout::fit(200, 10, 2).with_falling_rows(5).falling_rows(), 0);
+
+        let mut grid = CellGrid::new(200, 30);
+        pianokeys::draw(&mut grid, &layout).unwrap();
+        assert_eq!(grid.row(KEYBOARD_TOP + 9 + 15).chars().count(), 175);
+        super::draw_playlist_status(&mut grid, &layout, "Song 1 of 2").unwrap();
+        assert_eq!(grid.row(29), "Song 1 of 2");
+    }
+
+    fn drawn_keyboard(width: u16, sequence: i8) -> (KeyboardLayout, CellGrid) {
+        let layout = KeyboardLayout::fit(width, 40, sequence);
+        let mut grid = CellGrid::new(width, 24);
+        pianokeys::draw(&mut grid, &layout).unwrap();
+        (layout, grid)
+    }
+
+    #[test]
+    fn draw_keyboard() {
+        let (_, grid) = drawn_keyboard(200, 2);
+        // Black keys cover the borders between white keys, down to their ninth row
+        let row_start = |y: u16| -> String { grid.row(y).chars().take(24).collect() };
+        assert_eq!(row_start(KEYBOARD_TOP), "|█████|████████|████████");
+        assert_eq!(row_start(KEYBOARD_TOP + 15), "|██".repeat(8));
+        assert_eq!(grid.cell(3, KEYBOARD_TOP + 8).unwrap().foreground, Some(Color::Black));
+        assert_eq!(grid.cell(3, KEYBOARD_TOP + 9).unwrap().symbol, '|');
+        assert_eq!(grid.row(KEYBOARD_TOP + 15).chars().count(), 175);
+
+        let (_, grid) = drawn_keyboard(30, 2);
+        assert_eq!(grid.row(KEYBOARD_TOP + 15), format!("<{}|>", "|█".repeat(13)));
+    }
+
+    #[test]
+    fn draw_highlights() {
+        let (layout, mut grid) = drawn_keyboard(200, 2);
+        for sound in &["a2", "as2"] {
+            let note = Note::from(sound, Color::Red, Duration::from_millis(0)).unwrap();
+            super::paint_key(&mut grid, &layout, note.position, note.white, Some(note.color)).unwrap();
+        }
+        assert_eq!(grid.cell(64, KEYBOARD_TOP + 15).unwrap().foreground, Some(Color::Red));
+        assert_eq!(grid.cell(65, KEYBOARD_TOP + 15).unwrap().foreground, Some(Color::Red));
+        assert_eq!(grid.cell(66, KEYBOARD_TOP + 8).unwrap().foreground, Some(Color::Red));
+        assert_eq!(grid.cell(66, KEYBOARD_TOP + 15).unwrap().foreground, Some(Color::Black));
+
+        super::paint_key(&mut grid, &layout, 64, true, None).unwrap();
+        assert_eq!(grid.cell(64, KEYBOARD_TOP + 15).unwrap().foreground, Some(Color::White));
+    }
+
+    #[test]
+    fn draw_status() {
+        let mut grid = CellGrid::new(40, 24);
+        super::draw_status(&mut grid, KEYBOARD_TOP + 17, "Playing 0:05 / 1:30").unwrap();
+        super::draw_status(&mut grid, KEYBOARD_TOP + 17, "Paused 0:06").unwrap();
+        assert_eq!(grid.row(KEYBOARD_TOP + 17), "Paused 0:06");
+
+        super::draw_song_text(&mut grid, "[Verse]", "Twin-kle ", "twin-kle").unwrap();
+        assert_eq!(grid.row(0), "[Verse]");
+        assert_eq!(grid.row(1), "Twin-kle twin-kle");
+        assert_eq!(grid.cell(0, 1).unwrap().foreground, Some(Color::Yellow));
+        assert_eq!(grid.cell(9, 1).unwrap().foreground, None);
+        grid.clear().unwrap();
+        assert_eq!(grid.row(0), "");
+    }
+
+    #[test]
+    fn draw_status_bar() {
+        let mut status_bar = super::StatusBar {
+            volume: 0.5,
+            sequence: 3,
+            sound_duration: Duration::from_millis(0),
+            recording: true,
+            players: 2,
+            color: Color::Green,
+        };
+        let layout = KeyboardLayout::fit(100, 40, 3);
+        let mut grid = CellGrid::new(100, 24);
+        super::draw_status_bar(&mut grid, &layout, &status_bar).unwrap();
+        let row = KEYBOARD_TOP + 16;
+        assert_eq!(grid.row(row), "Volume 0.5  Sequence 3  Note duration full  Players 2  Color ██  ● REC");
+        assert_eq!(grid.cell(61, row).unwrap().foreground, Some(Color::Green));
+
+        status_bar.recording = false;
+        status_bar.sound_duration = Duration::from_millis(350);
+        super::draw_status_bar(&mut grid, &layout, &status_bar).unwrap();
+        assert_eq!(grid.row(row), "Volume 0.5  Sequence 3  Note duration 350ms  Players 2  Color ██");
+    }
+
+    #[test]
+    fn draw_key_labels() {
+        let layout = KeyboardLayout::fit(200, 40, 2).with_labels(KeyLabels::Both);
+        let mut grid = CellGrid::new(200, 24);
+        pianokeys::draw(&mut grid, &layout).unwrap();
+        let column = |x: u16, rows: std::ops::Range<u16>| -> String {
+            rows.map(|y| grid.cell(x, KEYBOARD_TOP + y).unwrap().symbol).collect()
+        };
+        // a1 is played with z, and with Ctrl and the key for a2
+        assert_eq!(column(43, 9..15), "a1z^q█");
+        assert_eq!(grid.cell(43, KEYBOARD_TOP + 9).unwrap().foreground, Some(Color::DarkGrey));
+        assert_eq!(grid.cell(43, KEYBOARD_TOP + 11).unwrap().background, Some(Color::White));
+        // Only Shift reaches f4, so it's dimmed
+        assert_eq!(column(100, 9..15), "f4Y███");
+        assert_eq!(grid.cell(100, KEYBOARD_TOP + 14).unwrap().foreground, Some(Color::Grey));
+        // gs1 sits above the a key
+        assert_eq!(column(42, 0..9), "gs1a^k███");
+
+        super::paint_key(&mut grid, &layout, 100, true, None).unwrap();
+        assert_eq!(grid.cell(100, KEYBOARD_TOP + 15).unwrap().foreground, Some(Color::Grey));
+        // Black keys out of reach are dimmed the same way
+        assert_eq!(grid.cell(42, KEYBOARD_TOP + 8).unwrap().foreground, Some(Color::Black));
+        let column = note_column(&layout, "cs6").unwrap();
+        assert_eq!(grid.cell(column, KEYBOARD_TOP + 8).unwrap().foreground, Some(Color::DarkGrey));
+        let note = Note::from("cs6", Color::Red, Duration::from_millis(0)).unwrap();
+        super::paint_key(&mut grid, &layout, note.position, note.white, None).unwrap();
+        assert_eq!(grid.cell(column, KEYBOARD_TOP + 8).unwrap().foreground, Some(Color::DarkGrey));
+        assert_eq!("keys".parse(), Ok(KeyLabels::Keys));
+    }
+
+    #[test]
+    fn draw_falling_notes() {
+        let layout = KeyboardLayout::fit(200, 40, 2).with_falling_rows(10);
+        let mut grid = CellGrid::new(200, 40);
+        let falling_note = |position, white, starts_in, ends_in| FallingNote {
+            position,
+            white,
+            color: Color::Red,
+            starts_in: Duration::from_millis(starts_in),
+            ends_in: Duration::from_millis(ends_in),
+        };
+        let notes = [
+            falling_note(64, true, 0, 250),
+            falling_note(63, false, 850, 900),
+            // Too far off to show yet
+            falling_note(70, true, 1500, 2000),
+        ];
+        super::draw_falling_notes(&mut grid, &layout, &notes).unwrap();
+        let column = |x: u16| -> String {
+            (0..10).map(|y| grid.cell(x, KEYBOARD_TOP + y).unwrap().symbol).collect()
+        };
+        assert_eq!(column(64), "       ███");
+        assert_eq!(column(65), "       ███");
+        assert_eq!(column(63), " █        ");
+        assert_eq!(grid.cell(63, KEYBOARD_TOP + 1).unwrap().foreground, Some(Color::Red));
+        assert_eq!(column(70), "          ");
+    }
+}
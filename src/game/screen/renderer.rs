@@ -0,0 +1,201 @@
+use std::io::{stdout, Stdout, Write};
+use crossterm::{
+    style,
+    queue,
+    Clear,
+    ClearType,
+    Goto,
+    Output,
+    PrintStyledFont,
+    Result,
+};
+use crossterm_style::Color;
+
+// Somewhere to draw the screen, so that drawing can be checked without a terminal
+pub trait Renderer {
+    // Writes `text` from column `x` of row `y`, leaving out colors to keep the terminal's own
+    fn print(&mut self, x: u16, y: u16, text: &str, foreground: Option<Color>, background: Option<Color>) -> Result<()>;
+    fn clear_row(&mut self, y: u16) -> Result<()>;
+    // Clears row `y` along with every row below it
+    fn clear_below(&mut self, y: u16) -> Result<()>;
+    fn clear(&mut self) -> Result<()>;
+    fn flush(&mut self) -> Result<()>;
+}
+
+// Draws to the terminal, holding the output back until it's flushed
+pub struct TerminalRenderer {
+    stdout: Stdout,
+}
+
+impl TerminalRenderer {
+    pub fn new() -> TerminalRenderer {
+        TerminalRenderer {
+            stdout: stdout(),
+        }
+    }
+}
+
+impl Default for TerminalRenderer {
+    fn default() -> TerminalRenderer {
+        TerminalRenderer::new()
+    }
+}
+
+impl Renderer for TerminalRenderer {
+    fn print(&mut self, x: u16, y: u16, text: &str, foreground: Option<Color>, background: Option<Color>) -> Result<()> {
+        queue!(self.stdout, Goto(x, y))?;
+        if foreground.is_none() && background.is_none() {
+            queue!(self.stdout, Output(text.to_string()))?;
+            return Ok(());
+        }
+        let mut styled_text = style(text);
+        if let Some(foreground) = foreground {
+            styled_text = styled_text.with(foreground);
+        }
+        if let Some(background) = background {
+            styled_text = styled_text.on(background);
+        }
+        queue!(self.stdout, PrintStyledFont(styled_text))?;
+        Ok(())
+    }
+
+    fn clear_row(&mut self, y: u16) -> Result<()> {
+        queue!(self.stdout, Goto(0, y), Clear(ClearType::CurrentLine))?;
+        Ok(())
+    }
+
+    fn clear_below(&mut self, y: u16) -> Result<()> {
+        queue!(self.stdout, Goto(0, y), Clear(ClearType::FromCursorDown))?;
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        queue!(self.stdout, Clear(ClearType::All))?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.stdout.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cell {
+    pub symbol: char,
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+}
+
+impl Default for Cell {
+    fn default() -> Cell {
+        Cell {
+            symbol: ' ',
+            foreground: None,
+            background: None,
+        }
+    }
+}
+
+// Keeps what's drawn in memory, cutting off anything outside of it like a terminal would
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellGrid {
+    width: u16,
+    height: u16,
+    cells: Vec<Cell>,
+}
+
+impl CellGrid {
+    pub fn new(width: u16, height: u16) -> CellGrid {
+        CellGrid {
+            width,
+            height,
+            cells: vec![Cell::default(); width as usize * height as usize],
+        }
+    }
+
+    pub fn cell(&self, x: u16, y: u16) -> Option<&Cell> {
+        if x < self.width && y < self.height {
+            self.cells.get(y as usize * self.width as usize + x as usize)
+        } else {
+            None
+        }
+    }
+
+    // Symbols on a row, without the blank cells at its end
+    pub fn row(&self, y: u16) -> String {
+        let row: String = (0..self.width)
+            .filter_map(|x| self.cell(x, y).map(|cell| cell.symbol))
+            .collect();
+        row.trim_end().to_string()
+    }
+
+    fn clear_rows(&mut self, rows: std::ops::Range<u16>) {
+        for y in rows.start..rows.end.min(self.height) {
+            let start = y as usize * self.width as usize;
+            for cell in &mut self.cells[start..start + self.width as usize] {
+                *cell = Cell::default();
+            }
+        }
+    }
+}
+
+impl Renderer for CellGrid {
+    fn print(&mut self, x: u16, y: u16, text: &str, foreground: Option<Color>, background: Option<Color>) -> Result<()> {
+        if y >= self.height {
+            return Ok(());
+        }
+        for (offset, symbol) in text.chars().enumerate() {
+            let x = x as usize + offset;
+            if x >= self.width as usize {
+                break;
+            }
+            self.cells[y as usize * self.width as usize + x] = Cell { symbol, foreground, background };
+        }
+        Ok(())
+    }
+
+    fn clear_row(&mut self, y: u16) -> Result<()> {
+        self.clear_rows(y..y + 1);
+        Ok(())
+    }
+
+    fn clear_below(&mut self, y: u16) -> Result<()> {
+        self.clear_rows(y..self.height);
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.clear_rows(0..self.height);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Cell, CellGrid, Color, Renderer};
+
+    #[test]
+    fn draw_to_cell_grid() {
+        let mut grid = CellGrid::new(10, 3);
+        grid.print(2, 1, "piano-rs", Some(Color::Red), None).unwrap();
+        assert_eq!(grid.row(1), "  piano-rs");
+        assert_eq!(grid.cell(9, 1), Some(&Cell { symbol: 's', foreground: Some(Color::Red), background: None }));
+        // Text running off the grid is cut off
+        grid.print(8, 1, "xyz", None, None).unwrap();
+        grid.print(0, 3, "below", None, None).unwrap();
+        assert_eq!(grid.row(1), "  piano-xy");
+        assert_eq!(grid.cell(0, 3), None);
+
+        grid.print(0, 2, "keys", None, None).unwrap();
+        grid.clear_row(1).unwrap();
+        assert_eq!(grid.row(1), "");
+        assert_eq!(grid.row(2), "keys");
+        grid.clear_below(0).unwrap();
+        assert_eq!(grid, CellGrid::new(10, 3));
+    }
+}
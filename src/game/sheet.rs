@@ -0,0 +1,229 @@
+use std::time::Duration;
+use crossterm::KeyEvent;
+use crate::game::notes;
+use crate::game::notes_file::{self, FileNote, Song};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SheetOptions {
+    pub bpm: f32,
+    // The octave sequence the sheet is typed at
+    pub sequence: i8,
+    // Whether keys in brackets are played together, or quickly one after another
+    pub chords: bool,
+}
+
+impl Default for SheetOptions {
+    fn default() -> Self {
+        SheetOptions {
+            bpm: 120.0,
+            sequence: 2,
+            chords: true,
+        }
+    }
+}
+
+// Reads a letter sheet of keystrokes, such as "[tu] y t | 8 9 0". Keys written together
+// are half a beat apart, while spaces make it a full beat and each | adds a beat of rest.
+// Since brackets group keys, the [ and ] piano keys can't be used in sheets.
+pub fn parse_song(content: &str, options: SheetOptions) -> Result<Song, String> {
+    let beat = 60.0 / options.bpm as f64;
+    let mut timed_notes: Vec<(Duration, FileNote)> = Vec::new();
+    let mut time = 0.0;
+    let mut gap: Option<f64> = None;
+
+    for (number, line) in content.lines().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        let error_at = |column: usize, message: String| {
+            format!("line {}, column {}: {}", number + 1, column + 1, message)
+        };
+        // Line breaks separate keys just like spaces do
+        gap = gap.map(|gap| gap.max(beat));
+
+        let mut i = 0;
+        while i < chars.len() {
+            let keys: Vec<(usize, char)> = match chars[i] {
+                c if c.is_whitespace() => {
+                    gap = gap.map(|gap| gap.max(beat));
+                    i += 1;
+                    continue;
+                }
+                '|' => {
+                    gap = gap.map(|gap| gap + beat);
+                    i += 1;
+                    continue;
+                }
+                '[' => {
+                    let end = chars[i..].iter()
+                        .position(|&c| c == ']')
+                        .map(|offset| i + offset)
+                        .ok_or_else(|| error_at(i, String::from("Missing closing ']'")))?;
+                    let keys = (i + 1..end)
+                        .map(|column| (column, chars[column]))
+                        .filter(|(_, key)| !key.is_whitespace())
+                        .collect();
+                    i = end + 1;
+                    keys
+                }
+                ']' => return Err(error_at(i, String::from("']' without an opening '['"))),
+                key => {
+                    i += 1;
+                    vec![(i - 1, key)]
+                }
+            };
+
+            time += gap.unwrap_or(0.0);
+            for (index, &(column, key)) in keys.iter().enumerate() {
+                let sound = notes::key_to_base_note(KeyEvent::Char(key), options.sequence)
+                    .filter(|sound| notes::sound_to_semitone(sound)
+                        .and_then(notes::semitone_to_sound)
+                        .is_some())
+                    .ok_or_else(|| error_at(column, format!("'{}' doesn't play a note at sequence {}", key, options.sequence)))?;
+                if index > 0 && !options.chords {
+                    time += beat / 4.0;
+                }
+                timed_notes.push((to_millis(time), FileNote {
+                    delay: Duration::from_millis(0),
+                    base_note: sound,
+                    duration: Duration::from_millis(0),
+                    track: None,
+                }));
+            }
+            if !keys.is_empty() {
+                gap = Some(beat / 2.0);
+            }
+        }
+    }
+
+    let (start_times, mut notes): (Vec<Duration>, Vec<FileNote>) = timed_notes.into_iter().unzip();
+    notes_file::set_start_times(&mut notes, &start_times);
+    Ok(Song {
+        notes,
+        ..Song::default()
+    })
+}
+
+// Writes the notes as keys typed at the sequence from the options, with the gaps between them
+// rounded to half beats
+pub fn write_sheet(song: &Song, options: SheetOptions) -> Result<String, String> {
+    let mut groups: Vec<(Duration, Vec<char>)> = Vec::new();
+    for (time, note) in notes_file::start_times(&song.notes).into_iter().zip(&song.notes) {
+        // Prefer keys without shift, and letters or digits over symbols
+        let key = notes::base_note_to_keys(&note.base_note, options.sequence)
+            .into_iter()
+            .filter_map(|key| match key {
+                KeyEvent::Char(key) if key != '[' && key != ']' => Some(key),
+                _ => None,
+            })
+            .min_by_key(|key| (!key.is_ascii_alphanumeric(), key.is_ascii_uppercase()))
+            .ok_or_else(|| format!("{} can't be typed at sequence {}", note.base_note, options.sequence))?;
+        match groups.last_mut() {
+            Some((start, keys)) if *start == time => keys.push(key),
+            _ => groups.push((time, vec![key])),
+        }
+    }
+
+    let half_beat = 30.0 / options.bpm as f64;
+    let mut sheet = String::new();
+    let mut line_length = 0;
+    let mut previous_time = None;
+    for (time, keys) in groups {
+        if let Some(previous_time) = previous_time {
+            let half_beats = ((time - previous_time).as_secs_f64() / half_beat).round() as usize;
+            if half_beats >= 2 {
+                // Long lines are only broken at a pause, so that a run of keys stays together
+                if line_length >= 16 {
+                    sheet.push('\n');
+                    line_length = 0;
+                } else {
+                    sheet.push(' ');
+                }
+                sheet.push_str(&"| ".repeat((half_beats - 2) / 2));
+            }
+        }
+        previous_time = Some(time);
+
+        let keys: String = keys.into_iter().collect();
+        if keys.chars().count() > 1 {
+            sheet.push_str(&format!("[{}]", keys));
+        } else {
+            sheet.push_str(&keys);
+        }
+        line_length += 1;
+    }
+    sheet.push('\n');
+    Ok(sheet)
+}
+
+fn to_millis(seconds: f64) -> Duration {
+    Duration::from_millis((seconds * 1000.0).round() as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_song, write_sheet, SheetOptions};
+
+    fn timeline(content: &str, options: SheetOptions) -> Vec<(u128, String)> {
+        let song = parse_song(content, options).unwrap();
+        let mut time = 0;
+        song.notes
+            .iter()
+            .map(|note| {
+                time += note.delay.as_millis();
+                (time, note.base_note.clone())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_keys_and_pauses() {
+        let options = SheetOptions::default();
+        assert_eq!(timeline("qw e\n| r", options), vec![
+            (0, String::from("a2")),
+            (250, String::from("b2")),
+            (750, String::from("c3")),
+            (1750, String::from("d3")),
+        ]);
+    }
+
+    #[test]
+    fn parse_shifted_keys() {
+        let options = SheetOptions { sequence: 1, ..SheetOptions::default() };
+        let sounds: Vec<String> = timeline("Q @", options).into_iter().map(|(_, sound)| sound).collect();
+        assert_eq!(sounds, ["a2", "as2"]);
+    }
+
+    #[test]
+    fn parse_brackets() {
+        let options = SheetOptions { bpm: 60.0, ..SheetOptions::default() };
+        assert_eq!(timeline("[qe] t", options), vec![
+            (0, String::from("a2")),
+            (0, String::from("c3")),
+            (1000, String::from("e3")),
+        ]);
+
+        let options = SheetOptions { bpm: 60.0, chords: false, ..SheetOptions::default() };
+        assert_eq!(timeline("[q e]t", options), vec![
+            (0, String::from("a2")),
+            (250, String::from("c3")),
+            (750, String::from("e3")),
+        ]);
+    }
+
+    #[test]
+    fn write_sheets() {
+        let options = SheetOptions::default();
+        let content = "qw e\n| [tu] r";
+        let sheet = write_sheet(&parse_song(content, options).unwrap(), options).unwrap();
+        assert_eq!(sheet, "qw e | [tu] r\n");
+
+        let options = SheetOptions { sequence: 0, ..SheetOptions::default() };
+        assert!(write_sheet(&parse_song(content, SheetOptions::default()).unwrap(), options).is_err());
+    }
+
+    #[test]
+    fn invalid_sheets() {
+        let options = SheetOptions::default();
+        assert_eq!(parse_song("q -", options).unwrap_err(), "line 1, column 3: '-' doesn't play a note at sequence 2");
+        assert!(parse_song("[qe", options).unwrap_err().contains("Missing closing"));
+    }
+}
@@ -0,0 +1,203 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use crate::game::notes_file::{Control, FileControl};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeSignature {
+    pub beats: u32,
+    pub beat_unit: u32,
+}
+
+impl TimeSignature {
+    // Length of a bar when a quarter note gets the given beats per minute
+    pub fn bar_length(&self, bpm: f32) -> Duration {
+        let quarters = self.beats as f64 * 4.0 / self.beat_unit as f64;
+        Duration::from_secs_f64(quarters * 60.0 / bpm as f64)
+    }
+}
+
+impl FromStr for TimeSignature {
+    type Err = String;
+
+    // Parses time signatures written as "4/4" or "6/8"
+    fn from_str(time: &str) -> Result<TimeSignature, String> {
+        let mut parts = time.splitn(2, '/').map(|part| part.trim().parse::<u32>().ok());
+        match (parts.next().flatten(), parts.next().flatten()) {
+            (Some(beats), Some(beat_unit)) if beats > 0 && beat_unit.is_power_of_two() => Ok(TimeSignature {
+                beats,
+                beat_unit,
+            }),
+            _ => Err(format!("Invalid time signature \"{}\", expected something like 4/4 or 6/8", time)),
+        }
+    }
+}
+
+impl fmt::Display for TimeSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.beats, self.beat_unit)
+    }
+}
+
+// Maps positions in a song to the time they are played at. Song files are written at their
+// first tempo, and later tempos speed up or slow down playback relative to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoMap {
+    // Tempo changes placed in seconds of the song, where ramps reach their tempo gradually
+    // from the change before them
+    tempos: Vec<(f64, f64, bool)>,
+}
+
+impl TempoMap {
+    pub fn new(controls: &[FileControl]) -> TempoMap {
+        let mut tempos: Vec<(f64, f64, bool)> = controls.iter()
+            .filter_map(|control| match control.control {
+                Control::Tempo { bpm, ramp } if bpm > 0.0 => Some((control.time.as_secs_f64(), bpm as f64, ramp)),
+                _ => None,
+            })
+            .collect();
+        tempos.sort_by(|first, second| first.0.partial_cmp(&second.0).unwrap());
+        // The first tempo also applies before it
+        if let Some(first) = tempos.first_mut() {
+            first.0 = 0.0;
+        }
+        TempoMap { tempos }
+    }
+
+    // The tempo the song file is written at
+    pub fn bpm(&self) -> Option<f32> {
+        self.tempos.first().map(|&(_, bpm, _)| bpm as f32)
+    }
+
+    pub fn bpm_at(&self, position: Duration) -> Option<f32> {
+        let position = position.as_secs_f64();
+        self.segments()
+            .take_while(|&(start, _, _, _)| start <= position)
+            .last()
+            .map(|(start, end, start_bpm, end_bpm)| bpm_between(position - start, end - start, start_bpm, end_bpm) as f32)
+    }
+
+    // Time taken to play the song up to the given position, before any playback tempo
+    pub fn clock_time(&self, position: Duration) -> Duration {
+        let position = position.as_secs_f64();
+        if self.tempos.is_empty() {
+            return Duration::from_secs_f64(position);
+        }
+        let clock: f64 = self.segments()
+            .take_while(|&(start, _, _, _)| start < position)
+            .map(|(start, end, start_bpm, end_bpm)| {
+                let length = position.min(end) - start;
+                self.segment_clock(length, start_bpm, bpm_between(length, end - start, start_bpm, end_bpm))
+            })
+            .sum();
+        Duration::from_secs_f64(clock)
+    }
+
+    // Position in the song reached after playing it for the given time
+    pub fn position(&self, clock_time: Duration) -> Duration {
+        let mut clock = clock_time.as_secs_f64();
+        for (start, end, start_bpm, end_bpm) in self.segments() {
+            let segment_clock = self.segment_clock(end - start, start_bpm, end_bpm);
+            if clock > segment_clock {
+                clock -= segment_clock;
+                continue;
+            }
+            let base = self.tempos[0].1;
+            let length = if end_bpm == start_bpm {
+                clock * start_bpm / base
+            } else {
+                let slope = (end_bpm - start_bpm) / (end - start);
+                let bpm = start_bpm * (clock * slope / base).exp();
+                (bpm - start_bpm) / slope
+            };
+            return Duration::from_secs_f64(start + length);
+        }
+        Duration::from_secs_f64(clock)
+    }
+
+    // Spans of the song from each tempo change to the next, with the tempo at either end
+    fn segments(&self) -> impl Iterator<Item = (f64, f64, f64, f64)> + '_ {
+        self.tempos.iter().enumerate().map(move |(index, &(start, bpm, _))| {
+            match self.tempos.get(index + 1) {
+                Some(&(end, end_bpm, ramp)) => (start, end, bpm, if ramp { end_bpm } else { bpm }),
+                None => (start, f64::INFINITY, bpm, bpm),
+            }
+        })
+    }
+
+    // Time taken to play `length` seconds of the song while the tempo changes steadily from
+    // `start_bpm` to `end_bpm`
+    fn segment_clock(&self, length: f64, start_bpm: f64, end_bpm: f64) -> f64 {
+        let base = self.tempos[0].1;
+        if end_bpm == start_bpm {
+            return length * base / start_bpm;
+        }
+        // Integrating over a tempo that changes linearly gives a logarithm
+        base * length / (end_bpm - start_bpm) * (end_bpm / start_bpm).ln()
+    }
+}
+
+fn bpm_between(length: f64, segment_length: f64, start_bpm: f64, end_bpm: f64) -> f64 {
+    if end_bpm == start_bpm {
+        return start_bpm;
+    }
+    start_bpm + (end_bpm - start_bpm) * length / segment_length
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TempoMap, TimeSignature, Duration};
+    use crate::game::notes_file::{Control, FileControl};
+
+    fn tempo_map(tempos: &[(u64, f32, bool)]) -> TempoMap {
+        let controls: Vec<FileControl> = tempos.iter()
+            .map(|&(time, bpm, ramp)| FileControl {
+                time: Duration::from_millis(time),
+                control: Control::Tempo { bpm, ramp },
+            })
+            .collect();
+        TempoMap::new(&controls)
+    }
+
+    fn millis(duration: Duration) -> u128 {
+        (duration.as_secs_f64() * 1000.0).round() as u128
+    }
+
+    #[test]
+    fn parse_time_signature() {
+        assert_eq!("6/8".parse(), Ok(TimeSignature { beats: 6, beat_unit: 8 }));
+        assert!("4/3".parse::<TimeSignature>().is_err());
+        assert!("four".parse::<TimeSignature>().is_err());
+        assert_eq!(TimeSignature { beats: 6, beat_unit: 8 }.bar_length(60.0), Duration::from_secs(3));
+        assert_eq!(TimeSignature { beats: 3, beat_unit: 4 }.to_string(), "3/4");
+    }
+
+    #[test]
+    fn follow_tempo_changes() {
+        let empty = tempo_map(&[]);
+        assert_eq!(empty.clock_time(Duration::from_millis(1234)), Duration::from_millis(1234));
+        assert_eq!(empty.bpm(), None);
+
+        // Half as fast after two seconds
+        let tempos = tempo_map(&[(0, 120.0, false), (2000, 60.0, false)]);
+        assert_eq!(millis(tempos.clock_time(Duration::from_millis(1000))), 1000);
+        assert_eq!(millis(tempos.clock_time(Duration::from_millis(3000))), 4000);
+        assert_eq!(millis(tempos.position(Duration::from_millis(4000))), 3000);
+        assert_eq!(tempos.bpm_at(Duration::from_millis(2500)), Some(60.0));
+    }
+
+    #[test]
+    fn follow_tempo_ramps() {
+        // Slows down from 120 to 60 bpm over two seconds of the song
+        let tempos = tempo_map(&[(0, 120.0, false), (2000, 60.0, true)]);
+        assert_eq!(tempos.bpm_at(Duration::from_millis(1000)), Some(90.0));
+        let clock = tempos.clock_time(Duration::from_millis(2000));
+        // 4 * ln 2 seconds
+        assert_eq!(millis(clock), 2773);
+        assert_eq!(millis(tempos.clock_time(Duration::from_millis(3000))), millis(clock) + 2000);
+        for &position in &[500, 1500, 2000, 2500] {
+            let clock = tempos.clock_time(Duration::from_millis(position));
+            assert_eq!(millis(tempos.position(clock)), position as u128);
+        }
+    }
+}
@@ -1,14 +1,20 @@
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::thread;
 use std::time::Duration;
 use std::net::SocketAddr;
-use std::io::{stdout, Write, Result};
-use std::path::PathBuf;
+use std::io::{stdout, Write, Result, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::fs;
+use std::panic;
+use std::process;
 use crossterm::{
     cursor,
     input,
     execute,
-    RawScreen,
+    terminal,
+    AlternateScreen,
     Clear,
     ClearType,
     InputEvent,
@@ -16,12 +22,29 @@ use crossterm::{
 };
 use crossterm_style::Color;
 
-use piano_rs::arguments::Options;
+use piano_rs::arguments::{Options, Command};
 use piano_rs::game::{
+    self,
     PianoKeyboard,
     GameEvent,
     Note,
-    NoteReader,
+    NoteRecorder,
+    Playback,
+    PlaybackCommand,
+    PlaybackEnd,
+    Quantizer,
+    NoteSource,
+    Song,
+    cheatsheet::CheatSheet,
+    convert,
+    edit,
+    library::{self, Browser, BrowserAction},
+    lilypond,
+    playlist::{self, Playlist, Repeat},
+    screen::{self, FallingNote},
+    sheet::SheetOptions,
+    tempo::TimeSignature,
+    watch::FileWatcher,
 };
 use piano_rs::network::{
     NetworkEvent,
@@ -41,44 +64,160 @@ fn handle_network_receive_event(
                 .parse()
                 .unwrap();
 
-            event_sender.lock().unwrap()
-                .register_remote_socket(
-                    event_receiver.socket.local_addr().unwrap().port(), remote_receiver_addr
-                )
-                .unwrap();
+            let players = {
+                let mut sender = event_sender.lock().unwrap();
+                sender
+                    .register_remote_socket(
+                        event_receiver.socket.local_addr().unwrap().port(), remote_receiver_addr
+                    )
+                    .unwrap();
+                sender.peer_addrs.len()
+            };
+            // Taking the keyboard while holding the sender could deadlock with the game loop
+            keyboard.lock().unwrap().set_players(players);
         }
         NetworkEvent::Peers(port, mut peers) => {
             peers[0] = format!("{}:{}", data.src.ip(), port).parse().unwrap();
+            keyboard.lock().unwrap().set_players(peers.len());
             event_sender.lock().unwrap().peer_addrs = peers;
         }
         NetworkEvent::ID(id) => {
-            keyboard.lock().unwrap().set_note_color(match id {
-                0 => Color::Blue,
-                1 => Color::Red,
-                2 => Color::Green,
-                3 => Color::Yellow,
-                4 => Color::Cyan,
-                5 => Color::Magenta,
-                _ => Color::Black,
-            });
+            keyboard.lock().unwrap().set_note_color(game::player_color(id));
         }
         NetworkEvent::Note(note) => {
-            keyboard.lock().unwrap().play_note(note);
+            // Our own notes also reach us through the network, coming from our sender socket
+            let own_note = event_sender.lock().unwrap().is_own_address(data.src);
+            let source = if own_note {
+                NoteSource::Local
+            } else {
+                NoteSource::Peer(data.src)
+            };
+            keyboard.lock().unwrap().play_note(note, source);
         }
        _ => { },
     }
 }
 
-fn game_loop(stdin: &mut SyncReader, keyboard: &Arc<Mutex<PianoKeyboard>>, event_sender: &Arc<Mutex<Sender>>) {
+// Songs are previewed by playing this much of them
+const PREVIEW_LENGTH: Duration = Duration::from_secs(15);
+// Going back within this long after a song starts plays the one before it instead
+const RESTART_THRESHOLD: Duration = Duration::from_secs(3);
+// How often to check whether the song being played has changed with --watch
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+// How often to check whether the terminal has been resized or the game asked to stop
+const TERMINAL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Signal asking the game to stop, left for the terminal watcher since a signal handler can't
+// safely do more than set it
+static TERMINATION_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+// Settings from the command line that apply to every song played from a file
+#[derive(Clone)]
+struct PlaybackSettings {
+    tempo: f32,
+    seek_step: Duration,
+    bpm: f32,
+    beats_per_bar: u32,
+    mute_tracks: Vec<usize>,
+    solo_tracks: Vec<usize>,
+    sheet_options: SheetOptions,
+    gap: Duration,
+    // Only play the start of each song
+    preview: Option<Duration>,
+    watch: bool,
+}
+
+impl PlaybackSettings {
+    fn from(arguments: &Options) -> PlaybackSettings {
+        PlaybackSettings {
+            tempo: arguments.play_file_tempo,
+            seek_step: Duration::from_secs(arguments.seek_step),
+            bpm: arguments.bpm,
+            beats_per_bar: arguments.beats_per_bar,
+            mute_tracks: arguments.mute_tracks.clone(),
+            solo_tracks: arguments.solo_tracks.clone(),
+            sheet_options: sheet_options(arguments),
+            gap: Duration::from_millis(arguments.playlist_gap),
+            preview: None,
+            watch: arguments.watch,
+        }
+    }
+
+    // Bars are skipped by as many beats as set with --beats-per-bar, at the song's own tempo
+    fn bar_length(&self, bpm: f32) -> Duration {
+        TimeSignature { beats: self.beats_per_bar, beat_unit: 4 }.bar_length(bpm)
+    }
+}
+
+fn game_loop(
+    stdin: &mut SyncReader,
+    keyboard: &Arc<Mutex<PianoKeyboard>>,
+    event_sender: &Arc<Mutex<Sender>>,
+    mut playback_sender: Option<mpsc::Sender<PlaybackCommand>>,
+    arguments: &Options,
+) {
     /* let duration = Duration::from_nanos(1000); */
+    let mut browser: Option<Browser> = None;
 
     loop {
         if let Some(event) = stdin.next() {
             if let InputEvent::Keyboard(key) = event {
+                // The library takes over the keys while it's open
+                if let Some(ref mut library_browser) = browser {
+                    let (path, preview) = match library_browser.process_key(key) {
+                        Some(BrowserAction::Play(path)) => (path, None),
+                        Some(BrowserAction::Preview(path)) => (path, Some(PREVIEW_LENGTH)),
+                        Some(BrowserAction::Close) => {
+                            browser = None;
+                            keyboard.lock().unwrap().close_library();
+                            continue;
+                        }
+                        None => {
+                            show_library(keyboard, library_browser, &arguments.library, None);
+                            continue;
+                        }
+                    };
+                    match read_song_with(path.clone(), sheet_options(arguments)) {
+                        Ok(_) => {
+                            let settings = PlaybackSettings {
+                                preview,
+                                ..PlaybackSettings::from(arguments)
+                            };
+                            let playlist = Playlist::new(vec![path], Repeat::Off);
+                            // Dropping the previous sender stops the song that was playing
+                            playback_sender = Some(start_playback(playlist, settings, keyboard, event_sender));
+                            if preview.is_none() {
+                                browser = None;
+                                keyboard.lock().unwrap().close_library();
+                            }
+                        }
+                        Err(e) => show_library(keyboard, library_browser, &arguments.library, Some(&e.to_string())),
+                    }
+                    continue;
+                }
+
                 match keyboard.lock().unwrap().process_key(key) {
                     Some(GameEvent::Note(note)) => {
                         event_sender.lock().unwrap().tick(note).unwrap();
                     }
+                    Some(GameEvent::Playback(command)) => {
+                        if let Some(ref playback) = playback_sender {
+                            // Playback may have already finished
+                            playback.send(command).unwrap_or_default();
+                        }
+                    }
+                    Some(GameEvent::OpenLibrary) => {
+                        match library::scan(&arguments.library, sheet_options(arguments)) {
+                            Ok(songs) => {
+                                let library_browser = Browser::new(songs);
+                                show_library(keyboard, &library_browser, &arguments.library, None);
+                                browser = Some(library_browser);
+                            }
+                            Err(e) => {
+                                keyboard.lock().unwrap().show_library(e, Vec::new(), None);
+                            }
+                        }
+                    }
                     Some(GameEvent::Quit) => break,
                     None => { },
                 }
@@ -87,45 +226,433 @@ fn game_loop(stdin: &mut SyncReader, keyboard: &Arc<Mutex<PianoKeyboard>>, event
     }
 }
 
-fn play_from_file(play_file: PathBuf, tempo: f32, keyboard: &Arc<Mutex<PianoKeyboard>>, event_sender: &Arc<Mutex<Sender>>) {
-    let file_base_notes = NoteReader::from(play_file);
-    for file_base_note in file_base_notes.parse_notes() {
-        let note = Note::from(
-            file_base_note.base_note.as_str(),
-            keyboard.lock().unwrap().color,
-            file_base_note.duration,
-        ).unwrap();
-        let normalized_delay = Duration::from_millis(
-            (file_base_note.delay.as_millis() as f32 / tempo) as u64
-        );
-        thread::sleep(normalized_delay);
-        event_sender.lock().unwrap().tick(note).unwrap();
+fn show_library(keyboard: &Arc<Mutex<PianoKeyboard>>, browser: &Browser, directory: &Path, error: Option<&str>) {
+    let heading = match error {
+        Some(error) => error.to_string(),
+        None => format!("Songs in {} (Enter to play, p to preview, Esc to close)", directory.display()),
+    };
+    let (lines, selected) = browser.lines();
+    keyboard.lock().unwrap().show_library(heading, lines, selected);
+}
+
+fn sheet_options(arguments: &Options) -> SheetOptions {
+    SheetOptions {
+        bpm: arguments.bpm,
+        sequence: arguments.sequence,
+        chords: !arguments.arpeggio,
+    }
+}
+
+// Plays the songs in the background until the returned sender is dropped
+fn start_playback(
+    playlist: Playlist,
+    settings: PlaybackSettings,
+    keyboard: &Arc<Mutex<PianoKeyboard>>,
+    event_sender: &Arc<Mutex<Sender>>,
+) -> mpsc::Sender<PlaybackCommand> {
+    let (command_sender, command_receiver) = mpsc::channel();
+    let watched_file = Arc::new(Mutex::new(None));
+    // While watching, commands reach the playback through the watcher, which goes away along
+    // with its sender once the returned one is dropped
+    let command_sender = if settings.watch {
+        let (watch_sender, watch_receiver) = mpsc::channel();
+        let watched_file = watched_file.clone();
+        thread::spawn(move || watch_file(&watched_file, &watch_receiver, command_sender));
+        watch_sender
+    } else {
+        command_sender
+    };
+    let fileboard = keyboard.clone();
+    let file_notes_sender = event_sender.clone();
+    thread::spawn(move || play_playlist(
+        playlist,
+        settings,
+        command_receiver,
+        &watched_file,
+        &fileboard,
+        &file_notes_sender
+    ));
+    command_sender
+}
+
+// Passes commands on to the playback, and asks it to reload whenever the song it's playing
+// changes. Stops once either the playback or the sender of the commands goes away.
+fn watch_file(
+    watched_file: &Arc<Mutex<Option<PathBuf>>>,
+    commands: &mpsc::Receiver<PlaybackCommand>,
+    playback: mpsc::Sender<PlaybackCommand>,
+) {
+    let mut watcher: Option<FileWatcher> = None;
+    // Only the playback thread and this one hold on to the file being played
+    while Arc::strong_count(watched_file) > 1 {
+        match commands.recv_timeout(WATCH_INTERVAL) {
+            Ok(command) => {
+                if playback.send(command).is_err() {
+                    break;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => { },
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        let path = match watched_file.lock().unwrap().clone() {
+            Some(path) => path,
+            None => continue,
+        };
+        match watcher {
+            Some(ref mut watcher) if watcher.path() == path => {
+                if watcher.changed() && playback.send(PlaybackCommand::Reload).is_err() {
+                    break;
+                }
+            }
+            _ => watcher = Some(FileWatcher::new(&path)),
+        }
+    }
+}
+
+// Redraws the screen when the terminal is resized, and restores the terminal before quitting on
+// SIGINT or SIGTERM
+fn watch_terminal(keyboard: &Arc<Mutex<PianoKeyboard>>) {
+    let terminal = terminal();
+    let mut size = terminal.size().ok();
+    loop {
+        thread::sleep(TERMINAL_INTERVAL);
+        let signal = TERMINATION_SIGNAL.load(Ordering::SeqCst);
+        if signal != 0 {
+            screen::restore_terminal();
+            process::exit(128 + signal);
+        }
+        let new_size = terminal.size().ok();
+        if new_size != size {
+            size = new_size;
+            keyboard.lock().unwrap().redraw().unwrap_or_default();
+        }
+    }
+}
+
+extern "C" fn request_termination(signal: libc::c_int) {
+    TERMINATION_SIGNAL.store(signal, Ordering::SeqCst);
+}
+
+fn handle_termination_signals() {
+    let handler = request_termination as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+// Waits for the song to change after it failed to load, returning false if playback is stopped
+fn wait_for_reload(commands: &mpsc::Receiver<PlaybackCommand>) -> bool {
+    loop {
+        match commands.recv() {
+            Ok(PlaybackCommand::Reload) => return true,
+            Ok(_) => {}
+            Err(_) => return false,
+        }
+    }
+}
+
+fn play_playlist(
+    mut playlist: Playlist,
+    settings: PlaybackSettings,
+    commands: mpsc::Receiver<PlaybackCommand>,
+    watched_file: &Arc<Mutex<Option<PathBuf>>>,
+    keyboard: &Arc<Mutex<PianoKeyboard>>,
+    event_sender: &Arc<Mutex<Sender>>,
+) {
+    let mut first_song = true;
+    // Stop once every song has failed to load in a row
+    let mut failures = 0;
+    // Where to pick up again after the song has been reloaded
+    let mut resume_at: Option<Duration> = None;
+    while let Some(path) = playlist.current().map(Path::to_path_buf) {
+        *watched_file.lock().unwrap() = Some(path.clone());
+        if playlist.len() > 1 {
+            keyboard.lock().unwrap().show_playlist_status(playlist.status());
+        }
+        if !first_song && resume_at.is_none() {
+            thread::sleep(settings.gap);
+            // Keys pressed in between songs shouldn't skip the next one
+            while commands.try_recv().is_ok() {}
+        }
+        first_song = false;
+
+        let song = match read_song_with(path, settings.sheet_options) {
+            Ok(song) => song,
+            // Keep going with the next version of the file instead
+            Err(e) if settings.watch => {
+                keyboard.lock().unwrap().show_playback_status(format!("{} (waiting for changes)", e));
+                if !wait_for_reload(&commands) {
+                    break;
+                }
+                resume_at = resume_at.or(Some(Duration::from_millis(0)));
+                continue;
+            }
+            Err(e) => {
+                keyboard.lock().unwrap().show_playback_status(e.to_string());
+                failures += 1;
+                if failures >= playlist.len() || !playlist.skip_next() {
+                    break;
+                }
+                continue;
+            }
+        };
+        failures = 0;
+        let song = match settings.preview {
+            Some(length) => edit::extract(&song, Duration::from_millis(0), length),
+            None => song,
+        };
+
+        let mut playback = Playback::new(&song, settings.tempo);
+        playback.set_seek_step(settings.seek_step);
+        playback.set_bpm(settings.bpm);
+        playback.set_bar_length(settings.bar_length(playback.bpm()));
+        playback.set_muted_tracks(settings.mute_tracks.clone());
+        playback.set_solo_tracks(settings.solo_tracks.clone());
+        if let Some(position) = resume_at.take() {
+            playback.seek(position);
+        }
+
+        let end = play_from_file(&song, &mut playback, &commands, keyboard, event_sender);
+        keyboard.lock().unwrap().show_falling_notes(Vec::new());
+        let play_next = match end {
+            PlaybackEnd::Finished => playlist.advance(),
+            PlaybackEnd::NextSong => playlist.skip_next(),
+            PlaybackEnd::PreviousSong => {
+                if playback.position() < RESTART_THRESHOLD {
+                    playlist.skip_previous();
+                }
+                true
+            }
+            // Songs with sections start again from the section being played
+            PlaybackEnd::Reload => {
+                resume_at = Some(playback.section_start().unwrap_or_else(|| playback.position()));
+                true
+            }
+            PlaybackEnd::Stopped => false,
+        };
+        if !play_next {
+            // While watching, the last song plays again once it changes
+            if settings.watch && end != PlaybackEnd::Stopped && wait_for_reload(&commands) {
+                resume_at = Some(Duration::from_millis(0));
+                continue;
+            }
+            break;
+        }
+    }
+}
+
+fn play_from_file(
+    song: &Song,
+    playback: &mut Playback,
+    commands: &mpsc::Receiver<PlaybackCommand>,
+    keyboard: &Arc<Mutex<PianoKeyboard>>,
+    event_sender: &Arc<Mutex<Sender>>,
+) -> PlaybackEnd {
+    playback.run(commands, |file_base_notes| {
+        let color = keyboard.lock().unwrap().color;
+        // Notes from a track are shown in the color they were originally played with
+        let notes: Vec<Note> = file_base_notes
+            .iter()
+            .map(|file_base_note| Note::from(
+                file_base_note.base_note.as_str(),
+                song.track(file_base_note).map_or(color, |track| track.color),
+                file_base_note.duration,
+            ).unwrap())
+            .collect();
+
+        let event_sender = event_sender.lock().unwrap();
+        for note in notes {
+            event_sender.tick(note).unwrap();
+        }
+    }, |control| keyboard.lock().unwrap().apply_control(control), |playback| {
+        let keyboard = keyboard.lock().unwrap();
+        keyboard.show_playback_status(playback.status());
+        if let Some((heading, sung, upcoming)) = playback.song_text() {
+            keyboard.show_song_text(heading, sung, upcoming);
+        }
+        let rows = keyboard.falling_rows();
+        if rows == 0 {
+            return;
+        }
+        let falling_notes = playback.upcoming_notes(screen::FALLING_NOTE_ROW * rows as u32)
+            .into_iter()
+            .filter_map(|(starts_in, ends_in, file_base_note)| {
+                let color = song.track(file_base_note).map_or(keyboard.color, |track| track.color);
+                Note::from(file_base_note.base_note.as_str(), color, file_base_note.duration)
+                    .map(|note| FallingNote { position: note.position, white: note.white, color, starts_in, ends_in })
+            })
+            .collect();
+        keyboard.show_falling_notes(falling_notes);
+    })
+}
+
+fn read_song(filename: PathBuf) -> Result<Song> {
+    read_song_with(filename, SheetOptions::default())
+}
+
+// Letter sheets are read with the given options, while other formats don't need any
+fn read_song_with(filename: PathBuf, sheet_options: SheetOptions) -> Result<Song> {
+    convert::read_song(&filename, None, sheet_options)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", filename.display(), e)))
+}
+
+fn write_song(filename: PathBuf, song: &Song) -> Result<()> {
+    NoteRecorder::create(filename)?.write_song(song);
+    Ok(())
+}
+
+fn run_command(command: Command) -> Result<()> {
+    match command {
+        Command::Quantize { input, output, grid, bpm, strength } => {
+            let quantizer = Quantizer::new(grid, bpm, strength);
+            let song = read_song(input)?;
+            write_song(output, &Song { notes: quantizer.quantize_notes(&song.notes), ..song })
+        }
+        Command::Trim { input, output } => {
+            write_song(output, &edit::trim(&read_song(input)?))
+        }
+        Command::Cut { input, output, start, end, keep } => {
+            let (start, end) = (Duration::from_millis(start), Duration::from_millis(end));
+            let song = read_song(input)?;
+            let song = if keep {
+                edit::extract(&song, start, end)
+            } else {
+                edit::cut(&song, start, end)
+            };
+            write_song(output, &song)
+        }
+        Command::Concat { first, second, output, gap } => {
+            let gap = Duration::from_millis(gap);
+            write_song(output, &edit::concat(&read_song(first)?, &read_song(second)?, gap))
+        }
+        Command::Overlay { first, second, output, offset } => {
+            let offset = Duration::from_millis(offset);
+            write_song(output, &edit::overlay(&read_song(first)?, &read_song(second)?, offset))
+        }
+        Command::Transpose { input, output, semitones } => {
+            let song = read_song(input)?;
+            let notes = edit::transpose(&song.notes, semitones)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            write_song(output, &Song { notes, ..song })
+        }
+        Command::Scale { input, output, factor } => {
+            let song = edit::scale_tempo(&read_song(input)?, factor)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            write_song(output, &song)
+        }
+        Command::Retrograde { input, output } => {
+            write_song(output, &edit::retrograde(&read_song(input)?))
+        }
+        Command::Invert { input, output, pivot } => {
+            let song = read_song(input)?;
+            let pivot = match pivot.or_else(|| song.notes.first().map(|note| note.base_note.clone())) {
+                Some(pivot) => pivot,
+                None => return write_song(output, &song),
+            };
+            let notes = edit::invert(&song.notes, &pivot)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            write_song(output, &Song { notes, ..song })
+        }
+        Command::Augment { input, output, factor } => {
+            let song = edit::augment(&read_song(input)?, factor)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            write_song(output, &song)
+        }
+        Command::MapScale { input, output, from, to } => {
+            let song = read_song(input)?;
+            let notes = edit::map_to_scale(&song.notes, &from, &to)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            write_song(output, &Song { notes, ..song })
+        }
+        Command::Sheet { input, output, options } => {
+            write_song(output, &read_song_with(input, options)?)
+        }
+        Command::CheatSheet { input, output } => {
+            let song = read_song(input.clone())?;
+            let cheat_sheet = CheatSheet::from_song(&song)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", input.display(), e)))?;
+            let content = match output.extension().and_then(|extension| extension.to_str()) {
+                Some("html") | Some("htm") => {
+                    let title = input.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+                    cheat_sheet.to_html(&title)
+                }
+                _ => cheat_sheet.to_text(),
+            };
+            fs::write(output, content)
+        }
+        Command::LilyPond { input, output, options } => {
+            let song = read_song(input.clone())?;
+            let title = input.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+            let score = lilypond::write_score(&song, &title, options)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            fs::write(output, score)
+        }
+        Command::Convert { input, output, from, to, options } => {
+            let song = convert::read_song(&input, from, options.sheet)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", input.display(), e)))?;
+            convert::write_song(&output, to, &song, options)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", output.display(), e)))
+        }
     }
 }
 
 fn main() -> Result<()> {
     let arguments = Options::read();
 
+    if let Some(command) = arguments.command {
+        return run_command(command);
+    }
+
+    // Read the first song before taking over the terminal, so that errors in it can be seen
+    let playlist = if arguments.play_files.is_empty() {
+        None
+    } else {
+        let songs = playlist::expand(&arguments.play_files)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let mut playlist = Playlist::new(songs, arguments.repeat);
+        if arguments.shuffle {
+            playlist.shuffle();
+        }
+        // While watching, errors are shown on screen until the file is fixed
+        if let (Some(path), false) = (playlist.current(), arguments.watch) {
+            read_song_with(path.to_path_buf(), sheet_options(&arguments))?;
+        }
+        Some(playlist)
+    };
+
     let receiver_address = arguments.receiver_address;
     let event_receiver = Receiver::new(receiver_address)?;
     let event_sender = Arc::new(Mutex::new(Sender::new(arguments.sender_address, arguments.host_address)?));
     let event_sender_clone = event_sender.clone();
 
+    // Play on a screen of our own, so that the terminal is left as it was on quitting
+    let _screen = AlternateScreen::to_alternate(true);
     execute!(stdout(), Clear(ClearType::All)).unwrap();
 
-    let _raw = RawScreen::into_raw_mode();
+    let default_panic_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        screen::restore_terminal();
+        default_panic_hook(info);
+    }));
+    handle_termination_signals();
 
     let keyboard = Arc::new(Mutex::new(PianoKeyboard::new(
         arguments.sequence,
         arguments.volume,
-        arguments.assets,
+        arguments.assets.clone(),
         Duration::from_millis(arguments.note_duration),
         Duration::from_millis(arguments.mark_duration),
         Color::Blue,
     )));
 
+    keyboard.lock().unwrap().set_key_labels(arguments.key_labels);
+    keyboard.lock().unwrap().set_falling_rows(arguments.falling_notes);
     keyboard.lock().unwrap().draw().unwrap();
 
+    let resizeboard = keyboard.clone();
+    thread::spawn(move || watch_terminal(&resizeboard));
+
     let cloneboard = keyboard.clone();
 
     thread::spawn(move || {
@@ -140,30 +667,27 @@ fn main() -> Result<()> {
 
     event_sender.lock().unwrap().register_self(arguments.receiver_address.port())?;
 
-    if let Some(v) = arguments.record_file {
-        keyboard.lock().unwrap().set_record_file(PathBuf::from(v));
+    if let Some(grid) = arguments.record_quantize {
+        let quantizer = Quantizer::new(grid, arguments.bpm, arguments.quantize_strength);
+        keyboard.lock().unwrap().set_record_quantizer(quantizer);
     }
 
-    if let Some(v) = arguments.play_file {
-        let play_file = PathBuf::from(v);
-        let tempo = arguments.play_file_tempo;
-        let fileboard = keyboard.clone();
-        let file_notes_sender = event_sender.clone();
-        thread::spawn(move || play_from_file(
-            play_file,
-            tempo,
-            &fileboard,
-            &file_notes_sender
-        ));
+    if let Some(ref v) = arguments.record_file {
+        keyboard.lock().unwrap().set_record_file(PathBuf::from(v));
     }
 
+    let playback_sender = playlist.map(|playlist| {
+        start_playback(playlist, PlaybackSettings::from(&arguments), &keyboard, &event_sender)
+    });
+
     let input = input();
     let mut sync_stdin = input.read_sync();
 
     let cursor = cursor();
     cursor.hide().unwrap_or_default();
 
-    game_loop(&mut sync_stdin, &keyboard, &event_sender);
+    game_loop(&mut sync_stdin, &keyboard, &event_sender, playback_sender, &arguments);
+    screen::restore_terminal();
 
     Ok(())
 }
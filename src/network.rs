@@ -115,4 +115,20 @@ mod test {
         assert_eq!(actual_client_id, expected_client_id);
         assert_eq!(actual_peers_received_by_client, expected_peers_received_by_client);
     }
+
+    #[test]
+    fn is_own_address() {
+        let sender_socket: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let mut event_sender = Sender::new(sender_socket, sender_socket).unwrap();
+        let sender_address = event_sender.socket.local_addr().unwrap();
+        assert!(event_sender.is_own_address(sender_address));
+        assert!(!event_sender.is_own_address(SocketAddr::new("192.0.2.1".parse().unwrap(), sender_address.port())));
+
+        let any_socket: SocketAddr = "0.0.0.0:0".parse().unwrap();
+        let mut event_sender = Sender::new(any_socket, any_socket).unwrap();
+        let port = event_sender.socket.local_addr().unwrap().port();
+        assert!(event_sender.is_own_address(SocketAddr::new("127.0.0.1".parse().unwrap(), port)));
+        assert!(!event_sender.is_own_address(SocketAddr::new("192.0.2.1".parse().unwrap(), port)));
+        assert!(!event_sender.is_own_address(SocketAddr::new("127.0.0.1".parse().unwrap(), port.wrapping_add(1))));
+    }
 }
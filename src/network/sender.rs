@@ -1,4 +1,5 @@
-use std::net::{SocketAddr, UdpSocket};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
 use std::io::Result;
 use crate::network::types;
 
@@ -7,6 +8,8 @@ pub struct Sender {
     pub socket: UdpSocket,
     pub host_addr: SocketAddr,
     pub peer_addrs: Vec<SocketAddr>,
+    // Whether each address notes came from belongs to this machine, worked out once per address
+    local_ips: HashMap<IpAddr, bool>,
 }
 
 impl Sender {
@@ -16,6 +19,7 @@ impl Sender {
             socket,
             host_addr,
             peer_addrs: Vec::new(),
+            local_ips: HashMap::new(),
         })
     }
 
@@ -45,6 +49,24 @@ impl Sender {
         Ok(())
     }
 
+    // Whether a packet coming from `addr` was sent through this sender. A socket bound to every
+    // interface sends from whichever local address reaches the peer, so any of them count.
+    pub fn is_own_address(&mut self, addr: SocketAddr) -> bool {
+        match self.socket.local_addr() {
+            Ok(own_addr) if own_addr.port() == addr.port() => {
+                if own_addr.ip().is_unspecified() {
+                    // Only addresses belonging to this machine can be bound to
+                    *self.local_ips
+                        .entry(addr.ip())
+                        .or_insert_with(|| UdpSocket::bind((addr.ip(), 0)).is_ok())
+                } else {
+                    own_addr.ip() == addr.ip()
+                }
+            }
+            _ => false,
+        }
+    }
+
     pub fn tick(&self, note: types::Note) -> Result<()> {
         let bytes = bincode::serialize(&types::NetworkEvent::Note(note)).unwrap();
         for peer_addr in self.peer_addrs.iter() {